@@ -1,14 +1,45 @@
 use clap::{ArgAction, Parser};
 use collector::*;
 use constcat::concat;
-use itm_processor::{ChanSpec, HandleAs, ITMProcessor};
+use itm::ITMFrame;
+use itm_processor::{ITMProcessor, CHANNEL_DELIMITER};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn, LevelFilter};
 use simplelog::*;
 use std::collections::HashSet;
-//use std::io::{self, Write};
+use std::io::Write;
 
-const CHANNEL_DELIMETER: char = ',';
+/// Protocols that can be chosen with `--protocol`
+#[cfg(not(feature = "serial"))]
+const PROTOCOLS: [&str; 5] = [
+    collector::OFLOW_PREFIX,
+    collector::LPOFLOW_PREFIX,
+    collector::ITM_PREFIX,
+    collector::AUTO_PREFIX,
+    collector::LISTEN_PREFIX,
+];
+/// Protocols that can be chosen with `--protocol`, where a serial port (and optional @baud)
+/// is given as the server
+#[cfg(feature = "serial")]
+const PROTOCOLS: [&str; 6] = [
+    collector::OFLOW_PREFIX,
+    collector::LPOFLOW_PREFIX,
+    collector::ITM_PREFIX,
+    collector::AUTO_PREFIX,
+    collector::LISTEN_PREFIX,
+    collector::SERIAL_PREFIX,
+];
+
+/// Layers of the stack whose output can be dumped with `--inspect`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    /// Frames as they come out of the COBS decoder, before OFLOW checks them
+    Cobs,
+    /// Payload of each valid OFLOW frame, for every stream
+    Oflow,
+    /// ITM packets decoded from our stream
+    Itm,
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -19,34 +50,48 @@ struct Args {
     #[arg(
         short = 'C',
         long,
-        default_value_t = 1,
-        hide_default_value(true),
-        help = "(Scaled) speed of the CPU in KHz,\n\
-        generally /1, /4, /16 or /64 of the real CPU speed"
+        value_parser = itm_processor::parse_cpu_freq,
+        help = "Frequency of the (scaled) timestamp clock, in Hz or with a Hz,\n\
+        KHz or MHz suffix (e.g. 168MHz), so target time is shown in\n\
+        µs. If the timestamps are prescaled this is the CPU clock\n\
+        divided by the prescaler. Raw ticks are shown if not set"
     )]
-    cpufreq: usize,
+    cpufreq: Option<usize>,
     #[arg(short = 'E', long)]
     /// Terminate when the feeding socket ends
     eof: bool,
     #[arg(short = 'f', long)]
     /// Take input from specified file
     input_file: Option<String>,
-    #[arg(short = 'g', long, default_value_t = itm_processor::DEFAULT_TRIGGER_CHAR)]
-    ///Character to use to trigger timestamp
-    trigger: char,
+    #[arg(long, requires = "input_file")]
+    /// Keep reading the input file as it grows, like tail -f
+    follow: bool,
+    #[arg(short = 'g', long, default_value_t = itm_processor::DEFAULT_TRIGGER_CHAR.to_string())]
+    /// Character, or sequence with \r, \n, \t, \0 or \xHH escapes, to trigger timestamp
+    trigger: String,
     #[arg(value_parser = clap::value_parser!(i32).range(0..=511))]
-    #[arg(short = 'i', long,num_args = 0.., value_delimiter = CHANNEL_DELIMETER,
+    #[arg(short = 'i', long,num_args = 0.., value_delimiter = CHANNEL_DELIMITER,
         help="Include interrupt information in output. Followed by values\n\
         constrains only those interrupts to be reported (range 0..511)")]
     interrupts: Option<Vec<i32>>,
+    #[arg(long, value_enum)]
+    /// Dump the output of this layer in hex instead of decoding to the channels
+    inspect: Option<Layer>,
+    #[cfg(feature = "serial")]
+    #[arg(short = 'l', long)]
+    /// List available sources and exit
+    list: bool,
     #[arg(short = 'n', long, default_value_t = true, action = ArgAction::SetFalse)]
     /// Enforce sync requirement for ITM
     itm_sync: bool,
     #[arg(
-        short, long, value_parser = [collector::OFLOW_PREFIX,collector::ITM_PREFIX],
+        short, long, value_parser = PROTOCOLS,
         help="Protocol to communicate. Defaults to itm if is-s\n set, otherwise oflow")]
     /// Protocol to communicate.
     protocol: Option<String>,
+    #[arg(long, value_enum)]
+    /// Show channels without a format as raw bytes in this radix
+    radix: Option<itm_processor::Radix>,
     #[arg(short, long)]
     /// Server and port to use
     server: Option<String>,
@@ -68,11 +113,16 @@ struct Args {
     /// Verbose mode 0(errors)..4(debug)..5(trace)
     verbose: u8,
     #[arg(value_parser = clap::value_parser!(i32).range(0..=15))]
-    #[arg(short = 'x', long,num_args = 0.., value_delimiter = CHANNEL_DELIMETER,
+    #[arg(short = 'x', long,num_args = 0.., value_delimiter = CHANNEL_DELIMITER,
         help="Include exception information in output. Followed by values\n\
         constrains only those exceptions to be reported (range 0..15)")]
     exceptions: Option<Vec<i32>>,
-    #[arg(num_args(1..), required=true, action = clap::ArgAction::Append)]
+    #[arg(num_args(1..), action = clap::ArgAction::Append)]
+    #[cfg_attr(not(feature = "serial"), arg(required_unless_present = "inspect"))]
+    #[cfg_attr(
+        feature = "serial",
+        arg(required_unless_present_any = ["list", "inspect"])
+    )]
     /// Channel(s) and formats in form [channel,"format"]
     channel: Vec<String>,
 }
@@ -84,6 +134,18 @@ fn main() {
         print_chelp();
         return;
     }
+    #[cfg(feature = "serial")]
+    if args.list {
+        for s in Collect::list_sources() {
+            println!(
+                "{:8} {:24} {}",
+                format!("{:?}", s.kind),
+                s.path,
+                s.description
+            );
+        }
+        return;
+    }
 
     /* === Setup logging */
     const LOG_LEVEL: [LevelFilter; 6] = [
@@ -130,7 +192,15 @@ fn main() {
         .collect();
 
     /* === Map the channels given on the command line into the output formats */
-    let channels = match map_channels(&args.channel) {
+    let channels = match itm_processor::parse_channel_spec(&args.channel) {
+        Ok(x) => x,
+        Err(y) => {
+            error!("{}", y);
+            std::process::exit(1);
+        }
+    };
+
+    let trigger = match itm_processor::parse_trigger(&args.trigger) {
         Ok(x) => x,
         Err(y) => {
             error!("{}", y);
@@ -140,13 +210,17 @@ fn main() {
 
     /* === Create the main process */
     let mut process = ITMProcessor::new(
-        args.trigger,
-        args.timestamp.unwrap_or(itm_processor::IntervalType::None),
-        args.cpufreq,
+        itm_processor::DEFAULT_TRIGGER_CHAR,
+        args.timestamp
+            .clone()
+            .unwrap_or(itm_processor::IntervalType::None),
+        args.cpufreq.unwrap_or(1),
         combined,
         channels,
         std::io::stdout(),
     );
+    process.set_trigger(trigger);
+    process.set_raw_radix(args.radix);
     debug!("Processor created");
 
     /* === Connect to the remote service */
@@ -165,71 +239,119 @@ fn main() {
             }
         };
 
+        collector.set_follow(args.follow);
+        /* Host side times are shown for when each frame arrived, not when it's written */
+        collector.set_arrival_time(matches!(
+            args.timestamp.as_ref(),
+            Some(
+                itm_processor::IntervalType::Absolute
+                    | itm_processor::IntervalType::Relative
+                    | itm_processor::IntervalType::Delta
+            )
+        ));
         debug!("Grabbing data");
-        let _z = collector.collect_data(&mut process);
+        let _z = match args.inspect {
+            Some(layer) => {
+                if layer != Layer::Itm {
+                    collector.set_frame_tap(Some(frame_dumper(layer)));
+                }
+                collector.collect_data(&mut Inspector { layer })
+            }
+            None => collector.collect_data(&mut Flushed(&mut process)),
+        };
         info!("Exited collect with error {:?}", _z);
 
+        if let Some(e) = process.output_failed() {
+            info!("Terminating due to output failure {:?}", e);
+            break;
+        }
+
         if args.eof {
             info!("Terminating due to args.eof set");
+            if args.inspect.is_none() {
+                let _ = process.flush();
+                eprint!("{}", process.summary());
+            }
             break;
         }
     }
 }
 
-// Perform channel mapping by extracting formats from arg string input
-pub fn map_channels(cli_channels: &Vec<String>) -> Result<ChanSpec, String> {
-    let mut channel: ChanSpec = Default::default();
-
-    for ip in cli_channels {
-        let parts: Vec<&str> = ip.split(CHANNEL_DELIMETER).collect();
-        /* Always expect a channel and format */
-        if 2 != parts.len() {
-            return Err(format!(
-                "Badly formed channel expression [{ip:}], should be [channel,\"format\"]"
-            ));
-        }
-        /* Grab the channel number */
-        let ch: u8 = match parts[0].parse() {
-            Ok(x) => x,
-            Err(_) => return Err(format!("Cannot identify channel in [{}]", parts[0])),
-        };
-        if (ch as usize) >= itm_processor::MAX_CHANNELS {
-            return Err(format!(
-                "Channel {} out of range 0..{}",
-                ch,
-                itm_processor::MAX_CHANNELS as u32 - 1
-            ));
+// Handler that flushes the processor output after every read of the source, so it's seen
+// even if nothing more arrives. The processor leaves flushing to us, and the next read can
+// block indefinitely.
+struct Flushed<'a>(&'a mut ITMProcessor);
+
+impl FrameHandler for Flushed<'_> {
+    fn process(&mut self, i: ITMFrame) -> bool {
+        self.0.process(i)
+    }
+
+    fn process_at(&mut self, i: ITMFrame, at: std::time::SystemTime) -> bool {
+        self.0.process_at(i, at)
+    }
+
+    fn state_ind(&self, e: &CollectError) {
+        self.0.state_ind(e)
+    }
+
+    fn read_done(&mut self) {
+        let _ = self.0.flush();
+        let _ = std::io::stdout().flush();
+    }
+}
+
+// Handler for --inspect, which stands in for the processor so nothing is decoded to channels
+struct Inspector {
+    layer: Layer,
+}
+
+impl FrameHandler for Inspector {
+    fn process(&mut self, i: ITMFrame) -> bool {
+        if self.layer == Layer::Itm {
+            println!("{:?}", i);
         }
-        /* Identify the active translations that are needed */
-        let mut active: u64 = 0;
-        for x in 0..itm_processor::PATTERNS.len() {
-            active |= if parts[1].contains(itm_processor::PATTERNS[x]) {
-                1 << x
-            } else {
-                0
-            };
+        true
+    }
+
+    fn state_ind(&self, e: &CollectError) {
+        if !matches!(e, CollectError::NoError) {
+            println!("!! {}", e);
         }
-        /* Now load the format into the channel */
-        channel[ch as usize] = itm_processor::Chan {
-            fmt: Some(parts[1].to_string()),
-            active,
-            handling: HandleAs::Normal,
-        };
     }
-    Ok(channel)
+}
+
+// Build a frame tap that dumps each frame arriving at the OFLOW layer, raw or decoded
+fn frame_dumper(layer: Layer) -> FrameTap {
+    let mut decoder = oflow::OFlow::new();
+    Box::new(move |f: &[u8]| match layer {
+        Layer::Oflow => match decoder.decode_slice(f) {
+            Ok((stream, payload)) => println!("{:3} {:4}: {}", stream, payload.len(), hex(payload)),
+            Err(e) => println!("!! {}", e),
+        },
+        _ => println!("{:4}: {}", f.len(), hex(f)),
+    })
+}
+
+// Bytes as space separated hex
+fn hex(b: &[u8]) -> String {
+    b.iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 // Output additional help for print substitutions
 fn print_chelp() {
     eprintln!("Substitions allowed in '-c' format string;\n");
-    for i in (0..itm_processor::PATTERNS.len()).step_by(2) {
-        eprintln!(
-            "\t{:6}\t{:20}\t\t{:6}\t{}",
-            itm_processor::PATTERNS[i],
-            itm_processor::DESCRIPTION[i],
-            itm_processor::PATTERNS[i + 1],
-            itm_processor::DESCRIPTION[i + 1]
-        );
+    for (p, d) in itm_processor::PATTERNS
+        .chunks(2)
+        .zip(itm_processor::DESCRIPTION.chunks(2))
+    {
+        match (p, d) {
+            ([p0, p1], [d0, d1]) => eprintln!("\t{:6}\t{:20}\t\t{:6}\t{}", p0, d0, p1, d1),
+            _ => eprintln!("\t{:6}\t{}", p[0], d[0]),
+        }
     }
     eprintln!("\nFor example; -c1,\"{{char}}\"            : Print all characters on channel 1");
     eprintln!("             -c2,\"Reading=0x{{x04}}\\n\" : Print \"Reading=0x1234abcd\"");
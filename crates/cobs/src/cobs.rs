@@ -6,7 +6,8 @@
 //!
 //! Each packet is returned in either the form of a filled `Vec<u8>` which is passed in by
 //! the caller pre-set with the maximum capacity, or as a new `Vec<u8>`. Short or over-long
-//! packets are automatically discarded and the stream re-syncronised. Statistics are
+//! packets are automatically discarded (unless over-long ones are to be truncated, see
+//! [`OverlongPolicy`]) and the stream re-syncronised. Statistics are
 //! maintained on the construction and forwarding of packets over the link.
 //!
 //! This decoder is based on **Consistent Overhead Byte Stuffing**, Stuart Cheshire
@@ -15,6 +16,9 @@
 //!
 
 use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::mem;
+use std::ops::Range;
 use std::vec::Vec;
 
 #[path = "test_lib.rs"]
@@ -35,8 +39,8 @@ enum DecoderState {
 /// Result of requesting the next packet from the stream
 #[derive(Debug, PartialEq)]
 enum TokenResult {
-    /// Something wrong in the reception
-    Error,
+    /// A sentinel arrived before the run it was in was complete
+    PrematureSentinel,
     /// We are flushing the stream
     Flushing,
     /// Store this
@@ -51,20 +55,79 @@ enum TokenResult {
 pub struct COBStats {
     /// Statistics maintained by this decoder
     pub inbytes: u64, // Number of bytes of input from source
-    pub goodbytes: u64, // Number of good bytes returned to layer above
-    pub badbytes: u64,  // Number of bad bytes abandoned and not returned
-    pub packets: u64,   // Number of packets returned to layer above
-    pub toolong: u64,   // Number of packets that were too long for their buffer
+    pub goodbytes: u64,      // Number of good bytes returned to layer above
+    pub badbytes: u64,       // Number of bad bytes abandoned and not returned
+    pub packets: u64,        // Number of packets returned to layer above
+    pub toolong: u64,        // Number of packets that were too long for their buffer
+    pub corrupt_frames: u64, // Number of packets cut short by a sentinel within a run
+}
+
+/// Counts of each run length seen by the decoder
+#[derive(Debug, Clone, Eq, Copy, PartialEq)]
+struct RunHistogram([u64; 256]);
+
+impl Default for RunHistogram {
+    fn default() -> Self {
+        RunHistogram([0; 256])
+    }
+}
+
+/// Callback for completed frames, see [`Cobs::set_on_complete`]
+pub type OnComplete = Box<dyn FnMut(usize) + Send>;
+
+// Holder for the frame completion callback. It belongs to the instance it was set on, so
+// isn't carried over by clone, and plays no part in comparisons.
+#[derive(Default)]
+struct CompleteHook(Option<OnComplete>);
+
+impl fmt::Debug for CompleteHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.0.is_some() { "Set" } else { "None" })
+    }
+}
+
+impl Clone for CompleteHook {
+    fn clone(&self) -> Self {
+        CompleteHook(None)
+    }
+}
+
+impl PartialEq for CompleteHook {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
+impl Eq for CompleteHook {}
+
 /// The COBS encoder/decoder object
-#[derive(Default, Debug, Clone, Eq, Copy, PartialEq)]
+///
+/// Cloning a decoder copies its state and statistics, but not any frame completion callback
+/// set by [`Cobs::set_on_complete`]; the clone has none. The callback plays no part in
+/// comparisons either. A decoder can be sent to another thread, so long as its callback can.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
 pub struct Cobs {
-    state: DecoderState, // Current state of the decoder
-    sentinel: u8,        // Sentinel value to be used (normally 0)
-    rxc: u8,             // Reception count..how many more to go in this run
-    maxcount: bool,      // Was rxc special case of 0xff?
-    stats: COBStats,     // Statistics
+    state: DecoderState,             // Current state of the decoder
+    sentinel: u8,                    // Sentinel value to be used (normally 0)
+    rxc: u8,                         // Reception count..how many more to go in this run
+    maxcount: bool,                  // Was rxc special case of 0xff?
+    stats: COBStats,                 // Statistics
+    runs: Option<Box<RunHistogram>>, // Run length histogram, if it's being collected
+    on_complete: CompleteHook,       // Called as each frame completes
+    frame_buf: Vec<u8>,              // Frame being assembled by for_each_frame
+    reject_empty: bool,              // Are zero length frames reported as errors?
+    overlong: OverlongPolicy,        // What to do with frames too long for their buffer
+    truncated: bool,                 // Has the frame being received been cut short?
+}
+
+/// What the decoder does with a frame that is too long for the buffer it is going into
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OverlongPolicy {
+    /// The whole frame is discarded
+    #[default]
+    Drop,
+    /// As much of the frame as fits is returned, flagged by `CobsError::Truncated`
+    Truncate,
 }
 
 /// Indication of if the packet is complete based on submitting byte(s) to the packetiser
@@ -81,6 +144,8 @@ pub const DEFAULT_SENTINEL: u8 = 0;
 
 /// Default max packet length for unencoded cobs packet
 pub const MAX_PACKET_LEN: usize = 8192;
+// Longest run of data bytes that can be described by a single run length
+const MAX_RUN_LEN: usize = 254;
 // Encoded packet has a start run length, a max of one extra byte per 254 bytes, and an end sentinel
 pub const MAX_ENC_PACKET_LEN: usize = 1 + MAX_PACKET_LEN + MAX_PACKET_LEN / 254 + 1;
 
@@ -95,12 +160,18 @@ pub enum CobsError {
     Overlong,
     /// Insufficent data in buffer to complete the packet
     ShortData,
-    /// Request to build packet of zero length
+    /// Request to build packet of zero length, or one received when they are rejected
     ZeroLength,
     /// Too busy to perform requested action
     Busy,
     /// Generic protocol error
     Error,
+    /// Error reading from the source
+    Io(ErrorKind),
+    /// Packet was too long for its buffer, so only its start has been returned
+    Truncated,
+    /// Packet was corrupt, a sentinel arrived where a data byte was due
+    PrematureSentinel,
 }
 
 impl fmt::Display for CobsError {
@@ -113,12 +184,21 @@ impl fmt::Display for CobsError {
             CobsError::ZeroLength => write!(f, "Zero length packet"),
             CobsError::Busy => write!(f, "Busy"),
             CobsError::Error => write!(f, "Generic error"),
+            CobsError::Io(k) => write!(f, "IO error: {}", k),
+            CobsError::Truncated => write!(f, "Packet was truncated"),
+            CobsError::PrematureSentinel => write!(f, "Sentinel within a run, packet is corrupt"),
         }
     }
 }
 
 impl std::error::Error for CobsError {}
 
+impl From<std::io::Error> for CobsError {
+    fn from(e: std::io::Error) -> Self {
+        CobsError::Io(e.kind())
+    }
+}
+
 impl Cobs {
     /// Create new instance of Cobs
     ///
@@ -155,6 +235,74 @@ impl Cobs {
         }
     }
 
+    /// Abandon any packet in progress and wait for the start of the next one
+    ///
+    /// Used when the layer above knows sync has been lost. If a packet is being received the
+    /// decoder discards everything up to and including the next sentinel, otherwise it is
+    /// already waiting for a packet to start. Statistics are kept, and the discarded bytes
+    /// are counted as bad. Any partial packet already in the caller's `Vec<u8>` should be
+    /// cleared by the caller.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let input = vec![0x05u8, 0x11, 0x00, 0x05, 0x11, 0x22, 0x33, 0x44, 0x00];
+    /// let mut dec = cobs::Cobs::new();
+    /// let mut v = Vec::<u8>::with_capacity(10000);
+    /// let _ = dec.get_frame(input[..2].iter(), &mut v);
+    /// dec.resync();
+    /// v.clear();
+    /// dec.get_frame(input[2..].iter(), &mut v).unwrap();
+    /// assert!(v == vec![0x11u8, 0x22, 0x33, 0x44]);
+    /// ```
+    ///
+    pub fn resync(&mut self) {
+        if DecoderState::Rxing == self.state {
+            self.state = DecoderState::Flushing;
+        }
+        self.truncated = false;
+        self.frame_buf.clear();
+    }
+
+    /// Is the decoder waiting for a packet to start?
+    ///
+    /// False while a packet is being received, and while the remains of a bad one are
+    /// being discarded up to the next sentinel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut dec = cobs::Cobs::new();
+    /// let mut v = Vec::with_capacity(16);
+    /// assert!(dec.is_idle());
+    /// let _ = dec.get_byte(0x05, &mut v);
+    /// assert!(!dec.is_idle());
+    /// ```
+    ///
+    pub fn is_idle(&self) -> bool {
+        self.state == DecoderState::Idle
+    }
+
+    /// Return how many more bytes the decoder expects in the current run
+    ///
+    /// For diagnostics, to show what a decoder that seems stuck is waiting for. The count
+    /// includes the byte that ends the run, which is either the next run length or the
+    /// sentinel. It is only meaningful while a packet is being received; when the decoder is
+    /// idle (see [`Cobs::is_idle`]) or discarding a bad packet it is whatever was left over.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut dec = cobs::Cobs::new();
+    /// let mut v = Vec::with_capacity(16);
+    /// let _ = dec.get_byte(0x03, &mut v);
+    /// assert_eq!(3, dec.bytes_in_current_run());
+    /// ```
+    ///
+    pub fn bytes_in_current_run(&self) -> u8 {
+        self.rxc
+    }
+
     /// Return statistics representing the behaviour of the decoder
     ///
     /// Provides information how many bytes have received specific dispensations by the decoder.
@@ -173,6 +321,144 @@ impl Cobs {
         self.stats
     }
 
+    /// Start collecting a histogram of run lengths
+    ///
+    /// Each run length (the distance to the next sentinel value in the original data) received
+    /// is counted. Lots of short runs show the payload is heavy in sentinel values. This is for
+    /// diagnostics only, so is off by default, and the histogram is only allocated once this is
+    /// called.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let input = vec![0x03u8, 0x11, 0x22, 0x02, 0x33, 0x00];
+    /// let mut dec = cobs::Cobs::new();
+    /// dec.enable_run_histogram();
+    /// let _ = dec.get_frame_as_vec(input.iter());
+    /// assert_eq!(1, dec.run_histogram()[3]);
+    /// assert_eq!(1, dec.run_histogram()[2]);
+    /// ```
+    ///
+    pub fn enable_run_histogram(&mut self) {
+        self.runs.get_or_insert_with(Box::default);
+    }
+
+    /// Return the histogram of run lengths, indexed by run length
+    ///
+    /// All counts are zero unless [`Cobs::enable_run_histogram`] has been called.
+    ///
+    pub fn run_histogram(&self) -> [u64; 256] {
+        self.runs.as_ref().map_or([0; 256], |r| r.0)
+    }
+
+    /// Set a callback to be made as each frame completes, with the length of the frame
+    ///
+    /// The callback is made synchronously inside [`Cobs::get_byte`] (and so the calls built on
+    /// it) as the terminating sentinel is processed, before the frame is returned. This lets a
+    /// timing harness stamp frame completion without polling the statistics, so it should be
+    /// quick. The callback isn't carried over if the instance is cloned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cobs::Cobs;
+    /// let mut dec = Cobs::new();
+    /// dec.set_on_complete(Some(Box::new(|len| println!("Frame of {} bytes", len))));
+    /// let _ = dec.get_frame_as_vec([0x02, 0x11, 0x00].iter());
+    /// ```
+    ///
+    pub fn set_on_complete(&mut self, hook: Option<OnComplete>) {
+        self.on_complete = CompleteHook(hook);
+    }
+
+    /// Report decoded zero length frames as an error rather than returning them
+    ///
+    /// A zero length payload is legal COBS (`[0x01, 0x00]`), but is of no use to a layer above
+    /// that always carries data. When set, such a frame completes with `CobsError::ZeroLength`
+    /// instead of `Ok`, so it can be dropped without further work. It isn't counted as a packet,
+    /// and the decoder is ready for the next one. Off by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cobs::{Cobs, CobsError};
+    /// let mut dec = Cobs::new();
+    /// dec.set_reject_empty(true);
+    /// let mut v = Vec::<u8>::with_capacity(10);
+    /// assert_eq!(Err(CobsError::ZeroLength), dec.get_frame([0x01, 0x00].iter(), &mut v));
+    /// ```
+    ///
+    pub fn set_reject_empty(&mut self, reject: bool) {
+        self.reject_empty = reject;
+    }
+
+    /// Choose what happens to a frame that is too long for the buffer it is going into
+    ///
+    /// By default ([`OverlongPolicy::Drop`]) the frame is discarded and counted as too long.
+    /// Consumers that can make use of part of a frame can choose [`OverlongPolicy::Truncate`]
+    /// instead. The rest of the frame is then discarded, and when it ends the buffer holds its
+    /// start, but `CobsError::Truncated` is returned rather than `Ok` so it can't be mistaken
+    /// for a whole frame. It is still counted as too long. Helpers that only pass on good frames
+    /// ([`Cobs::for_each_frame`], [`Cobs::get_frame_as_vec`]) skip it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cobs::{Cobs, CobsError, OverlongPolicy};
+    /// let mut dec = Cobs::new();
+    /// dec.set_overlong_policy(OverlongPolicy::Truncate);
+    /// let mut v = Vec::<u8>::with_capacity(2);
+    /// let r = dec.get_frame([0x04, 0x11, 0x22, 0x33, 0x00].iter(), &mut v);
+    /// assert_eq!((Err(CobsError::Truncated), vec![0x11, 0x22]), (r, v));
+    /// ```
+    ///
+    pub fn set_overlong_policy(&mut self, policy: OverlongPolicy) {
+        self.overlong = policy;
+    }
+
+    // Count a run length, if we're collecting them
+    fn count_run(&mut self, len: u8) {
+        if let Some(r) = self.runs.as_mut() {
+            r.0[len as usize] += 1;
+        }
+    }
+
+    /// Return the decoded length of the next complete frame in `buf`, without consuming it
+    ///
+    /// Scans `buf` as if it were the next input to the decoder, from wherever the decoder has
+    /// got to, but changes nothing. If the decoder is part way through a frame the length is of
+    /// what `buf` adds to it. Broken frames are skipped, as they would be by the decoder.
+    /// `None` if there is no complete frame in `buf`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cobs::Cobs;
+    /// let dec = Cobs::new();
+    /// assert_eq!(Some(2), dec.peek_next_frame_len(&[0x03, 0x11, 0x22, 0x00, 0x02]));
+    /// assert_eq!(None, dec.peek_next_frame_len(&[0x03, 0x11]));
+    /// ```
+    ///
+    pub fn peek_next_frame_len(&self, buf: &[u8]) -> Option<usize> {
+        let mut scan = Cobs {
+            state: self.state,
+            sentinel: self.sentinel,
+            rxc: self.rxc,
+            maxcount: self.maxcount,
+            ..Default::default()
+        };
+        let mut len = 0;
+        for c in buf {
+            match scan.process_token(*c).1 {
+                TokenResult::Store => len += 1,
+                TokenResult::Complete => return Some(len),
+                TokenResult::PrematureSentinel => len = 0,
+                TokenResult::Flushing | TokenResult::NoAction => (),
+            }
+        }
+        None
+    }
+
     /// Interate through the packet assembler, returning a Vec
     ///
     /// Feeds iterated bytes through the packet assembler, until either the stream expires or
@@ -209,12 +495,54 @@ impl Cobs {
                     /* Since the vector is ours there is no opportunity to extend it - its a bad frame */
                     self.stats.badbytes += op.len() as u64;
                     self.state = DecoderState::Flushing;
+                    self.truncated = false;
                 }
                 Err(r)
             }
         }
     }
 
+    /// Interate through the packet assembler, calling `f` with each complete packet
+    ///
+    /// Every packet is assembled in the same buffer, held by the decoder, and `f` borrows it,
+    /// so there is no allocation per packet. This is the cheapest way to process a chunk of
+    /// input containing many packets. A packet that isn't complete when the stream expires
+    /// is kept, and completed by the next call.
+    ///
+    /// Stats are updated and may be returned via [`Cobs::stats()`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let input = vec![0x03u8, 0x11, 0x22, 0x00, 0x02, 0x33, 0x00];
+    /// let mut dec = cobs::Cobs::new();
+    /// let mut total = 0;
+    /// dec.for_each_frame(input.iter(), |f| total += f.len());
+    /// assert_eq!(3, total);
+    /// ```
+    ///
+    pub fn for_each_frame<'a, F: FnMut(&[u8])>(
+        &mut self,
+        iter: impl Iterator<Item = &'a u8>,
+        mut f: F,
+    ) {
+        let mut op = std::mem::take(&mut self.frame_buf);
+        if op.capacity() == 0 {
+            op = Vec::with_capacity(MAX_PACKET_LEN);
+        }
+        for c in iter {
+            match self.get_byte(*c, &mut op) {
+                Ok(()) => {
+                    f(&op);
+                    op.clear();
+                }
+                Err(CobsError::Truncated) => op.clear(),
+                Err(_) => (),
+            }
+        }
+        self.frame_buf = op;
+    }
+
     /// Interate through the packet assembler, filling a pre-existing Vec
     ///
     /// Feeds iterated bytes through the packet assembler, until either the stream expires or
@@ -223,6 +551,13 @@ impl Cobs {
     /// It will not be extended if the length of the packet exceeds the capacity of the `Vec<u8>`,
     /// rather, the packet will be discarded.
     ///
+    /// A packet is only complete when its terminating sentinel arrives, so where the stream
+    /// expires makes no difference to the result. If it expires on the sentinel the packet is
+    /// returned, and the next call starts on the next packet; extra sentinels between packets
+    /// are skipped and never produce an empty packet. If it expires before the sentinel, every
+    /// byte so far (including a zero implied by a run length) is already in the `Vec<u8>`, and
+    /// the packet is completed by the sentinel on a later call.
+    ///
     /// Stats are updated and may be returned via [`Cobs::stats()`].
     ///
     /// # Return value
@@ -266,6 +601,45 @@ impl Cobs {
         }
     }
 
+    /// Read a frame from a source, filling a pre-existing Vec
+    ///
+    /// Pulls bytes from the reader until a packet is complete. Bytes are read one at a time so
+    /// nothing beyond the end of the packet is consumed, and the next call picks up from there.
+    /// For unbuffered sources (files, sockets) wrap the reader in a [`std::io::BufReader`].
+    /// As with [`Cobs::get_frame`] the `Vec<u8>` will not be extended beyond its capacity.
+    ///
+    /// Stats are updated and may be returned via [`Cobs::stats()`].
+    ///
+    /// # Errors
+    /// `CobsError::ShortData` is returned if the source runs out before the packet is complete,
+    /// `CobsError::Io` if reading from it fails, and otherwise as [`Cobs::get_byte`].
+    ///
+    /// # Example
+    /// ```
+    /// let input = vec![0x05u8, 0x11, 0x22, 0x33, 0x44, 0x00];
+    /// let result = vec![0x11u8, 0x22, 0x33, 0x44];
+    /// let mut dec = cobs::Cobs::new();
+    /// let mut v = Vec::<u8>::with_capacity(10000);
+    /// dec.read_frame(&mut std::io::Cursor::new(input), &mut v).unwrap();
+    /// assert!(v == result);
+    /// ```
+    ///
+    pub fn read_frame<R: Read>(&mut self, r: &mut R, op: &mut Vec<u8>) -> Result<(), CobsError> {
+        let mut t = [0u8; 1];
+        loop {
+            match r.read(&mut t) {
+                Ok(0) => return Err(CobsError::ShortData),
+                Ok(_) => match self.get_byte(t[0], op) {
+                    Ok(_s) => return Ok(()),
+                    Err(CobsError::Ongoing) => (),
+                    Err(e) => return Err(e),
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
     /// Pass a single byte through the packet assembler
     ///
     /// Feeds the passed byte through the packet assembler, and indicates if the packet is now complete.
@@ -297,10 +671,12 @@ impl Cobs {
         let (val, action) = self.process_token(c);
         match action {
             /* Something went wrong - accumulate the current captured bytes and flush */
-            TokenResult::Error => {
+            TokenResult::PrematureSentinel => {
                 self.stats.badbytes += op.len() as u64;
+                self.stats.corrupt_frames += 1;
+                self.truncated = false;
                 op.clear();
-                return Err(CobsError::Error);
+                return Err(CobsError::PrematureSentinel);
             }
 
             /* We are still flushing, increment the bad bytes */
@@ -311,8 +687,14 @@ impl Cobs {
 
             /* This token is to be stored, of there is room */
             TokenResult::Store => {
-                if op.len() < op.capacity() {
+                if self.truncated {
+                    self.stats.badbytes += 1;
+                } else if op.len() < op.capacity() {
                     op.push(val);
+                } else if self.overlong == OverlongPolicy::Truncate {
+                    /* Keep what we have, and lose the rest of the frame */
+                    self.stats.badbytes += 1;
+                    self.truncated = true;
                 } else {
                     self.stats.badbytes += op.len() as u64;
                     self.stats.toolong += 1;
@@ -324,8 +706,19 @@ impl Cobs {
 
             /* This frame is complete, return it */
             TokenResult::Complete => {
+                if self.reject_empty && op.is_empty() {
+                    return Err(CobsError::ZeroLength);
+                }
+                if mem::take(&mut self.truncated) {
+                    self.stats.goodbytes += op.len() as u64;
+                    self.stats.toolong += 1;
+                    return Err(CobsError::Truncated);
+                }
                 self.stats.packets += 1;
                 self.stats.goodbytes += op.len() as u64;
+                if let Some(hook) = self.on_complete.0.as_mut() {
+                    hook(op.len());
+                }
                 return Ok(());
             }
         }
@@ -339,6 +732,7 @@ impl Cobs {
             /* === Waiting for a non-sentinel value. This will be the size of this run */
             DecoderState::Idle => {
                 if tok != self.sentinel {
+                    self.count_run(tok);
                     self.rxc = tok;
                     self.maxcount = tok == 255;
                     self.state = DecoderState::Rxing;
@@ -359,13 +753,14 @@ impl Cobs {
                         } else {
                             TokenResult::NoAction
                         };
+                        self.count_run(tok);
                         self.rxc = tok;
                         self.maxcount = tok == 255;
                         (self.sentinel, action)
                     }
                 } else if self.sentinel == tok {
                     self.state = DecoderState::Flushing;
-                    (tok, TokenResult::Error)
+                    (tok, TokenResult::PrematureSentinel)
                 } else {
                     (tok, TokenResult::Store)
                 }
@@ -427,7 +822,7 @@ impl Cobs {
     /// let test_encoded = dec.cobs_encode_into_vec( &[&unencoded[..]] ).unwrap();
     /// assert!(encoded == test_encoded);
     ///
-    pub fn cobs_encode_into_vec(self, ip: &[&[u8]]) -> Result<Vec<u8>, CobsError> {
+    pub fn cobs_encode_into_vec(&self, ip: &[&[u8]]) -> Result<Vec<u8>, CobsError> {
         let mut e = Vec::<u8>::with_capacity(MAX_ENC_PACKET_LEN);
         match self.cobs_encode(ip, &mut e) {
             Ok(_s) => Ok(e),
@@ -447,6 +842,10 @@ impl Cobs {
     /// passed. `CobsError::Overlong` is returned for the case that it was not possible to
     /// encode the input vectors into the output vector.
     ///
+    /// A single slice of up to 254 bytes with no sentinels in it (the common case for short
+    /// packets) is a single run, so it is copied straight through rather than being tracked
+    /// byte by byte.
+    ///
     /// # Example
     /// ```
     /// let encoded = vec![0x05u8, 0x11, 0x22, 0x33, 0x44, 0x00];
@@ -457,7 +856,7 @@ impl Cobs {
     /// assert!(encoded == v);
     ///
     pub fn cobs_encode<'a>(
-        self,
+        &self,
         ip: &'a [&[u8]],
         e: &'a mut Vec<u8>,
     ) -> Result<&'a mut Vec<u8>, CobsError> {
@@ -471,31 +870,188 @@ impl Cobs {
         } else if Self::max_possible_enc_len(enc_size) > MAX_ENC_PACKET_LEN {
             Err(CobsError::Overlong)
         } else {
-            let mut d: usize = 0; // Position for size pointer to end of slice
-            e.push(self.sentinel); // Make room for initial stride byte
-
-            for inner in ip.iter() {
-                for i in inner.iter() {
-                    /* Deal with case of 0xff bytes with no sentinel - start a new run */
-                    if e.len() - d == 0xff {
-                        e[d] = (e.len() - d) as u8;
-                        d = e.len();
-                        e.push(self.sentinel);
-                    }
+            match ip {
+                /* Fast path: a short packet with no sentinels in it is a single run */
+                [only] if only.len() <= MAX_RUN_LEN && !only.contains(&self.sentinel) => {
+                    e.push((only.len() + 1) as u8);
+                    e.extend_from_slice(only);
+                    e.push(self.sentinel);
+                }
+                _ => self.encode_runs(ip, e),
+            }
+            Ok(e)
+        }
+    }
 
-                    /* Deal with case that this is a sentinel - start a new run */
-                    if *i == self.sentinel {
-                        e[d] = (e.len() - d) as u8;
-                        d = e.len();
-                    }
+    /// Encode cobs packet without the terminating sentinel
+    ///
+    /// As [`Cobs::cobs_encode`], but the sentinel that ends the packet is left off. This is for
+    /// transports that delimit packets themselves (by length, or a leading delimiter) so the
+    /// sentinel would be redundant. The decoder only knows a packet has ended when it sees the
+    /// sentinel, so before such a packet is passed to [`Cobs::get_frame`] (or the like) the
+    /// receiver must put the sentinel back on the end, once the transport says it is complete.
+    ///
+    /// # Errors
+    /// As [`Cobs::cobs_encode`].
+    ///
+    /// # Example
+    /// ```
+    /// let cobs = cobs::Cobs::new();
+    /// let mut v = Vec::new();
+    /// cobs.cobs_encode_no_terminator(&[&[0x11, 0x22]], &mut v).unwrap();
+    /// assert_eq!(vec![0x03u8, 0x11, 0x22], v);
+    /// ```
+    ///
+    pub fn cobs_encode_no_terminator<'a>(
+        &self,
+        ip: &'a [&[u8]],
+        e: &'a mut Vec<u8>,
+    ) -> Result<&'a mut Vec<u8>, CobsError> {
+        let e = self.cobs_encode(ip, e)?;
+        e.pop();
+        Ok(e)
+    }
+
+    /// Encode cobs packet into a caller provided buffer, with a hard cap on its size
+    ///
+    /// Encodes `ip` exactly as [`Cobs::cobs_encode`] does, but into `out`, returning the
+    /// encoded length including the terminating sentinel. Rather than rejecting anything that
+    /// might exceed the limit in the worst case, encode stops as soon as the output would go
+    /// beyond `max` bytes (or the length of `out`, if that is smaller). This suits links with a
+    /// fixed MTU, where a packet that fits should be sent even if a worst case one wouldn't.
+    ///
+    /// # Errors
+    ///  `CobsError::ZeroLength` is returned for an empty input, and `CobsError::Overlong` if the
+    /// encoded packet won't fit. In that case the content of `out` is undefined.
+    ///
+    /// # Example
+    /// ```
+    /// let mut out = [0u8; 6];
+    /// let cobs = cobs::Cobs::new();
+    /// assert_eq!(Ok(6), cobs.encode_capped(&[0x11, 0x22, 0x33, 0x44], &mut out, 6));
+    /// assert_eq!([0x05u8, 0x11, 0x22, 0x33, 0x44, 0x00], out);
+    /// assert_eq!(
+    ///     Err(cobs::CobsError::Overlong),
+    ///     cobs.encode_capped(&[0x11, 0x22, 0x33, 0x44], &mut out, 5)
+    /// );
+    /// ```
+    ///
+    pub fn encode_capped(&self, ip: &[u8], out: &mut [u8], max: usize) -> Result<usize, CobsError> {
+        let cap = max.min(out.len());
+        let put = |out: &mut [u8], n: &mut usize, c: u8| {
+            if *n >= cap {
+                return Err(CobsError::Overlong);
+            }
+            out[*n] = c;
+            *n += 1;
+            Ok(())
+        };
 
-                    /* This appends either a data byte or a sentinel (which will be overwritten with a run length later) */
-                    e.push(*i);
+        if ip.is_empty() {
+            return Err(CobsError::ZeroLength);
+        }
+
+        let mut d: usize = 0; // Position for size pointer to end of slice
+        let mut n: usize = 0; // Next position to write
+        put(out, &mut n, self.sentinel)?; // Make room for initial stride byte
+        for c in ip {
+            /* Deal with case of 0xff bytes with no sentinel - start a new run */
+            if n - d == 0xff {
+                out[d] = (n - d) as u8;
+                d = n;
+                put(out, &mut n, self.sentinel)?;
+            }
+
+            /* Deal with case that this is a sentinel - start a new run */
+            if *c == self.sentinel {
+                out[d] = (n - d) as u8;
+                d = n;
+            }
+            put(out, &mut n, *c)?;
+        }
+        out[d] = (n - d) as u8;
+        put(out, &mut n, self.sentinel)?;
+        Ok(n)
+    }
+
+    /// Encode cobs packet onto the end of a Vec, returning where it was put
+    ///
+    /// Anything already in `out` is kept, so several packets can be gathered into one buffer
+    /// and sent with a single write. The returned range is the encoded packet, including its
+    /// terminating sentinel, within `out`.
+    ///
+    /// # Errors
+    /// As [`Cobs::cobs_encode`]. On error `out` is left as it was.
+    ///
+    /// # Example
+    /// ```
+    /// let cobs = cobs::Cobs::new();
+    /// let mut out = Vec::new();
+    /// assert_eq!(Ok(0..3), cobs.encode_append(&[0x11], &mut out));
+    /// assert_eq!(Ok(3..6), cobs.encode_append(&[0x22], &mut out));
+    /// assert_eq!(vec![0x02u8, 0x11, 0x00, 0x02, 0x22, 0x00], out);
+    /// ```
+    ///
+    pub fn encode_append(&self, ip: &[u8], out: &mut Vec<u8>) -> Result<Range<usize>, CobsError> {
+        let start = out.len();
+        self.cobs_encode(&[ip], out)?;
+        Ok(start..out.len())
+    }
+
+    // General encode, tracking the runs between sentinels byte by byte
+    fn encode_runs(&self, ip: &[&[u8]], e: &mut Vec<u8>) {
+        let mut d: usize = e.len(); // Position for size pointer to end of slice
+        e.push(self.sentinel); // Make room for initial stride byte
+
+        for inner in ip.iter() {
+            for i in inner.iter() {
+                /* Deal with case of 0xff bytes with no sentinel - start a new run */
+                if e.len() - d == 0xff {
+                    e[d] = (e.len() - d) as u8;
+                    d = e.len();
+                    e.push(self.sentinel);
                 }
-                e[d] = (e.len() - d) as u8;
-                e.push(self.sentinel);
+
+                /* Deal with case that this is a sentinel - start a new run */
+                if *i == self.sentinel {
+                    e[d] = (e.len() - d) as u8;
+                    d = e.len();
+                }
+
+                /* This appends either a data byte or a sentinel (which will be overwritten with a run length later) */
+                e.push(*i);
             }
-            Ok(e)
+            e[d] = (e.len() - d) as u8;
+            e.push(self.sentinel);
         }
     }
 }
+
+/// Check that `payload` survives an encode and decode with the given sentinel
+///
+/// Encodes `payload` as a single packet, decodes the result with a fresh decoder and compares
+/// the two. This is a property for fuzzers to assert over arbitrary payloads, and a quick way
+/// to check a sentinel choice works for the data a link carries. Payloads that can't be
+/// encoded (empty, or longer than [`MAX_PACKET_LEN`]) don't round trip, so return false.
+///
+/// Run lengths are sent as they are, so with a sentinel other than 0 any run whose length
+/// byte equals the sentinel ends the packet early. That's the kind of payload this catches.
+///
+/// # Example
+/// ```
+/// assert!(cobs::roundtrip_ok(&[0x11, 0x00, 0x22], 0));
+/// assert!(!cobs::roundtrip_ok(&[], 0));
+/// assert!(!cobs::roundtrip_ok(&[0x11, 0x22], 3));
+/// ```
+///
+pub fn roundtrip_ok(payload: &[u8], sentinel: u8) -> bool {
+    let mut c = Cobs::new();
+    if c.set_sentinel(sentinel, false).is_err() {
+        return false;
+    }
+    let Ok(encoded) = c.cobs_encode_into_vec(&[payload]) else {
+        return false;
+    };
+    c.get_frame_as_vec(encoded.iter())
+        .is_ok_and(|decoded| decoded == payload)
+}
@@ -265,26 +265,27 @@ fn wikipedia_tests() {
     ];
 
     println!("Check wikipedia test cases");
-    for n in 0..tests.len() {
+    for (n, t) in tests.iter().enumerate() {
         let mut dec: Cobs = Cobs::new();
         //let mut v = Vec::<u8>::with_capacity(10000);
-        let v = dec.get_frame_as_vec(tests[n].input.iter()).unwrap();
+        let v = dec.get_frame_as_vec(t.input.iter()).unwrap();
 
-        assert!(v == tests[n].result);
+        assert!(v == t.result);
         println!(
             "Example {}: {}",
             n + 1,
-            if v == tests[n].result { "OK" } else { "Failed" }
+            if v == t.result { "OK" } else { "Failed" }
         );
 
         assert_eq!(
             dec.stats(),
             COBStats {
-                inbytes: tests[n].input.len() as u64,
-                goodbytes: tests[n].result.len() as u64,
+                inbytes: t.input.len() as u64,
+                goodbytes: t.result.len() as u64,
                 badbytes: 0,
                 packets: 1,
-                toolong: 0
+                toolong: 0,
+                corrupt_frames: 0
             }
         );
     }
@@ -358,7 +359,8 @@ fn check_incomplete_packet() {
             goodbytes: 0,
             badbytes: BUFFER_CAP as u64,
             packets: 0,
-            toolong: 1
+            toolong: 1,
+            corrupt_frames: 0
         }
     );
 
@@ -373,7 +375,8 @@ fn check_incomplete_packet() {
             goodbytes: 0,
             badbytes: test_packet.result.len() as u64 - 1,
             packets: 0,
-            toolong: 1
+            toolong: 1,
+            corrupt_frames: 0
         }
     );
 }
@@ -406,7 +409,8 @@ fn short_packet() {
             goodbytes: 4,
             badbytes: 7,
             packets: 1,
-            toolong: 0
+            toolong: 0,
+            corrupt_frames: 1
         }
     );
     assert_eq!(vec![0; 0], v);
@@ -468,7 +472,8 @@ fn byte_feeder() {
             goodbytes: result.len() as u64,
             badbytes: 0,
             packets: 1,
-            toolong: 0
+            toolong: 0,
+            corrupt_frames: 0
         }
     );
 }
@@ -730,11 +735,11 @@ fn test_encode() {
         },
     ];
 
-    for t in 0..tests.len() {
+    for (n, t) in tests.iter().enumerate() {
         let dec = Cobs::new();
-        let r = dec.cobs_encode_into_vec(&[&tests[t].result[..]]).unwrap();
-        assert_eq!(r, tests[t].input);
-        println!("Test {}: OK", t + 1);
+        let r = dec.cobs_encode_into_vec(&[&t.result[..]]).unwrap();
+        assert_eq!(r, t.input);
+        println!("Test {}: OK", n + 1);
     }
 }
 
@@ -782,3 +787,472 @@ fn smoke_test() {
         assert_eq!(original, dec_candidate);
     }
 }
+
+#[test]
+fn read_frames() {
+    let input = vec![
+        0x05u8, 0x11, 0x22, 0x33, 0x44, 0x00, // First frame
+        0x03, 0x11, 0x22, 0x02, 0x33, 0x00, // Second frame
+        0x02, 0x55, // Incomplete frame
+    ];
+    let mut dec = Cobs::new();
+    let mut c = std::io::Cursor::new(input);
+
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    dec.read_frame(&mut c, &mut v).unwrap();
+    assert_eq!(v, [0x11, 0x22, 0x33, 0x44]);
+
+    /* The second frame is left in the reader for the next call */
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    dec.read_frame(&mut c, &mut v).unwrap();
+    assert_eq!(v, [0x11, 0x22, 0x00, 0x33]);
+
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    assert_eq!(dec.read_frame(&mut c, &mut v), Err(CobsError::ShortData));
+    assert_eq!(dec.stats().packets, 2);
+}
+
+#[test]
+fn read_frame_io_error() {
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(ErrorKind::ConnectionReset))
+        }
+    }
+    let mut dec = Cobs::new();
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    assert_eq!(
+        dec.read_frame(&mut Broken, &mut v),
+        Err(CobsError::Io(ErrorKind::ConnectionReset))
+    );
+
+    /* Failing part way through a frame isn't mistaken for the data running out */
+    let mut r = std::io::Read::chain(&[0x05u8, 0x11, 0x22][..], Broken);
+    let e = dec.read_frame(&mut r, &mut v).unwrap_err();
+    assert_eq!(CobsError::Io(ErrorKind::ConnectionReset), e);
+    assert_eq!("IO error: connection reset", e.to_string());
+}
+
+#[test]
+fn run_histogram() {
+    let input = vec![
+        0x01u8, 0x01, 0x00, // Two sentinels
+        0x03, 0x11, 0x22, 0x02, 0x33, 0x00, // Runs of 3 and 2
+        0x05, 0x11, 0x22, 0x33, 0x44, 0x00, // Run of 5
+    ];
+
+    /* Off by default */
+    let mut dec = Cobs::new();
+    let mut i = input.iter();
+    while dec.get_frame_as_vec(&mut i).is_ok() {}
+    assert_eq!([0u64; 256], dec.run_histogram());
+    assert!(dec.runs.is_none());
+
+    let mut dec = Cobs::new();
+    dec.enable_run_histogram();
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    let mut i = input.iter();
+    while dec.get_frame(&mut i, &mut v).is_ok() {
+        v.clear();
+    }
+    let mut expected = [0u64; 256];
+    expected[1] = 2;
+    expected[2] = 1;
+    expected[3] = 1;
+    expected[5] = 1;
+    assert_eq!(expected, dec.run_histogram());
+    assert_eq!(3, dec.stats().packets);
+}
+
+/// Packets whose encoding ends with a run length implying a zero, or with a maximum length run
+#[cfg(test)]
+fn run_end_packets() -> Vec<Vec<u8>> {
+    vec![
+        vec![0x11, 0x22, 0x00],
+        vec![0x11, 0x00, 0x00],
+        vec![0x00],
+        vec![0x55; 254],
+        [vec![0x55; 254], vec![0x00]].concat(),
+    ]
+}
+
+#[test]
+fn ends_on_sentinel() {
+    for p in run_end_packets() {
+        let mut ip = Cobs::new().cobs_encode_into_vec(&[&p[..]]).unwrap();
+        ip.push(0x00); // Extra sentinel, which must not become an empty packet
+        let mut dec = Cobs::new();
+        let mut i = ip.iter();
+        let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+
+        dec.get_frame(&mut i, &mut v).unwrap();
+        assert_eq!(p, v);
+        v.clear();
+        assert_eq!(dec.get_frame(&mut i, &mut v), Err(CobsError::ShortData));
+        assert!(v.is_empty());
+        assert_eq!(dec.stats().packets, 1);
+        assert_eq!(dec.stats().badbytes, 0);
+    }
+}
+
+#[test]
+fn ends_before_sentinel() {
+    for p in run_end_packets() {
+        let ip = Cobs::new().cobs_encode_into_vec(&[&p[..]]).unwrap();
+        let (body, sentinel) = ip.split_at(ip.len() - 1);
+        let mut dec = Cobs::new();
+        let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+
+        /* Everything is there, but the packet isn't done until the sentinel */
+        assert_eq!(
+            dec.get_frame(body.iter(), &mut v),
+            Err(CobsError::ShortData)
+        );
+        assert_eq!(p, v);
+        dec.get_frame(sentinel.iter(), &mut v).unwrap();
+        assert_eq!(p, v);
+        assert_eq!(dec.stats().packets, 1);
+    }
+}
+
+#[test]
+fn fast_path_matches_general() {
+    for sentinel in [0x00, 0x55] {
+        let mut c = Cobs::new();
+        c.set_sentinel(sentinel, false).unwrap();
+        for len in 1..=MAX_RUN_LEN + 2 {
+            let mut p: Vec<u8> = (0..len).map(|_| fastrand::u8(..)).collect();
+            p.iter_mut()
+                .filter(|b| **b == sentinel)
+                .for_each(|b| *b = !sentinel);
+            for payload in [
+                p.clone(),
+                [&p[..len / 2], &[sentinel], &p[len / 2..]].concat(),
+            ] {
+                let mut fast = Vec::new();
+                let mut general = Vec::new();
+                c.cobs_encode(&[&payload[..]], &mut fast).unwrap();
+                c.encode_runs(&[&payload[..]], &mut general);
+                assert_eq!(general, fast, "length {}", payload.len());
+            }
+        }
+    }
+}
+
+#[test]
+fn on_complete_callback() {
+    use std::sync::{Arc, Mutex};
+
+    let lens = Arc::new(Mutex::new(Vec::new()));
+    let seen = lens.clone();
+    let mut dec = Cobs::new();
+    dec.set_on_complete(Some(Box::new(move |len| seen.lock().unwrap().push(len))));
+
+    let mut ip = Vec::new();
+    for p in [&[0x11u8, 0x22][..], &[0x00], &[0x33; 300]] {
+        ip.extend(Cobs::new().cobs_encode_into_vec(&[p]).unwrap());
+    }
+    ip.extend([0x03, 0x11, 0x00]); // Broken frame, which doesn't complete
+
+    /* The decoder, callback and all, can be moved to a reader thread */
+    let dec = std::thread::spawn(move || {
+        let mut i = ip.iter();
+        let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+        while dec.get_frame(&mut i, &mut v) != Err(CobsError::ShortData) {
+            v.clear();
+        }
+        dec
+    })
+    .join()
+    .unwrap();
+    assert_eq!(vec![2, 1, 300], *lens.lock().unwrap());
+
+    /* A clone doesn't take the callback with it */
+    let mut copy = dec.clone();
+    let _ = copy.get_frame_as_vec([0x02, 0x11, 0x00].iter());
+    assert_eq!(3, lens.lock().unwrap().len());
+}
+
+#[test]
+fn peek_next_frame_len() {
+    let first = Cobs::new()
+        .cobs_encode_into_vec(&[&[0x11, 0x00, 0x22, 0x33][..]])
+        .unwrap();
+    let second = Cobs::new()
+        .cobs_encode_into_vec(&[&[0x44; 10][..]])
+        .unwrap();
+    let ip = [&first[..], &second[..6]].concat();
+
+    let mut dec = Cobs::new();
+    assert_eq!(Some(4), dec.peek_next_frame_len(&ip));
+    assert_eq!(Some(4), dec.peek_next_frame_len(&ip));
+    assert_eq!(0, dec.stats().inbytes);
+
+    /* Once the first is taken only half a frame remains */
+    let mut i = ip.iter();
+    assert_eq!(
+        vec![0x11, 0x00, 0x22, 0x33],
+        dec.get_frame_as_vec(&mut i).unwrap()
+    );
+    let rest: Vec<u8> = i.copied().collect();
+    assert_eq!(None, dec.peek_next_frame_len(&rest));
+
+    /* ...and the decoder picks up from part way through that frame */
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    assert_eq!(
+        Err(CobsError::ShortData),
+        dec.get_frame(rest.iter(), &mut v)
+    );
+    assert_eq!(Some(5), dec.peek_next_frame_len(&second[6..]));
+}
+
+#[test]
+fn for_each_frame() {
+    let mut ip = Vec::new();
+    let mut expected = 0;
+    for len in 1..=40 {
+        let p: Vec<u8> = (0..len).map(|n| n as u8).collect();
+        expected += len;
+        ip.extend(Cobs::new().cobs_encode_into_vec(&[&p[..]]).unwrap());
+    }
+
+    /* Deliver in chunks so some frames are split between calls */
+    let mut dec = Cobs::new();
+    let (mut frames, mut total) = (0, 0);
+    for chunk in ip.chunks(37) {
+        dec.for_each_frame(chunk.iter(), |f| {
+            frames += 1;
+            total += f.len();
+        });
+    }
+    assert_eq!(40, frames);
+    assert_eq!(expected, total);
+    assert_eq!(40, dec.stats().packets);
+}
+
+#[test]
+fn encode_capped() {
+    let cobs = Cobs::new();
+    let mut out = [0u8; 600];
+    let inputs: [Vec<u8>; 4] = [
+        vec![0x11],
+        vec![0x00, 0x11, 0x00],
+        (0..=255).collect(),
+        (0..300).map(|n| (n % 255) as u8 + 1).collect(),
+    ];
+    for ip in inputs {
+        let expected = cobs.cobs_encode_into_vec(&[&ip[..]]).unwrap();
+
+        /* Exactly at the cap it fits, and matches the regular encode */
+        assert_eq!(
+            Ok(expected.len()),
+            cobs.encode_capped(&ip, &mut out, expected.len())
+        );
+        assert_eq!(expected, out[..expected.len()]);
+
+        /* ...one byte less and it doesn't */
+        assert_eq!(
+            Err(CobsError::Overlong),
+            cobs.encode_capped(&ip, &mut out, expected.len() - 1)
+        );
+        assert_eq!(
+            Err(CobsError::Overlong),
+            cobs.encode_capped(&ip, &mut out[..expected.len() - 1], 600)
+        );
+    }
+    assert_eq!(
+        Err(CobsError::ZeroLength),
+        cobs.encode_capped(&[], &mut out, 600)
+    );
+}
+
+#[test]
+fn encode_append() {
+    let cobs = Cobs::new();
+    let frames: [Vec<u8>; 3] = [
+        vec![0x11, 0x22],
+        vec![0x00, 0x33, 0x00],
+        (0..300).map(|n| n as u8).collect(),
+    ];
+    let mut out = vec![0x00]; // Something already there is kept
+    let ranges: Vec<_> = frames
+        .iter()
+        .map(|f| cobs.encode_append(f, &mut out).unwrap())
+        .collect();
+    assert_eq!(0x00, out[0]);
+    assert_eq!(1, ranges[0].start);
+    assert_eq!(out.len(), ranges[2].end);
+
+    /* Each range decodes back to its frame on its own */
+    for (f, r) in frames.iter().zip(ranges) {
+        assert_eq!(
+            cobs.cobs_encode_into_vec(&[&f[..]]).unwrap(),
+            out[r.clone()]
+        );
+        let mut dec = Cobs::new();
+        let mut v = Vec::with_capacity(MAX_PACKET_LEN);
+        dec.get_frame(out[r].iter(), &mut v).unwrap();
+        assert_eq!(f, &v);
+    }
+
+    assert_eq!(
+        Err(CobsError::ZeroLength),
+        cobs.encode_append(&[], &mut out)
+    );
+}
+
+#[test]
+fn reject_empty() {
+    let input = [0x01u8, 0x00, 0x02, 0x11, 0x00];
+    let mut v = Vec::with_capacity(MAX_PACKET_LEN);
+
+    /* By default an empty frame is just another frame */
+    let mut dec = Cobs::new();
+    assert_eq!(Ok(()), dec.get_frame(input.iter(), &mut v));
+    assert!(v.is_empty());
+
+    let mut dec = Cobs::new();
+    dec.set_reject_empty(true);
+    let mut s = input.iter();
+    assert_eq!(Err(CobsError::ZeroLength), dec.get_frame(&mut s, &mut v));
+    assert_eq!(Ok(()), dec.get_frame(&mut s, &mut v));
+    assert_eq!(vec![0x11], v);
+    assert_eq!(1, dec.stats().packets);
+}
+
+#[test]
+fn encode_no_terminator() {
+    let cobs = Cobs::new();
+    for ip in [
+        vec![0x11u8, 0x22, 0x33],
+        vec![0x00, 0x11, 0x00],
+        (0..300).map(|n| n as u8).collect(),
+    ] {
+        let full = cobs.cobs_encode_into_vec(&[&ip[..]]).unwrap();
+        let mut v = vec![0xaa]; // Something already there is kept
+        cobs.cobs_encode_no_terminator(&[&ip[..]], &mut v).unwrap();
+        assert_eq!(0xaa, v[0]);
+        assert_eq!(&full[..full.len() - 1], &v[1..]);
+        assert_ne!(Some(&DEFAULT_SENTINEL), v.last());
+
+        /* Decodes once the sentinel is put back */
+        v.push(DEFAULT_SENTINEL);
+        let mut dec = Cobs::new();
+        let mut op = Vec::with_capacity(MAX_PACKET_LEN);
+        dec.get_frame(v[1..].iter(), &mut op).unwrap();
+        assert_eq!(ip, op);
+    }
+
+    let mut v = Vec::new();
+    assert_eq!(
+        Err(CobsError::ZeroLength),
+        cobs.cobs_encode_no_terminator(&[], &mut v).map(|_| ())
+    );
+    assert!(v.is_empty());
+}
+
+#[test]
+fn overlong_policy() {
+    let input = [
+        0x06u8, 0x11, 0x22, 0x33, 0x44, 0x55, 0x00, // Too long for the buffer
+        0x03, 0x66, 0x77, 0x00, // Fits
+    ];
+
+    /* By default the frame is lost */
+    let mut dec = Cobs::new();
+    let mut s = input.iter();
+    let mut v = Vec::<u8>::with_capacity(3);
+    assert_eq!(Err(CobsError::Error), dec.get_frame(&mut s, &mut v));
+    let mut v = Vec::<u8>::with_capacity(3);
+    assert_eq!(Ok(()), dec.get_frame(&mut s, &mut v));
+    assert_eq!(vec![0x66, 0x77], v);
+
+    /* ...but it can be cut down to fit instead */
+    let mut dec = Cobs::new();
+    dec.set_overlong_policy(OverlongPolicy::Truncate);
+    let mut s = input.iter();
+    let mut v = Vec::<u8>::with_capacity(3);
+    assert_eq!(Err(CobsError::Truncated), dec.get_frame(&mut s, &mut v));
+    assert_eq!(vec![0x11, 0x22, 0x33], v);
+    let mut v = Vec::<u8>::with_capacity(3);
+    assert_eq!(Ok(()), dec.get_frame(&mut s, &mut v));
+    assert_eq!(vec![0x66, 0x77], v);
+    assert_eq!(
+        COBStats {
+            inbytes: input.len() as u64,
+            goodbytes: 5,
+            badbytes: 2,
+            packets: 1,
+            toolong: 1,
+            corrupt_frames: 0
+        },
+        dec.stats()
+    );
+}
+
+#[test]
+fn premature_sentinel() {
+    let input = [
+        0x05u8, 0x11, 0x22, 0x00, // Run of 5 cut short
+        0x00, // Back in step
+        0x02, 0x33, 0x00, // Good frame
+    ];
+    let mut dec = Cobs::new();
+    let mut s = input.iter();
+    let mut v = Vec::<u8>::with_capacity(MAX_PACKET_LEN);
+    assert_eq!(
+        Err(CobsError::PrematureSentinel),
+        dec.get_frame(&mut s, &mut v)
+    );
+    assert!(v.is_empty());
+    dec.get_frame(&mut s, &mut v).unwrap();
+    assert_eq!(vec![0x33], v);
+
+    /* Counted apart from bytes lost for other reasons */
+    let stats = dec.stats();
+    assert_eq!(
+        (1, 2, 0),
+        (stats.corrupt_frames, stats.badbytes, stats.toolong)
+    );
+}
+
+#[test]
+fn roundtrip_runs() {
+    /* Runs either side of the 254 byte limit, of sentinels and of data, with neighbours */
+    for len in [1usize, 253, 254, 255, 256, 508, 509, 510] {
+        for fill in [0x00u8, 0x55] {
+            let mut p = vec![fill; len];
+            assert!(roundtrip_ok(&p, 0), "{len} x {fill:#x}");
+            p.insert(0, 0x00);
+            p.push(0x55);
+            assert!(roundtrip_ok(&p, 0), "{len} x {fill:#x}, bounded");
+            p[0] = 0x55;
+            p.push(0x00);
+            assert!(roundtrip_ok(&p, 0), "{len} x {fill:#x}, bounded");
+        }
+    }
+    assert!(!roundtrip_ok(&[], 0));
+    assert!(!roundtrip_ok(&vec![0x11; MAX_PACKET_LEN + 1], 0));
+
+    /* Another sentinel only fails where a run length lands on it */
+    assert!(roundtrip_ok(&[0x55; 0x7d], 0x7f));
+    assert!(!roundtrip_ok(&[0x55; 0x7e], 0x7f));
+}
+
+#[test]
+fn bytes_in_current_run() {
+    let mut dec = Cobs::new();
+    let mut v = Vec::with_capacity(16);
+    assert!(dec.is_idle());
+    let _ = dec.get_byte(0x05, &mut v);
+    assert!(!dec.is_idle());
+    for (b, left) in [(0x11u8, 4u8), (0x22, 3), (0x33, 2), (0x44, 1)] {
+        assert_eq!(left + 1, dec.bytes_in_current_run());
+        let _ = dec.get_byte(b, &mut v);
+        assert_eq!(left, dec.bytes_in_current_run());
+    }
+    assert_eq!(Ok(()), dec.get_byte(0x00, &mut v));
+    assert!(dec.is_idle());
+    assert_eq!(vec![0x11u8, 0x22, 0x33, 0x44], v);
+}
@@ -8,26 +8,48 @@
 
 use cobs::{Cobs, CobsError};
 use constcat::concat;
+use flate2::read::GzDecoder;
 use itm::*;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn, LevelFilter};
-use oflow::{OFlow, OFlowError};
+use oflow::{OFlow, OFlowError, OFlowFrame};
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{ErrorKind, Read};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
 use std::mem;
-use std::net::TcpStream;
-use std::path::Path;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 #[path = "test_lib.rs"]
 mod test_lib;
 
 /// Prefix for an address offering oflow
 pub const OFLOW_PREFIX: &str = "oflow";
+/// Prefix for an address offering oflow with length prefixed framing
+pub const LPOFLOW_PREFIX: &str = "lpoflow";
 /// Prefix for an address offering itm
 pub const ITM_PREFIX: &str = "itm";
+/// Prefix for an address offering either itm or oflow, found from what arrives
+pub const AUTO_PREFIX: &str = "auto";
+/// Prefix for an address to listen on for a connection offering oflow
+pub const LISTEN_PREFIX: &str = "listen";
+/// Prefix for a serial port offering itm (e.g. SWO through a UART), with the `serial` feature
+///
+/// The address is the port, optionally followed by `@` and the baud rate, e.g.
+/// `serial:///dev/ttyACM0@2000000`. Without a baud rate [`DEFAULT_BAUD`] is used.
+pub const SERIAL_PREFIX: &str = "serial";
+/// Baud rate of a serial port when the address doesn't give one
+pub const DEFAULT_BAUD: u32 = 115200;
 /// Prefix for the address of a file
 pub const FILE_PREFIX: &str = "file";
+/// Prefix for the address of a file that is memory mapped rather than read
+///
+/// The mapping assumes nothing else changes the file while it is being collected from, so
+/// this is for finished captures, not ones still being written.
+#[cfg(feature = "memmap2")]
+pub const MMAP_PREFIX: &str = "file+mmap";
 /// Separator for parts of a url
 pub const URL_SEPARATOR: &str = "://";
 /// Default connection address for when one isn't specified
@@ -35,17 +57,300 @@ pub const DEFAULT_CONNECT_ADDR: &str = "localhost";
 /// Default port for when one isn't specified
 pub const DEFAULT_PORT: &str = "3402";
 const PORT_SEP: &str = ":";
+/// Magic number at the start of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Extension for a gzip file
+const GZIP_EXTENSION: &str = "gz";
+/// Most bytes looked at to tell the framing of an `auto://` source before assuming ITM
+const SNIFF_LEN: usize = 2 * cobs::MAX_ENC_PACKET_LEN;
+/// Good OFLOW frames in a row that show an `auto://` source is carrying COBS
+const SNIFF_FRAMES: usize = 2;
+/// Time to wait before looking for more data in a followed file
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+/// Time to wait for a network source to respond when probing for it
+#[cfg(feature = "serial")]
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Longest a read from a serial port waits for data, so a quiet port is seen as idle
+#[cfg(feature = "serial")]
+const SERIAL_TIMEOUT: Duration = Duration::from_millis(100);
 
 /// Trait any frame handler is required to implement
 pub trait FrameHandler {
     /// A frame to be processed. Return true if everything is OK, false to reset the link
     fn process(&mut self, i: ITMFrame) -> bool;
 
+    /// A frame to be processed, with the host time it was decoded at
+    ///
+    /// Called instead of `process` when the collector is recording arrival times, see
+    /// [`Collect::set_arrival_time`]. Handlers that have no use for the time can leave this
+    /// as it is, and it goes to `process`.
+    fn process_at(&mut self, i: ITMFrame, _at: SystemTime) -> bool {
+        self.process(i)
+    }
+
     /// Indication of current state. Return true if everything is OK, false to reset the link
     fn state_ind(&self, e: &CollectError);
+
+    /// Called by [`Collect::collect_data`] once the frames from each read of the source have
+    /// been processed
+    ///
+    /// This is the place to flush output, so it's seen even if the source then goes quiet
+    /// with the next read blocked. Does nothing unless the handler needs it to.
+    fn read_done(&mut self) {}
+}
+
+/// A boxed handler is a handler, so handlers chosen at run time can be used as
+/// `Box<dyn FrameHandler>`, at the cost of dynamic dispatch for every frame
+impl<H: FrameHandler + ?Sized> FrameHandler for Box<H> {
+    fn process(&mut self, i: ITMFrame) -> bool {
+        (**self).process(i)
+    }
+
+    fn process_at(&mut self, i: ITMFrame, at: SystemTime) -> bool {
+        (**self).process_at(i, at)
+    }
+
+    fn state_ind(&self, e: &CollectError) {
+        (**self).state_ind(e)
+    }
+
+    fn read_done(&mut self) {
+        (**self).read_done()
+    }
+}
+
+/// Iterate over the OFLOW frames carried in COBS packets read from `r`
+///
+/// OFLOW has no framing of its own, so this runs the whole COBS+OFLOW stack over the
+/// reader, giving a one-liner to pull frames from a socket or file. Frames for every
+/// stream are returned. Iteration ends cleanly at the end of the source if it falls on a
+/// packet boundary.
+///
+/// # Errors
+/// Bad COBS or OFLOW packets are returned as errors and iteration carries on with the
+/// next packet. An IO error, or the source ending part way through a packet, is returned
+/// and then ends the iteration.
+///
+/// # Example
+/// ```
+/// let frame = oflow::OFlow::new().encode_to_vec(1, vec![0x41, 0x42]).unwrap();
+/// let input = cobs::Cobs::new().cobs_encode_into_vec(&[&frame[..]]).unwrap();
+/// let frames: Vec<_> = collector::oflow_frames_from(std::io::Cursor::new(input))
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(frames[0].content(), [0x41, 0x42]);
+/// ```
+///
+pub fn oflow_frames_from<R: Read>(
+    mut r: R,
+) -> impl Iterator<Item = Result<OFlowFrame, CollectError>> {
+    let mut cobs = Cobs::new();
+    cobs.set_reject_empty(true);
+    let mut oflow = OFlow::new();
+    let mut done = false;
+    std::iter::from_fn(move || {
+        while !done {
+            let mut packet = Vec::with_capacity(cobs::MAX_PACKET_LEN);
+            match cobs.read_frame(&mut r, &mut packet) {
+                Ok(()) => return Some(oflow.decode(packet).map_err(CollectError::from)),
+                Err(CobsError::ShortData) if packet.is_empty() => done = true,
+                Err(CobsError::ZeroLength) => (),
+                Err(e @ (CobsError::ShortData | CobsError::Io(_))) => {
+                    done = true;
+                    return Some(Err(e.into()));
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        None
+    })
+}
+
+/// Decode ITM frames from a capture held in memory, calling `cb` with each
+///
+/// The batch equivalent of [`Collect::collect_data`] for data that is already to hand. The
+/// buffer holds OFLOW frames in COBS packets, and the frames carrying ITM for stream `tag`
+/// are decoded, starting straight away if `itm_sync` is set, otherwise from the first ITM
+/// sync. Buffers are reused from one packet to the next, so nothing is allocated per frame.
+/// Bad packets are skipped.
+///
+/// # Example
+/// ```
+/// let frame = oflow::OFlow::new().encode_to_vec(1, vec![0x09, 0x41]).unwrap();
+/// let input = cobs::Cobs::new().cobs_encode_into_vec(&[&frame[..]]).unwrap();
+/// let mut frames = Vec::new();
+/// collector::decode_buffer(&input, 1, true, |f| frames.push(f));
+/// assert_eq!(1, frames.len());
+/// ```
+///
+pub fn decode_buffer(buf: &[u8], tag: u8, itm_sync: bool, mut cb: impl FnMut(ITMFrame)) {
+    let mut cobs = Cobs::new();
+    cobs.set_reject_empty(true);
+    let mut oflow = OFlow::new();
+    let mut itm = ITMDecoder::new(itm_sync);
+    cobs.for_each_frame(buf.iter(), |packet| match oflow.decode_slice(packet) {
+        Ok((stream, content)) if stream == tag => {
+            let mut i = content.iter();
+            while let Ok(f) = itm.get_frame(&mut i) {
+                cb(f);
+            }
+        }
+        Ok(_) => trace!("Stream not for us, dropped"),
+        Err(x) => debug!("Error returned by OFLOW decode: {:?}", x),
+    });
+}
+
+/// Fan out frames and state indications to several handlers
+///
+/// Every wrapped handler sees every frame, in the order they were added.
+///
+/// # Example
+///
+/// ```
+/// use collector::*;
+/// use itm::ITMFrame;
+///
+/// struct Process;
+/// impl FrameHandler for Process {
+///     fn process(&mut self, _i: ITMFrame) -> bool {
+///         true
+///     }
+///     fn state_ind(&self, _e: &CollectError) {}
+/// }
+///
+/// let (mut a, mut b) = (Process, Process);
+/// let mut both = MultiHandler::new().with(&mut a).with(&mut b);
+/// assert!(both.process(ITMFrame::Sync { count: 0 }));
+/// ```
+///
+#[derive(Default)]
+pub struct MultiHandler<'a> {
+    handlers: Vec<&'a mut dyn FrameHandler>,
+}
+
+impl<'a> MultiHandler<'a> {
+    /// Create an empty handler set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a handler to the set
+    pub fn with(mut self, h: &'a mut dyn FrameHandler) -> Self {
+        self.handlers.push(h);
+        self
+    }
+
+    /// Add a handler to an existing set
+    pub fn push(&mut self, h: &'a mut dyn FrameHandler) {
+        self.handlers.push(h);
+    }
+}
+
+impl FrameHandler for MultiHandler<'_> {
+    /// Pass the frame to every handler, false if any of them returned false
+    fn process(&mut self, i: ITMFrame) -> bool {
+        let mut ok = true;
+        for h in self.handlers.iter_mut() {
+            ok &= h.process(i.clone());
+        }
+        ok
+    }
+
+    /// Pass the frame and its time to every handler, false if any of them returned false
+    fn process_at(&mut self, i: ITMFrame, at: SystemTime) -> bool {
+        let mut ok = true;
+        for h in self.handlers.iter_mut() {
+            ok &= h.process_at(i.clone(), at);
+        }
+        ok
+    }
+
+    fn state_ind(&self, e: &CollectError) {
+        for h in self.handlers.iter() {
+            h.state_ind(e);
+        }
+    }
+
+    fn read_done(&mut self) {
+        for h in self.handlers.iter_mut() {
+            h.read_done();
+        }
+    }
+}
+
+/// Named counters that can be scraped uniformly from every layer of the stack
+///
+/// Names are prefixed with the layer they come from, so a set gathered from several layers
+/// (e.g. by [`Collect::all_stats()`]) holds no duplicates.
+///
+/// # Example
+///
+/// ```
+/// use collector::*;
+/// use cobs::Cobs;
+///
+/// for (name, value) in Cobs::new().snapshot() {
+///     println!("{} {}", name, value);
+/// }
+/// ```
+///
+pub trait Stats {
+    /// Current value of each counter maintained by this layer
+    fn snapshot(&self) -> Vec<(&'static str, u64)>;
+}
+
+impl Stats for Cobs {
+    fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        let s = self.stats();
+        vec![
+            ("cobs_inbytes", s.inbytes),
+            ("cobs_goodbytes", s.goodbytes),
+            ("cobs_badbytes", s.badbytes),
+            ("cobs_packets", s.packets),
+            ("cobs_toolong", s.toolong),
+            ("cobs_corrupt_frames", s.corrupt_frames),
+        ]
+    }
+}
+
+impl Stats for OFlow {
+    fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        let s = self.stats();
+        vec![
+            ("oflow_inbytestotal", s.inbytestotal),
+            ("oflow_inpackets", s.inpackets),
+            ("oflow_badchecksum", s.badchecksum),
+            ("oflow_tooshort", s.tooshort),
+            ("oflow_toolong", s.toolong),
+        ]
+    }
+}
+
+impl Stats for ITMDecoder {
+    fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        let s = self.stats();
+        vec![
+            ("itm_inbytestotal", s.inbytestotal),
+            ("itm_inpackets", s.inpackets),
+            ("itm_tpiusync", s.tpiusync),
+            ("itm_itmsync", s.itmsync),
+            ("itm_consecutive_syncs", s.consecutive_syncs),
+            ("itm_instrupkts", s.instrupkts),
+            ("itm_datatrace", s.datatrace),
+            ("itm_exceptions", s.exceptions),
+            ("itm_pcsamples", s.pcsamples),
+            ("itm_overflow", s.overflow),
+            ("itm_ts", s.ts),
+            ("itm_ts_overflow", s.ts_overflow),
+            ("itm_noise", s.noise),
+        ]
+    }
 }
 
 /// Errors from use of this crate
+///
+/// This is also the combined error for the whole stack (re-exported as `collector::Error`),
+/// with conversions from the COBS, OFLOW, ITM and IO errors, so `?` works across all of them.
 #[derive(Debug, thiserror::Error)]
 pub enum CollectError {
     /// No error
@@ -74,9 +379,131 @@ pub enum CollectError {
     ITMError(#[from] ITMError),
 }
 
-trait ReadWrite: std::io::Read + std::io::Write {}
-impl ReadWrite for TcpStream {}
-impl ReadWrite for File {}
+impl CollectError {
+    /// Indicate if the error is transient, so the source is worth retrying
+    ///
+    /// A lost connection or timeout may well be fixed by reconnecting (after a suitable
+    /// back off). Anything else (e.g. a source that doesn't exist, or no permission to use it)
+    /// will not be fixed by trying again.
+    ///
+    /// # Example
+    /// ```
+    /// use collector::CollectError;
+    /// assert!(CollectError::Reset.is_retryable());
+    /// assert!(!CollectError::NoSource.is_retryable());
+    /// ```
+    ///
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CollectError::Reset => true,
+            CollectError::IoError(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::NotConnected
+                    | ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Kinds of source that data can be collected from
+#[cfg(feature = "serial")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceKind {
+    /// A serial port
+    Serial,
+    /// A network server
+    Network,
+}
+
+/// Description of a source that is available for collection
+#[cfg(feature = "serial")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceInfo {
+    /// Path or address of the source
+    pub path: String,
+    /// What kind of source this is
+    pub kind: SourceKind,
+    /// Human readable description of the source
+    pub description: String,
+}
+
+/// How frames are carried by the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// OFLOW frames in COBS packets
+    Cobs,
+    /// OFLOW frames with a length prefix
+    LengthPrefixed,
+    /// Raw ITM
+    Itm,
+    /// Not known yet, one of the others is chosen from what arrives
+    Auto,
+}
+
+// Looks at the start of an `auto://` source to decide its framing, a read at a time
+struct Sniffer {
+    cobs: Cobs,   // COBS decode of everything seen so far
+    oflow: OFlow, // Checks each COBS packet is a good OFLOW frame
+    run: usize,   // Good OFLOW frames in a row
+}
+
+impl Sniffer {
+    fn new() -> Self {
+        Sniffer {
+            cobs: Cobs::new(),
+            oflow: OFlow::new(),
+            run: 0,
+        }
+    }
+
+    // Decide how the source is framed, if it's clear yet, from `held` (everything seen so far)
+    // of which the last `new` bytes haven't been looked at before. See new_collector.
+    fn sniff(&mut self, held: &[u8], new: usize) -> Option<Framing> {
+        let start = held.len() - new;
+        let from = start.saturating_sub(ITM_SYNC_SEQUENCE.len() - 1);
+        if held[from..]
+            .windows(ITM_SYNC_SEQUENCE.len())
+            .any(|w| w == ITM_SYNC_SEQUENCE)
+        {
+            return Some(Framing::Itm);
+        }
+
+        /* The first packet may be joined part way through, so look for good ones in a row */
+        let (oflow, run) = (&mut self.oflow, &mut self.run);
+        self.cobs.for_each_frame(held[start..].iter(), |f| {
+            if *run < SNIFF_FRAMES {
+                *run = if oflow.decode_slice(f).is_ok() {
+                    *run + 1
+                } else {
+                    0
+                };
+            }
+        });
+        (self.run >= SNIFF_FRAMES).then_some(Framing::Cobs)
+    }
+}
+
+/// Callback for the raw content of OFLOW frames, see [`Collect::set_frame_tap`]
+pub type FrameTap = Box<dyn FnMut(&[u8])>;
+
+/// Callback for a source that has gone quiet, see [`Collect::set_idle_hook`]
+pub type IdleHook = Box<dyn FnMut(Duration)>;
+
+// Device and inode of a file
+type FileId = (u64, u64);
+
+// Watch for a source going quiet
+struct IdleWatch {
+    threshold: Duration, // How long without a frame counts as idle
+    hook: IdleHook,      // Called once each time the source goes idle
+    reported: bool,      // Has this idle period been reported?
+}
 
 /// The collection object
 pub struct Collect {
@@ -84,8 +511,25 @@ pub struct Collect {
     cobs_decoder: Cobs,
     oflow_decoder: OFlow,
     itm_decoder: ITMDecoder,
-    is_itm: bool,
-    stream: Box<dyn ReadWrite>,
+    itm_sync: bool,
+    framing: Framing,
+    sniffer: Sniffer,
+    file: Option<PathBuf>,
+    file_id: Option<FileId>,
+    follow: Option<u64>,
+    ppacket: Vec<u8>,
+    replay_rate: Option<u64>,
+    read_buffer_size: usize,
+    frame_tap: Option<FrameTap>,
+    ts_stream: Option<u8>,
+    last_frame: Instant,
+    clock: fn() -> Instant,
+    idle: Option<IdleWatch>,
+    arrival: Option<(SystemTime, Instant)>,
+    read_at: Option<SystemTime>,
+    #[cfg(feature = "memmap2")]
+    mapped: Option<memmap2::Mmap>,
+    stream: Box<dyn Read>,
 }
 
 impl Collect {
@@ -96,7 +540,8 @@ impl Collect {
     ///   Return a file url if a file is specified.
     ///   Else;
     ///      Use the address if specified, otherwise default address
-    ///      If no port was specified in the address then add the default port
+    ///      If no port was specified in the address then add the default port (unless it's a
+    ///      serial port, where the address is the port itself)
     ///      If the protocol was specified explicitly then add that
     ///      Else;
     ///         If an address was provided then add ITM protocol, otherwise add OFLOW.
@@ -106,8 +551,8 @@ impl Collect {
     /// ```
     /// use collector::*;
     /// println!("default URL is {}",Collect::calculate_url(&None,&None,&None));
-    /// println!("File URL is {}",Collect::calculate_url(&Some("fileexample"),&None,&None));
-    /// println!("COBS URL is {}",Collect::calculate_url(&None,&Some("example"),&Some(String::new("cobs"))));
+    /// println!("File URL is {}",Collect::calculate_url(&Some("fileexample".to_string()),&None,&None));
+    /// println!("COBS URL is {}",Collect::calculate_url(&None,&Some("example".to_string()),&Some("cobs".to_string())));
     /// ```
     ///
     pub fn calculate_url(
@@ -142,7 +587,7 @@ impl Collect {
                     .to_string();
 
                 /* Add a port number if we need one */
-                if !addr.contains(PORT_SEP) {
+                if !addr.contains(PORT_SEP) && prot != SERIAL_PREFIX {
                     addr = addr + PORT_SEP + DEFAULT_PORT;
                 };
 
@@ -152,9 +597,91 @@ impl Collect {
         }
     }
 
+    // -------------------------------------------------------------------------------------
+    /// Enumerate the sources that are available for collection
+    ///
+    /// Returns the serial ports present on the host, and the default network address if a
+    /// server is found to be listening there. The path of each is its url, ready to be passed
+    /// to [`Collect::new_collector`].
+    ///
+    /// # Example
+    /// ```
+    /// use collector::*;
+    /// for s in Collect::list_sources() {
+    ///     println!("{:?} {} ({})", s.kind, s.path, s.description);
+    /// }
+    /// ```
+    ///
+    #[cfg(feature = "serial")]
+    pub fn list_sources() -> Vec<SourceInfo> {
+        let mut sources: Vec<SourceInfo> = match serialport::available_ports() {
+            Ok(ports) => ports
+                .into_iter()
+                .map(|p| SourceInfo {
+                    description: match p.port_type {
+                        serialport::SerialPortType::UsbPort(u) => format!(
+                            "USB {:04x}:{:04x} {}",
+                            u.vid,
+                            u.pid,
+                            u.product.unwrap_or_default()
+                        ),
+                        serialport::SerialPortType::PciPort => "PCI serial port".to_string(),
+                        serialport::SerialPortType::BluetoothPort => {
+                            "Bluetooth serial port".to_string()
+                        }
+                        serialport::SerialPortType::Unknown => "Serial port".to_string(),
+                    },
+                    path: concat!(SERIAL_PREFIX, URL_SEPARATOR).to_string() + &p.port_name,
+                    kind: SourceKind::Serial,
+                })
+                .collect(),
+            Err(e) => {
+                debug!("Failed to enumerate serial ports:{:?}", e);
+                Vec::new()
+            }
+        };
+
+        /* Check if there is anything listening at the default address too */
+        let addr = concat!(DEFAULT_CONNECT_ADDR, PORT_SEP, DEFAULT_PORT);
+        if let Ok(mut a) = std::net::ToSocketAddrs::to_socket_addrs(addr) {
+            if a.any(|x| TcpStream::connect_timeout(&x, PROBE_TIMEOUT).is_ok()) {
+                sources.push(SourceInfo {
+                    path: Collect::calculate_url(&None, &None, &None),
+                    kind: SourceKind::Network,
+                    description: "Orbuculum server".to_string(),
+                });
+            }
+        }
+        sources
+    }
+
     // -------------------------------------------------------------------------------------
     /// Create new instance which will (attempt to) connect to specified address
     ///
+    /// A `file://` source that is gzip compressed (a `.gz` extension, or the gzip magic number
+    /// at the start of the file) is decompressed as it is read.
+    ///
+    /// With the `serial` feature a `serial://` source reads itm from a serial port, see
+    /// [`SERIAL_PREFIX`]. Reads time out when the port is quiet, so it shows as idle.
+    ///
+    /// With the `memmap2` feature a `file+mmap://` source maps the (uncompressed) file into
+    /// memory and decodes it where it lies, so there are no reads and nothing is copied into a
+    /// read buffer. A mapped file can't be followed or paced with [`Collect::set_replay_rate`].
+    ///
+    /// An `auto://` source is for servers that don't say what they send. It looks at what
+    /// arrives first to decide. An ITM sync sequence means raw ITM, since five zeros in a row
+    /// are never seen in a COBS flow. Two COBS packets in a row that are each a good OFLOW frame
+    /// mean OFLOW. The bytes looked at are then decoded as that. If neither is seen in the first
+    /// 16K or so it's taken to be ITM. This can go wrong; ITM without a sync at its start can
+    /// (rarely, with a 1 in 65536 chance for each pair of candidate packets) look like OFLOW,
+    /// and OFLOW carried on a non-zero COBS sentinel, or with a checksum kind other than the
+    /// default, is never recognised. Once decided, the framing doesn't change.
+    ///
+    /// A `listen://` source binds to the address and waits for the trace producer to connect,
+    /// for when it is the connecting party. It carries oflow. Only one connection is accepted,
+    /// the listener is closed as soon as it arrives, and when that connection ends so does the
+    /// source. Create a new collector to wait for the next connection.
+    ///
     /// # Example
     ///
     /// ```
@@ -167,15 +694,337 @@ impl Collect {
             "Collector created for address:{}, sync state:{} and tag:{}",
             addr, itm_sync, tag
         );
-        let c = Collect::do_open(addr)?;
-        Ok(Collect {
-            cobs_decoder: Cobs::new(),
+        #[cfg(feature = "memmap2")]
+        if let Some(map_path) = addr.strip_prefix(concat!(MMAP_PREFIX, URL_SEPARATOR)) {
+            let mut c =
+                Collect::with_stream(Framing::Cobs, Box::new(std::io::empty()), itm_sync, tag);
+            c.mapped = Some(Collect::map_file(Path::new(map_path))?);
+            return Ok(c);
+        }
+        if let Some(file_path) = addr.strip_prefix(concat!(FILE_PREFIX, URL_SEPARATOR)) {
+            let path = PathBuf::from(file_path);
+            let (stream, id) = Collect::open_file(&path)?;
+            let mut c = Collect::with_stream(Framing::Cobs, stream, itm_sync, tag);
+            c.file = Some(path);
+            c.file_id = id;
+            return Ok(c);
+        }
+        let (framing, stream) = Collect::do_open(addr)?;
+        Ok(Collect::with_stream(framing, stream, itm_sync, tag))
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Create new instance which will collect from an already open reader
+    ///
+    /// No URL is involved, so any `Read` can act as the source; a mock, a decompressor or a
+    /// transport this crate doesn't know about. When `is_itm` is set the reader carries raw
+    /// ITM, otherwise it carries oflow in COBS packets. The source is not treated as a file,
+    /// so [`Collect::set_replay_rate`] has no effect on it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use collector::*;
+    /// let mut collect_data = Collect::from_reader(Box::new(std::io::empty()), false, 1, true);
+    /// ```
+    ///
+    pub fn from_reader(reader: Box<dyn Read>, is_itm: bool, tag: u8, itm_sync: bool) -> Self {
+        info!(
+            "Collector created for reader, itm:{}, sync state:{} and tag:{}",
+            is_itm, itm_sync, tag
+        );
+        let framing = if is_itm { Framing::Itm } else { Framing::Cobs };
+        Collect::with_stream(framing, reader, itm_sync, tag)
+    }
+
+    fn with_stream(framing: Framing, stream: Box<dyn Read>, itm_sync: bool, tag: u8) -> Self {
+        /* Empty COBS packets can't carry an OFLOW frame, so drop them before they get that far */
+        let mut cobs_decoder = Cobs::new();
+        cobs_decoder.set_reject_empty(true);
+        Collect {
+            cobs_decoder,
             oflow_decoder: OFlow::new(),
             itm_decoder: ITMDecoder::new(itm_sync),
             stream_number: tag,
-            is_itm: c.0,
-            stream: c.1,
-        })
+            itm_sync,
+            framing,
+            sniffer: Sniffer::new(),
+            file: None,
+            file_id: None,
+            follow: None,
+            ppacket: Vec::with_capacity(cobs::MAX_PACKET_LEN),
+            replay_rate: None,
+            read_buffer_size: cobs::MAX_ENC_PACKET_LEN,
+            frame_tap: None,
+            ts_stream: None,
+            last_frame: Instant::now(),
+            clock: Instant::now,
+            idle: None,
+            arrival: None,
+            read_at: None,
+            #[cfg(feature = "memmap2")]
+            mapped: None,
+            stream,
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Set the rate at which a file source is replayed
+    ///
+    /// By default (`None`) a file is replayed as fast as it can be read. When a rate in bytes
+    /// per second is set, reads are paced to approximate a live link of that speed. This has
+    /// no effect on network sources, which are paced by the link itself.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("file://capture.bin", true, 1).unwrap();
+    /// collect_data.set_replay_rate(Some(115200 / 10));
+    /// ```
+    ///
+    pub fn set_replay_rate(&mut self, bytes_per_sec: Option<u64>) {
+        self.replay_rate = bytes_per_sec.filter(|r| *r != 0);
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Keep reading a file source as it grows, like `tail -f`
+    ///
+    /// By default the end of a file ends the collection. When following, reaching the end
+    /// waits for more to be written to the file by whoever is capturing it. The end is only
+    /// real once the file has been removed, replaced by another file (e.g. by log rotation),
+    /// or truncated so it is shorter than what has already been read, at which point
+    /// collection ends as it would have done. Replacement is spotted by device and inode, so
+    /// only on Unix.
+    /// This has no effect on other sources, or on compressed files which can't be read
+    /// until they are complete.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("file://capture.bin", true, 1).unwrap();
+    /// collect_data.set_follow(true);
+    /// ```
+    ///
+    pub fn set_follow(&mut self, on: bool) {
+        let followable = self.file.as_ref().is_some_and(|p| {
+            File::open(p).is_ok_and(|mut f| Collect::is_gzip(p, &mut f).is_ok_and(|gz| !gz))
+        });
+        if on && !followable {
+            warn!("Only uncompressed files can be followed");
+        }
+        self.follow = (on && followable).then_some(self.follow.unwrap_or(0));
+    }
+
+    // Reached the end of a followed file, so wait for more if it's still being written
+    fn follow_wait(&self) -> bool {
+        let (Some(read), Some(path)) = (self.follow, self.file.as_ref()) else {
+            return false;
+        };
+        match std::fs::metadata(path) {
+            Ok(m) if m.len() >= read && Collect::file_id(&m) == self.file_id => {
+                thread::sleep(FOLLOW_INTERVAL);
+                true
+            }
+            _ => {
+                info!("Followed file has been removed or replaced");
+                false
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Set the size of the buffer used for reads from the source
+    ///
+    /// By default this is [`cobs::MAX_ENC_PACKET_LEN`], which is enough to hold one complete
+    /// frame. A larger buffer allows more frames per read, reducing the number of reads needed
+    /// on high throughput links. Frames are assembled across reads, so a smaller buffer still
+    /// decodes correctly, but a frame will take more than one read to arrive. The buffer is
+    /// allocated when [`Collect::collect_data`] starts, and will be at least one byte.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.set_read_buffer_size(1024 * 1024);
+    /// ```
+    ///
+    pub fn set_read_buffer_size(&mut self, size: usize) {
+        self.read_buffer_size = size.max(1);
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Record the host time each frame arrives at
+    ///
+    /// For correlating trace with host side logs. When set, each frame (including host
+    /// timestamps, see [`Collect::set_timestamp_stream`]) goes to the handler's
+    /// [`FrameHandler::process_at`] with the time the bytes carrying it were read, rather than
+    /// to `process`. The time is taken once for each read (or call to
+    /// [`Collect::process_bytes`]), so all the frames completed by it share it. This is a wall
+    /// clock time, but it's taken from a monotonic clock started when this is set, so it never
+    /// goes backwards even if the system clock is changed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.set_arrival_time(true);
+    /// ```
+    ///
+    pub fn set_arrival_time(&mut self, on: bool) {
+        self.arrival = on.then(|| (SystemTime::now(), Instant::now()));
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Set a stream that carries host timestamps alongside the ITM stream
+    ///
+    /// Orbuculum can multiplex host side time into a stream of its own. Each frame on that
+    /// stream carries one or more times, in ns, as little-endian `u64`s. Each is passed to the
+    /// frame handler as an `ITMFrame::HostTimestamp`, in order with the ITM frames, so target
+    /// and host time can be correlated. A frame that isn't a whole number of times is reported
+    /// as `OFlowError::WrongLength`. `None` (the default) stops timestamp handling. This has
+    /// no effect on ITM sources, which have no streams.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.set_timestamp_stream(Some(2));
+    /// ```
+    ///
+    pub fn set_timestamp_stream(&mut self, stream_number: Option<u8>) {
+        self.ts_stream = stream_number;
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Set a tap to be called with the raw content of each OFLOW frame
+    ///
+    /// The tap is called with the bytes of each COBS decoded (or length prefixed) frame before
+    /// they go to the OFLOW decoder, so it sees exactly what is on the wire at the OFLOW layer.
+    /// These bytes have not been validated; they may have a bad checksum or be for another
+    /// stream. The tap is not called for ITM sources, which have no OFLOW layer. `None`
+    /// removes the tap.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.set_frame_tap(Some(Box::new(|f: &[u8]| println!("{:02x?}", f))));
+    /// ```
+    ///
+    pub fn set_frame_tap(&mut self, tap: Option<FrameTap>) {
+        self.frame_tap = tap;
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Return the time since the last ITM frame was decoded
+    ///
+    /// Before the first frame this is the time since the collector was created. A healthy
+    /// but quiet link has a growing idle duration, just as a stalled one does, so it's up to
+    /// the caller to decide what is too long for its target.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// println!("Idle for {:?}", collect_data.idle_duration());
+    /// ```
+    ///
+    pub fn idle_duration(&self) -> Duration {
+        (self.clock)() - self.last_frame
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Set a hook to be called when no ITM frame has been decoded for `threshold`
+    ///
+    /// The hook is called with the idle duration, once for each time the source goes idle;
+    /// it is called again only after frames have arrived and stopped again. Idle time is
+    /// checked by [`Collect::collect_data`] each time a read of the source returns, so a
+    /// source that blocks indefinitely needs a read timeout for the hook to be called while
+    /// it is stalled. `None` removes the hook.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// use std::time::Duration;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.set_idle_hook(
+    ///     Duration::from_secs(10),
+    ///     Some(Box::new(|d: Duration| eprintln!("No trace for {}s", d.as_secs()))),
+    /// );
+    /// ```
+    ///
+    pub fn set_idle_hook(&mut self, threshold: Duration, hook: Option<IdleHook>) {
+        self.idle = hook.map(|hook| IdleWatch {
+            threshold,
+            hook,
+            reported: false,
+        });
+    }
+
+    // Report the source going idle, if anyone is interested
+    fn check_idle(&mut self) {
+        let idle = (self.clock)() - self.last_frame;
+        if let Some(w) = self
+            .idle
+            .as_mut()
+            .filter(|w| !w.reported && idle >= w.threshold)
+        {
+            w.reported = true;
+            (w.hook)(idle);
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Reset the whole decode chain, without touching the connection
+    ///
+    /// For use after a protocol desync is detected. The COBS decoder discards any frame in
+    /// progress, up to the start of the next frame, and the ITM decoder returns to the sync
+    /// state it was created with. The OFLOW decoder holds no partial state, so there is
+    /// nothing to do there. The connection and all statistics are kept.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// collect_data.reset_decoders();
+    /// ```
+    ///
+    pub fn reset_decoders(&mut self) {
+        self.cobs_decoder.resync();
+        self.ppacket.clear();
+        self.itm_decoder.resync(self.itm_sync);
+    }
+
+    // -------------------------------------------------------------------------------------
+    /// Counters from every layer of the stack
+    ///
+    /// The COBS counters only move when the source uses COBS framing, but are always reported
+    /// so the set of names doesn't depend on the source.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// let collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// for (name, value) in collect_data.all_stats() {
+    ///     println!("{} {}", name, value);
+    /// }
+    /// ```
+    ///
+    pub fn all_stats(&self) -> Vec<(&'static str, u64)> {
+        let mut s = self.cobs_decoder.snapshot();
+        s.extend(self.oflow_decoder.snapshot());
+        s.extend(self.itm_decoder.snapshot());
+        s
     }
 
     // -------------------------------------------------------------------------------------
@@ -183,112 +1032,302 @@ impl Collect {
     ///
     /// This routine is called with a pre-created instance.
     ///
+    /// The handler is generic, so calls to it are dispatched statically and can be inlined
+    /// into the decode loop. A handler chosen at run time can be passed as a
+    /// `Box<dyn FrameHandler>`.
+    ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
     /// use collector::*;
-    /// let mut collect_data = Collect::new("oflow://localhost:3402");
-    /// let mut p = Process::new();
+    /// use itm::ITMFrame;
+    ///
+    /// struct Process;
+    /// impl FrameHandler for Process {
+    ///     fn process(&mut self, i: ITMFrame) -> bool {
+    ///         println!("{:?}", i);
+    ///         true
+    ///     }
+    ///     fn state_ind(&self, _e: &CollectError) {}
+    /// }
+    ///
+    /// let mut collect_data = Collect::new_collector("oflow://localhost:3402", true, 1).unwrap();
+    /// let mut p = Process;
     /// loop {
     ///     println!("ERROR::{:?}", collect_data.collect_data(&mut p));
     /// }
+    /// ```
     ///
     pub fn collect_data(&mut self, cb: &mut impl FrameHandler) -> CollectError {
-        let mut tokens = [0u8; cobs::MAX_ENC_PACKET_LEN];
-        let mut ppacket = Vec::with_capacity(cobs::MAX_PACKET_LEN);
+        let mut tokens = vec![0u8; self.read_buffer_size];
         info!("Starting collector");
         cb.state_ind(&self::CollectError::NoError);
+
+        /* If we're pacing the replay then limit each read to about a tenth of a second of data */
+        let pacing = self.replay_rate.filter(|_| self.file.is_some());
+        let readlen = pacing.map_or(tokens.len(), |r| (r as usize / 10).clamp(1, tokens.len()));
+        let start = Instant::now();
+        let mut total: u64 = 0;
+
+        /* A mapped file is decoded in one go, then the source is at its end */
+        #[cfg(feature = "memmap2")]
+        if let Some(m) = self.mapped.take() {
+            self.process_bytes(&m, cb);
+            cb.read_done();
+            cb.state_ind(&CollectError::NoError);
+        }
+
         loop {
-            let iplen = match self.stream.read(&mut tokens) {
+            let iplen = match self.stream.read(&mut tokens[..readlen]) {
                 Ok(n) => n,
                 Err(x) => {
-                    if ErrorKind::Interrupted == x.kind() || ErrorKind::WouldBlock == x.kind() {
+                    /* A read timeout (as on a serial port) just means nothing has arrived yet */
+                    if matches!(
+                        x.kind(),
+                        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) {
+                        self.check_idle();
                         continue;
                     } else {
-                        debug!("Error from rx:{:?}", x);
                         let err = self::CollectError::from(x);
+                        debug!("Error from rx:{:?}, retryable:{}", err, err.is_retryable());
                         cb.state_ind(&err);
-                        /* Errors from the stream collection layer are terminal */
+                        /* Errors from the stream collection layer end this collection, the
+                         * layer above can use is_retryable() to decide if it's worth another go */
                         return err;
                     }
                 }
             };
 
             if 0 == iplen {
+                if self.follow_wait() {
+                    self.check_idle();
+                    continue;
+                }
+                /* Still undecided at the end, so what's held is taken as ITM, as it would be later */
+                if self.framing == Framing::Auto {
+                    self.set_framing(Framing::Itm, cb);
+                    cb.read_done();
+                }
                 debug!("Zero length data rx, Resetting connection");
                 cb.state_ind(&self::CollectError::Reset);
                 /* This is EOF, so return...up to the layer above what happens next */
                 return self::CollectError::Reset;
             }
 
+            if let Some(read) = self.follow.as_mut() {
+                *read += iplen as u64;
+            }
+
+            /* Hold back until the data would have arrived at the requested rate */
+            if let Some(rate) = pacing {
+                total += iplen as u64;
+                if let Some(wait) = Collect::replay_wait(total, rate, start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+
             /* At this point we have _some_ data, but we don't know that it forms into packets */
-            let mut s = tokens[..iplen.min(tokens.len())].iter().peekable();
-
-            if !self.is_itm {
-                /* These are Oflow packets, so they need to go through COBS and OFLOW decoders */
-                debug!("COBS input packet len {}", iplen);
-                while s.peek().is_some() {
-                    match self.cobs_decoder.get_frame(&mut s, &mut ppacket) {
-                        Ok(()) => (),
-                        Err(x) => {
-                            if x == cobs::CobsError::ShortData {
-                                debug!("Short COBS packet");
-                                // It's quite normal to not have a complete end of packet here, so spin and wait for more
-                                break;
-                            } else {
-                                debug!("Error in cobs decode {:?}", x);
-                                ppacket.clear();
-                                cb.state_ind(&self::CollectError::from(x));
-                            }
-                        }
-                    }
+            self.process_bytes(&tokens[..iplen.min(tokens.len())], cb);
+            cb.read_done();
+            self.check_idle();
 
-                    debug!("Complete COBS packet, len {}", ppacket.len());
-                    /* Constructed packet ownership goes to the decoder */
-                    let packet = mem::take(&mut ppacket);
-                    /* ...so we will need a new one for next time around */
-                    ppacket = Vec::with_capacity(cobs::MAX_PACKET_LEN);
-
-                    /* A COBS packet contains a maximum of one OFlow packet */
-                    let oflow_frame = match self.oflow_decoder.decode(packet) {
-                        Ok(r) => r,
-                        Err(x) => {
-                            debug!("Error returned by OFLOW decode: {:?}", x);
-                            cb.state_ind(&self::CollectError::from(x));
-                            continue;
-                        }
-                    };
+            debug!("NoError callback");
+            cb.state_ind(&CollectError::NoError);
+        }
+    }
 
-                    /* Only continue if the stream was for us */
-                    if oflow_frame.get_stream_no() != self.stream_number {
-                        debug!("Stream not for us, dropped");
-                        continue;
-                    }
+    // How much longer to wait before `total` bytes would have arrived at `rate` bytes per
+    // second, given `elapsed` since the start
+    fn replay_wait(total: u64, rate: u64, elapsed: Duration) -> Option<Duration> {
+        Duration::from_nanos(total.saturating_mul(1_000_000_000) / rate).checked_sub(elapsed)
+    }
 
-                    debug!("OFlow frame length {}", oflow_frame.len());
-                    let mut i = oflow_frame.iter().peekable();
+    // -------------------------------------------------------------------------------------
+    /// Process a buffer of bytes from the source, calling callback with any frames that result
+    ///
+    /// The bytes go through exactly the same decode as they would in [`Collect::collect_data`],
+    /// but without needing to read them from the source. Frames that aren't complete by the
+    /// end of the buffer are completed by subsequent calls.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use collector::*;
+    /// use itm::ITMFrame;
+    ///
+    /// struct Process;
+    /// impl FrameHandler for Process {
+    ///     fn process(&mut self, i: ITMFrame) -> bool {
+    ///         println!("{:?}", i);
+    ///         true
+    ///     }
+    ///     fn state_ind(&self, _e: &CollectError) {}
+    /// }
+    ///
+    /// let mut collect_data = Collect::new_collector("file://capture.bin", true, 1).unwrap();
+    /// collect_data.process_bytes(&[0x05, 0x01, 0x01, 0x22, 0xdc, 0x00], &mut Process);
+    /// ```
+    ///
+    pub fn process_bytes(&mut self, bytes: &[u8], cb: &mut impl FrameHandler) {
+        self.read_at = self.arrival.map(|(wall, mono)| wall + mono.elapsed());
+        let mut s = bytes.iter().peekable();
+        let frames = self.itm_decoder.stats().inpackets;
 
-                    match self.itm_process(&mut i, cb) {
-                        Ok(_) => (),
-                        Err(_y) => {
-                            debug!("{:?}", _y);
-                            continue;
-                        }
-                    };
+        match self.framing {
+            Framing::Cobs => {
+                debug!("COBS input packet len {}", bytes.len());
+                self.cobs_process(&mut s, cb);
+            }
+            Framing::LengthPrefixed => {
+                /* Frames can straddle reads, so gather until each is complete */
+                debug!("Length prefixed input packet len {}", bytes.len());
+                self.ppacket.extend_from_slice(bytes);
+                let mut used = 0;
+                while let Some(len) = OFlow::length_prefixed_len(&self.ppacket[used..]) {
+                    if used + len > self.ppacket.len() {
+                        break;
+                    }
+                    let packet = self.ppacket[used + OFlow::LENGTH_PREFIX_LEN..used + len].to_vec();
+                    used += len;
+                    self.oflow_process(packet, cb);
+                }
+                self.ppacket.drain(..used);
+            }
+            Framing::Auto => {
+                self.ppacket.extend_from_slice(bytes);
+                let framing =
+                    self.sniffer
+                        .sniff(&self.ppacket, bytes.len())
+                        .or((self.ppacket.len() >= SNIFF_LEN).then_some(Framing::Itm));
+                if let Some(framing) = framing {
+                    self.set_framing(framing, cb);
                 }
-            } else {
+            }
+            Framing::Itm => {
                 /* If we're in ITM mode just chew on what we've got */
-                debug!("ITM packet len {}", iplen);
-                match self.itm_process(&mut s, cb) {
-                    Ok(_) => (),
-                    Err(_y) => {
-                        debug!("{:?}", _y);
+                debug!("ITM packet len {}", bytes.len());
+                if let Err(_y) = self.itm_process(&mut s, cb) {
+                    debug!("{:?}", _y);
+                }
+            }
+        }
+
+        /* Anything decoded means the source is active again */
+        if self.itm_decoder.stats().inpackets != frames {
+            self.last_frame = (self.clock)();
+            if let Some(w) = self.idle.as_mut() {
+                w.reported = false;
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    // Settle the framing of an auto:// source, and decode the bytes held while deciding
+    fn set_framing(&mut self, framing: Framing, cb: &mut impl FrameHandler) {
+        info!("Source framing is {:?}", framing);
+        self.framing = framing;
+        let held = mem::replace(&mut self.ppacket, Vec::with_capacity(cobs::MAX_PACKET_LEN));
+        self.process_bytes(&held, cb);
+    }
+
+    // -------------------------------------------------------------------------------------
+    // Process COBS packets, each carrying an OFLOW frame, until the data run out
+    fn cobs_process<'a, I>(&mut self, s: &mut std::iter::Peekable<I>, cb: &mut impl FrameHandler)
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        /* These are Oflow packets, so they need to go through COBS and OFLOW decoders */
+        while s.peek().is_some() {
+            match self.cobs_decoder.get_frame(&mut *s, &mut self.ppacket) {
+                Ok(()) => (),
+                Err(x) => {
+                    if x == cobs::CobsError::ShortData {
+                        debug!("Short COBS packet");
+                        // It's quite normal to not have a complete end of packet here, so spin and wait for more
+                        break;
+                    } else if x == cobs::CobsError::ZeroLength {
+                        // Carries no OFLOW frame, so there's nothing to pass on
+                        trace!("Empty COBS packet");
                         continue;
+                    } else {
+                        debug!("Error in cobs decode {:?}", x);
+                        self.ppacket.clear();
+                        cb.state_ind(&self::CollectError::from(x));
                     }
-                };
+                }
+            }
+
+            debug!("Complete COBS packet, len {}", self.ppacket.len());
+            /* Constructed packet ownership goes to the decoder, so we need a new one for next time around */
+            let packet = mem::replace(&mut self.ppacket, Vec::with_capacity(cobs::MAX_PACKET_LEN));
+
+            /* A COBS packet contains a maximum of one OFlow packet */
+            self.oflow_process(packet, cb);
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    // Pass a frame to the handler, with the time it arrived if that's being recorded
+    fn deliver(cb: &mut impl FrameHandler, frame: ITMFrame, at: Option<SystemTime>) -> bool {
+        match at {
+            Some(at) => cb.process_at(frame, at),
+            None => cb.process(frame),
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    // Pass on each host time carried by a frame from the timestamp stream
+    fn host_timestamps(ts: &[u8], cb: &mut impl FrameHandler, at: Option<SystemTime>) {
+        const TS_LEN: usize = mem::size_of::<u64>();
+        if !ts.len().is_multiple_of(TS_LEN) {
+            cb.state_ind(&CollectError::from(OFlowError::WrongLength {
+                got: ts.len(),
+                expected: ts.len().next_multiple_of(TS_LEN),
+            }));
+            return;
+        }
+        for t in ts.chunks_exact(TS_LEN) {
+            let ns = u64::from_le_bytes(t.try_into().unwrap());
+            if !Collect::deliver(cb, ITMFrame::HostTimestamp { ns }, at) {
+                break;
             }
-            debug!("NoError callback");
-            cb.state_ind(&CollectError::NoError);
+        }
+    }
+
+    // -------------------------------------------------------------------------------------
+    // Process a single OFLOW frame, passing on its content if it's for our stream
+    fn oflow_process(&mut self, packet: Vec<u8>, cb: &mut impl FrameHandler) {
+        if let Some(tap) = self.frame_tap.as_mut() {
+            tap(&packet);
+        }
+
+        let oflow_frame = match self.oflow_decoder.decode(packet) {
+            Ok(r) => r,
+            Err(x) => {
+                debug!("Error returned by OFLOW decode: {:?}", x);
+                cb.state_ind(&self::CollectError::from(x));
+                return;
+            }
+        };
+
+        /* Host time rides on a stream of its own */
+        if Some(oflow_frame.get_stream_no()) == self.ts_stream {
+            Collect::host_timestamps(&oflow_frame, cb, self.read_at);
+            return;
+        }
+
+        /* Only continue if the stream was for us */
+        if oflow_frame.get_stream_no() != self.stream_number {
+            debug!("Stream not for us, dropped");
+            return;
+        }
+
+        debug!("OFlow frame length {}", oflow_frame.len());
+        let mut i = oflow_frame.iter().peekable();
+
+        if let Err(_y) = self.itm_process(&mut i, cb) {
+            debug!("{:?}", _y);
         }
     }
 
@@ -305,7 +1344,7 @@ impl Collect {
         loop {
             let itm_frame = self.itm_decoder.get_frame(i)?;
             debug!("Sent frame for processing");
-            if !cb.process(itm_frame) {
+            if !Collect::deliver(cb, itm_frame, self.read_at) {
                 debug!("Frame processor returned false");
                 cb.state_ind(&CollectError::ProcessingFailed);
                 return Err(ITMError::ProcessingError);
@@ -313,22 +1352,94 @@ impl Collect {
         }
     }
 
+    // -------------------------------------------------------------------------------------
+    // Check if a file is gzip compressed, by extension or magic number. File is left at the start.
+    fn is_gzip(path: &Path, f: &mut File) -> Result<bool, CollectError> {
+        if path.extension().is_some_and(|e| e == GZIP_EXTENSION) {
+            return Ok(true);
+        }
+        let mut magic = [0u8; GZIP_MAGIC.len()];
+        let found = f.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+        f.seek(SeekFrom::Start(0))?;
+        Ok(found)
+    }
+
     // -------------------------------------------------------------------------------------
     // Open a new connection and configure it for use
-    // Returns a ReadWrite handle to the connection and an indication if it's ITM or OFLOW
+    // Returns a read handle to the connection and how frames are carried on it
     //
-    fn do_open(addr: &str) -> Result<(bool, Box<dyn ReadWrite>), CollectError> {
+    fn do_open(addr: &str) -> Result<(Framing, Box<dyn Read>), CollectError> {
+        #[cfg(feature = "serial")]
+        if let Some(port) = addr.strip_prefix(concat!(SERIAL_PREFIX, URL_SEPARATOR)) {
+            return Ok((Framing::Itm, Collect::open_serial(port)?));
+        }
         if let Some(oflow_addr) = addr.strip_prefix(concat!(OFLOW_PREFIX, URL_SEPARATOR)) {
             let r = TcpStream::connect(oflow_addr)?;
-            Ok((false, Box::new(r)))
+            Ok((Framing::Cobs, Box::new(r)))
+        } else if let Some(lp_addr) = addr.strip_prefix(concat!(LPOFLOW_PREFIX, URL_SEPARATOR)) {
+            let r = TcpStream::connect(lp_addr)?;
+            Ok((Framing::LengthPrefixed, Box::new(r)))
         } else if let Some(itm_addr) = addr.strip_prefix(concat!(ITM_PREFIX, URL_SEPARATOR)) {
             let r = TcpStream::connect(itm_addr)?;
-            Ok((true, Box::new(r)))
-        } else if let Some(file_path) = addr.strip_prefix(concat!(FILE_PREFIX, URL_SEPARATOR)) {
-            let r = File::open(Path::new(file_path))?;
-            Ok((false, Box::new(r)))
+            Ok((Framing::Itm, Box::new(r)))
+        } else if let Some(auto_addr) = addr.strip_prefix(concat!(AUTO_PREFIX, URL_SEPARATOR)) {
+            let r = TcpStream::connect(auto_addr)?;
+            Ok((Framing::Auto, Box::new(r)))
+        } else if let Some(listen_addr) = addr.strip_prefix(concat!(LISTEN_PREFIX, URL_SEPARATOR)) {
+            let l = TcpListener::bind(listen_addr)?;
+            info!("Waiting for connection on {}", listen_addr);
+            let (r, peer) = l.accept()?;
+            info!("Accepted connection from {}", peer);
+            Ok((Framing::Cobs, Box::new(r)))
         } else {
             Err(CollectError::NoSource)
         }
     }
+
+    // Open a serial port, given as the port with an optional @baud
+    #[cfg(feature = "serial")]
+    fn open_serial(port: &str) -> Result<Box<dyn Read>, CollectError> {
+        let (path, baud) = match port.rsplit_once('@') {
+            Some((path, baud)) => (path, baud.parse().map_err(|_| CollectError::NoSource)?),
+            None => (port, DEFAULT_BAUD),
+        };
+        info!("Opening serial port {} at {} baud", path, baud);
+        let r = serialport::new(path, baud)
+            .timeout(SERIAL_TIMEOUT)
+            .open()
+            .map_err(std::io::Error::from)?;
+        Ok(Box::new(r))
+    }
+
+    // Open a file, returning a read handle to it and its identity
+    fn open_file(path: &Path) -> Result<(Box<dyn Read>, Option<FileId>), CollectError> {
+        let mut r = File::open(path)?;
+        let id = Collect::file_id(&r.metadata()?);
+        if Collect::is_gzip(path, &mut r)? {
+            info!("Decompressing {}", path.display());
+            Ok((Box::new(GzDecoder::new(r)), id))
+        } else {
+            Ok((Box::new(r), id))
+        }
+    }
+
+    // Identity of a file, so one replaced by another at the same path can be spotted
+    #[cfg(unix)]
+    fn file_id(m: &std::fs::Metadata) -> Option<FileId> {
+        use std::os::unix::fs::MetadataExt;
+        Some((m.dev(), m.ino()))
+    }
+
+    #[cfg(not(unix))]
+    fn file_id(_m: &std::fs::Metadata) -> Option<FileId> {
+        None
+    }
+
+    // Map a file into memory, to be decoded where it lies
+    #[cfg(feature = "memmap2")]
+    fn map_file(path: &Path) -> Result<memmap2::Mmap, CollectError> {
+        let f = File::open(path)?;
+        /* Only sound while nobody else changes the file, which is the MMAP_PREFIX contract */
+        Ok(unsafe { memmap2::Mmap::map(&f)? })
+    }
 }
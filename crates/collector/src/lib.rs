@@ -1,2 +1,10 @@
 pub use collector::*;
 mod collector;
+
+/// Errors from each layer of the stack, so they can be matched without depending on each crate
+pub use cobs::CobsError;
+pub use itm::ITMError;
+pub use oflow::OFlowError;
+
+/// Combined error for the whole stack, anything from the lower layers converts into it with `?`
+pub type Error = CollectError;
@@ -1,6 +1,102 @@
 #[cfg(test)]
 use super::*;
 
+/// Frame handler that just counts what it is given
+#[cfg(test)]
+#[derive(Default)]
+struct Counter {
+    frames: Vec<ITMFrame>,
+    reads: usize,
+}
+
+#[cfg(test)]
+impl FrameHandler for Counter {
+    fn process(&mut self, i: ITMFrame) -> bool {
+        self.frames.push(i);
+        true
+    }
+    fn state_ind(&self, _e: &CollectError) {}
+    fn read_done(&mut self) {
+        self.reads += 1;
+    }
+}
+
+/// Wrap an ITM flow in an OFLOW frame for the specified stream, then COBS encode it
+#[cfg(test)]
+fn encode(stream_number: u8, itm: &[u8]) -> Vec<u8> {
+    let frame = OFlow::new()
+        .encode_to_vec(stream_number, itm.to_vec())
+        .unwrap();
+    Cobs::new().cobs_encode_into_vec(&[&frame[..]]).unwrap()
+}
+
+/// A file in the temporary directory, which is removed when this is dropped. Derefs to its url.
+#[cfg(test)]
+struct TempCapture {
+    path: PathBuf,
+    url: String,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempCapture {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempCapture {
+    fn drop(&mut self) {
+        /* The test may already have removed it */
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Create a file in the temporary directory with the specified contents
+#[cfg(test)]
+fn temp_capture(name: &str, contents: &[u8]) -> TempCapture {
+    let path = std::env::temp_dir().join(format!("collector_{}_{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    let url = Collect::calculate_url(&Some(path.to_str().unwrap().to_string()), &None, &None);
+    TempCapture { path, url }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Time added to the test clock by stalls, so idle tests don't depend on real sleeps
+    static STALLED: std::cell::Cell<Duration> = const { std::cell::Cell::new(Duration::ZERO) };
+}
+
+/// Clock for the collector, that moves on by the stalls of a scripted source as well as time
+#[cfg(test)]
+fn test_clock() -> Instant {
+    Instant::now() + STALLED.get()
+}
+
+/// Source that delivers each chunk in turn, with an empty chunk being a read that stalls
+/// for 30ms on [`test_clock`]
+#[cfg(test)]
+struct Scripted(std::collections::VecDeque<Vec<u8>>);
+
+#[cfg(test)]
+impl Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.pop_front() {
+            None => Ok(0),
+            Some(c) if c.is_empty() => {
+                STALLED.set(STALLED.get() + Duration::from_millis(30));
+                Err(ErrorKind::WouldBlock.into())
+            }
+            Some(c) => {
+                buf[..c.len()].copy_from_slice(&c);
+                Ok(c.len())
+            }
+        }
+    }
+}
+
 #[test]
 fn url_test1() {
     let r = Collect::calculate_url(&None, &None, &None);
@@ -46,3 +142,782 @@ fn url_test7() {
     );
     assert_eq!(r, "ttt://address:3402")
 }
+
+#[test]
+fn url_test8() {
+    let r = Collect::calculate_url(
+        &None,
+        &Some("/dev/ttyACM0@2000000".to_string()),
+        &Some(SERIAL_PREFIX.to_string()),
+    );
+    assert_eq!(r, "serial:///dev/ttyACM0@2000000")
+}
+
+#[cfg(feature = "serial")]
+#[test]
+fn serial_open_test() {
+    assert!(matches!(
+        Collect::new_collector("serial:///dev/ttyACM0@fast", true, 1),
+        Err(CollectError::NoSource)
+    ));
+    assert!(matches!(
+        Collect::new_collector("serial:///dev/no_such_port", true, 1),
+        Err(CollectError::IoError(_))
+    ));
+}
+
+#[test]
+fn replay_rate_test() {
+    let url = temp_capture("replay", &[0u8; 2000]);
+
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    c.set_replay_rate(Some(10000));
+    let mut p = Counter::default();
+    let start = Instant::now();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    /* Waits can only overrun, so this holds however loaded the machine is */
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    /* Reads ahead of the rate are held back until they're due, later ones go straight on */
+    let ms = Duration::from_millis;
+    assert_eq!(Some(ms(100)), Collect::replay_wait(1000, 10000, ms(0)));
+    assert_eq!(Some(ms(60)), Collect::replay_wait(1000, 10000, ms(40)));
+    assert_eq!(Some(ms(0)), Collect::replay_wait(1000, 10000, ms(100)));
+    assert_eq!(None, Collect::replay_wait(1000, 10000, ms(150)));
+}
+
+#[test]
+fn read_buffer_size_test() {
+    let mut ip = Vec::new();
+    for n in 0..200u8 {
+        ip.extend(encode(1, &[0x0b, n, 0x00, n, 0xff]));
+    }
+    let url = temp_capture("bufsize", &ip);
+
+    let mut results = Vec::new();
+    for size in [1, 7, 1024 * 1024] {
+        let mut c = Collect::new_collector(&url, true, 1).unwrap();
+        c.set_read_buffer_size(size);
+        let mut p = Counter::default();
+        assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+        assert_eq!(200, p.frames.len());
+        results.push(p.frames);
+    }
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0], results[2]);
+    assert_eq!(
+        ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0xff050005,
+            len: 4,
+            ts: None
+        },
+        results[0][5]
+    );
+}
+
+#[test]
+fn multi_handler_test() {
+    let mut ip = Vec::new();
+    for n in 0..10u8 {
+        ip.extend(encode(1, &[0x09, n]));
+    }
+    let url = temp_capture("multi", &[]);
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+
+    let mut a = Counter::default();
+    let mut b = Counter::default();
+    {
+        let mut both = MultiHandler::new().with(&mut a).with(&mut b);
+        c.process_bytes(&ip, &mut both);
+    }
+    assert_eq!(10, a.frames.len());
+    assert_eq!(a.frames, b.frames);
+}
+
+#[test]
+fn gzip_capture_test() {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut ip = Vec::new();
+    for n in 0..50u8 {
+        ip.extend(encode(1, &[0x0b, n, 0x00, n, 0xff]));
+    }
+    let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&ip).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let mut results = Vec::new();
+    /* Plain, found by magic number, and found by extension */
+    for url in [
+        temp_capture("plain", &ip),
+        temp_capture("magic", &gz),
+        temp_capture("capture.gz", &gz),
+    ] {
+        let mut c = Collect::new_collector(&url, true, 1).unwrap();
+        let mut p = Counter::default();
+        assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+        assert_eq!(50, p.frames.len());
+        results.push(p.frames);
+    }
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0], results[2]);
+}
+
+#[test]
+fn frame_tap_test() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut ip = Vec::new();
+    for n in 0..5u8 {
+        ip.extend(encode(1, &[0x09, n]));
+    }
+    /* Frames for other streams are still seen by the tap */
+    ip.extend(encode(2, &[0x09, 0x55]));
+
+    let url = temp_capture("tap", &[]);
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let seen = Rc::new(RefCell::new(Vec::<Vec<u8>>::new()));
+    let s = seen.clone();
+    c.set_frame_tap(Some(Box::new(move |f: &[u8]| {
+        s.borrow_mut().push(f.to_vec())
+    })));
+
+    let mut p = Counter::default();
+    c.process_bytes(&ip, &mut p);
+    assert_eq!(5, p.frames.len());
+    assert_eq!(6, seen.borrow().len());
+    assert_eq!(
+        OFlow::new().encode_to_vec(2, vec![0x09, 0x55]).unwrap(),
+        seen.borrow()[5]
+    );
+}
+
+#[test]
+fn retryable_test() {
+    for kind in [
+        ErrorKind::ConnectionReset,
+        ErrorKind::BrokenPipe,
+        ErrorKind::TimedOut,
+        ErrorKind::NotConnected,
+    ] {
+        assert!(CollectError::from(std::io::Error::from(kind)).is_retryable());
+    }
+    for kind in [ErrorKind::NotFound, ErrorKind::PermissionDenied] {
+        assert!(!CollectError::from(std::io::Error::from(kind)).is_retryable());
+    }
+    assert!(CollectError::Reset.is_retryable());
+    assert!(!CollectError::NoSource.is_retryable());
+    assert!(!CollectError::from(OFlowError::BadChecksum {
+        expected: 0,
+        got: 1
+    })
+    .is_retryable());
+}
+
+#[test]
+fn reset_decoders_test() {
+    let url = temp_capture("reset", &[]);
+    let clean = encode(1, &[0x09, 0x55]);
+    let expected = ITMFrame::Instrumentation {
+        addr: 1,
+        data: 0x55,
+        len: 1,
+        ts: None,
+    };
+
+    /* A COBS frame broken off part way through */
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let mut p = Counter::default();
+    let broken = encode(1, &[0x09, 0x33]);
+    c.process_bytes(&broken[..2], &mut p);
+    c.reset_decoders();
+    c.process_bytes(&broken[3..], &mut p);
+    c.process_bytes(&clean, &mut p);
+    assert_eq!(vec![expected.clone()], p.frames);
+
+    /* An ITM packet that will never be completed */
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let mut p = Counter::default();
+    c.process_bytes(&encode(1, &[0x0b, 0x01, 0x02]), &mut p);
+    c.reset_decoders();
+    c.process_bytes(&clean, &mut p);
+    assert_eq!(vec![expected], p.frames);
+}
+
+#[test]
+fn combined_error_test() {
+    fn cobs_layer() -> Result<(), crate::Error> {
+        Cobs::new().cobs_encode_into_vec(&[&[]])?;
+        Ok(())
+    }
+    fn oflow_layer() -> Result<(), crate::Error> {
+        OFlow::new().decode(vec![27, 1, 2, 3, 4])?;
+        Ok(())
+    }
+    fn itm_layer() -> Result<(), crate::Error> {
+        ITMDecoder::new(true).get_frame(&mut [0x0b].iter())?;
+        Ok(())
+    }
+    fn io_layer() -> Result<(), crate::Error> {
+        File::open("/nonexistent/capture")?;
+        Ok(())
+    }
+
+    assert!(matches!(
+        cobs_layer(),
+        Err(crate::Error::CobsError(CobsError::ZeroLength))
+    ));
+    assert!(matches!(
+        oflow_layer(),
+        Err(crate::Error::OFlowError(OFlowError::BadChecksum { .. }))
+    ));
+    assert!(matches!(
+        itm_layer(),
+        Err(crate::Error::ITMError(ITMError::ShortData))
+    ));
+    assert!(matches!(io_layer(), Err(crate::Error::IoError(_))));
+}
+
+#[test]
+fn length_prefixed_source_test() {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    let mut ip = Vec::new();
+    let mut oflow = OFlow::new();
+    for n in 0..20u8 {
+        ip.extend(oflow.encode_length_prefixed(1, vec![0x09, n]).unwrap());
+    }
+    ip.extend(oflow.encode_length_prefixed(2, vec![0x09, 0x55]).unwrap());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        /* Dribble it out so frames straddle reads */
+        for chunk in ip.chunks(7) {
+            s.write_all(chunk).unwrap();
+            s.flush().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    let url = Collect::calculate_url(
+        &None,
+        &Some(format!("127.0.0.1:{}", port)),
+        &Some(LPOFLOW_PREFIX.to_string()),
+    );
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    server.join().unwrap();
+
+    assert_eq!(20, p.frames.len());
+    assert_eq!(
+        ITMFrame::Instrumentation {
+            addr: 1,
+            data: 19,
+            len: 1,
+            ts: None
+        },
+        p.frames[19]
+    );
+}
+
+#[test]
+fn stats_names_test() {
+    let names = |s: Vec<(&'static str, u64)>| s.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "cobs_inbytes",
+            "cobs_goodbytes",
+            "cobs_badbytes",
+            "cobs_packets",
+            "cobs_toolong",
+            "cobs_corrupt_frames"
+        ],
+        names(Cobs::new().snapshot())
+    );
+    assert_eq!(
+        vec![
+            "oflow_inbytestotal",
+            "oflow_inpackets",
+            "oflow_badchecksum",
+            "oflow_tooshort",
+            "oflow_toolong"
+        ],
+        names(OFlow::new().snapshot())
+    );
+    assert_eq!(
+        vec![
+            "itm_inbytestotal",
+            "itm_inpackets",
+            "itm_tpiusync",
+            "itm_itmsync",
+            "itm_consecutive_syncs",
+            "itm_instrupkts",
+            "itm_datatrace",
+            "itm_exceptions",
+            "itm_pcsamples",
+            "itm_overflow",
+            "itm_ts",
+            "itm_ts_overflow",
+            "itm_noise"
+        ],
+        names(ITMDecoder::new(true).snapshot())
+    );
+}
+
+#[test]
+fn all_stats_test() {
+    let url = temp_capture("all_stats", &encode(1, &[0x01, 0x41]));
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let mut p = Counter::default();
+    c.collect_data(&mut p);
+    assert_eq!(1, p.frames.len());
+
+    let s = c.all_stats();
+    assert_eq!(6 + 5 + 13, s.len());
+    let get = |name| s.iter().find(|(n, _)| *n == name).unwrap().1;
+    assert_eq!(1, get("cobs_packets"));
+    assert_eq!(1, get("oflow_inpackets"));
+    assert_eq!(1, get("itm_instrupkts"));
+}
+
+#[test]
+fn url_listen_test() {
+    let r = Collect::calculate_url(
+        &None,
+        &Some("0.0.0.0".to_string()),
+        &Some(LISTEN_PREFIX.to_string()),
+    );
+    assert_eq!(r, "listen://0.0.0.0:3402")
+}
+
+#[test]
+fn listen_source_test() {
+    use std::io::Write;
+
+    /* Find a free port, then hand it to the collector to listen on */
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let ip = encode(1, &[0x01, 0x41]);
+    let client = thread::spawn(move || {
+        let mut s = loop {
+            match TcpStream::connect(addr) {
+                Ok(s) => break s,
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        s.write_all(&ip).unwrap();
+    });
+
+    let url = Collect::calculate_url(
+        &None,
+        &Some(addr.to_string()),
+        &Some(LISTEN_PREFIX.to_string()),
+    );
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    client.join().unwrap();
+
+    assert_eq!(
+        vec![ITMFrame::Instrumentation {
+            addr: 0,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }],
+        p.frames
+    );
+}
+
+#[test]
+fn timestamp_stream_test() {
+    let mut ip = Vec::new();
+    ip.extend(encode(1, &[0x09, 0x41]));
+    ip.extend(encode(2, &1_000_000_123u64.to_le_bytes()));
+    ip.extend(encode(1, &[0x09, 0x42]));
+    ip.extend(encode(
+        2,
+        &[
+            2_000_000_000u64.to_le_bytes(),
+            3_000_000_000u64.to_le_bytes(),
+        ]
+        .concat(),
+    ));
+    ip.extend(encode(2, &[0x01, 0x02, 0x03])); // Not a whole timestamp
+
+    let url = temp_capture("timestamps", &[]);
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+
+    /* Without a timestamp stream set they're for someone else */
+    let mut p = Counter::default();
+    c.process_bytes(&ip, &mut p);
+    assert_eq!(2, p.frames.len());
+
+    c.set_timestamp_stream(Some(2));
+    let mut p = Counter::default();
+    c.process_bytes(&ip, &mut p);
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::HostTimestamp { ns: 1_000_000_123 },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x42,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::HostTimestamp { ns: 2_000_000_000 },
+            ITMFrame::HostTimestamp { ns: 3_000_000_000 },
+        ],
+        p.frames
+    );
+}
+
+#[test]
+fn from_reader_test() {
+    use std::io::Cursor;
+    let mut ip = Vec::new();
+    ip.extend(encode(1, &[0x09, 0x41]));
+    ip.extend(encode(2, &[0x09, 0x5a])); // Someone else's stream
+    ip.extend(encode(1, &[0x0a, 0x42, 0x43]));
+
+    let mut c = Collect::from_reader(Box::new(Cursor::new(ip)), false, 1, true);
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x4342,
+                len: 2,
+                ts: None
+            },
+        ],
+        p.frames
+    );
+
+    /* The same content as raw ITM */
+    let mut c = Collect::from_reader(Box::new(Cursor::new(vec![0x09, 0x41])), true, 1, true);
+    let mut p = Counter::default();
+    c.collect_data(&mut p);
+    assert_eq!(
+        vec![ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }],
+        p.frames
+    );
+}
+
+#[test]
+fn idle_hook_test() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let script = [
+        encode(1, &[0x09, 0x41]),
+        vec![],
+        vec![],
+        vec![],
+        encode(1, &[0x09, 0x42]),
+        vec![],
+        vec![],
+        vec![],
+    ];
+    let mut c = Collect::from_reader(Box::new(Scripted(script.into())), false, 1, true);
+    c.clock = test_clock;
+    let idles = Rc::new(RefCell::new(Vec::new()));
+    let i = idles.clone();
+    c.set_idle_hook(
+        Duration::from_millis(50),
+        Some(Box::new(move |d| i.borrow_mut().push(d))),
+    );
+
+    let mut p = Counter::default();
+    c.collect_data(&mut p);
+    assert_eq!(2, p.frames.len());
+
+    /* Reported once for each quiet spell, not on every read while it lasts */
+    assert_eq!(2, idles.borrow().len());
+    assert!(idles
+        .borrow()
+        .iter()
+        .all(|d| *d >= Duration::from_millis(50)));
+    assert!(c.idle_duration() >= Duration::from_millis(90));
+}
+
+#[test]
+fn read_done_test() {
+    /* Told after every read with data, including one that stalls after it */
+    let script = [encode(1, &[0x09, 0x41]), vec![], encode(1, &[0x09, 0x42])];
+    let mut c = Collect::from_reader(Box::new(Scripted(script.into())), false, 1, true);
+    let mut p = Counter::default();
+    let mut h: Box<dyn FrameHandler> = Box::new(MultiHandler::new().with(&mut p));
+    c.collect_data(&mut h);
+    drop(h);
+    assert_eq!(2, p.frames.len());
+    assert_eq!(2, p.reads);
+}
+
+#[test]
+fn boxed_handler_test() {
+    let mut c = Collect::from_reader(Box::new(std::io::empty()), true, 1, true);
+    let mut p = Counter::default();
+    {
+        let mut h: Box<dyn FrameHandler> = Box::new(MultiHandler::new().with(&mut p));
+        c.process_bytes(&[0x09, 0x41, 0x09, 0x42], &mut h);
+    }
+    assert_eq!(2, p.frames.len());
+}
+
+#[test]
+fn oflow_frames_from_test() {
+    /* An empty COBS packet carries nothing, so is skipped */
+    let mut input = vec![0x01, 0x00];
+    input.extend(encode(1, &[0x09, 0x41]));
+    input.extend(encode(2, &[0x42]));
+    /* A packet with a bad checksum is reported, but doesn't stop the iteration */
+    let mut bad = OFlow::new().encode_to_vec(1, vec![0x43]).unwrap();
+    *bad.last_mut().unwrap() ^= 0xff;
+    input.extend(Cobs::new().cobs_encode_into_vec(&[&bad[..]]).unwrap());
+    input.extend(encode(3, &[0x44, 0x45]));
+
+    let frames: Vec<_> = oflow_frames_from(std::io::Cursor::new(input.clone())).collect();
+    assert_eq!(4, frames.len());
+    let f = frames[0].as_ref().unwrap();
+    assert_eq!((1, &[0x09, 0x41][..]), (f.get_stream_no(), f.content()));
+    assert_eq!(2, frames[1].as_ref().unwrap().get_stream_no());
+    assert!(matches!(frames[2], Err(CollectError::OFlowError(_))));
+    assert_eq!([0x44, 0x45], frames[3].as_ref().unwrap().content());
+
+    /* Running out part way through a packet is an error, and the end of the iteration */
+    let frames: Vec<_> =
+        oflow_frames_from(std::io::Cursor::new(&input[..input.len() - 2])).collect();
+    assert_eq!(4, frames.len());
+    assert!(matches!(
+        frames[3],
+        Err(CollectError::CobsError(CobsError::ShortData))
+    ));
+
+    assert_eq!(0, oflow_frames_from(std::io::empty()).count());
+}
+
+#[test]
+fn follow_test() {
+    let url = temp_capture("follow", &encode(1, &[0x09, 0x41]));
+    let path = PathBuf::from(url.strip_prefix("file://").unwrap());
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    c.set_follow(true);
+
+    /* More is written while the collector waits at the end, then the capture goes away */
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let mut f = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            std::io::Write::write_all(&mut f, &encode(1, &[0x09, 0x42])).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            std::fs::remove_file(&path).unwrap();
+        })
+    };
+
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    writer.join().unwrap();
+    assert_eq!(2, p.frames.len());
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_rotated_test() {
+    let url = temp_capture("rotated", &encode(1, &[0x09, 0x41]));
+    let path = PathBuf::from(url.strip_prefix("file://").unwrap());
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    c.set_follow(true);
+
+    /* The capture is rotated to a longer file, which mustn't be mistaken for more of it */
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let next = path.with_extension("next");
+            let mut rotated = encode(1, &[0x09, 0x42]);
+            rotated.extend(encode(1, &[0x09, 0x43]));
+            std::fs::write(&next, rotated).unwrap();
+            std::fs::rename(&next, &path).unwrap();
+        })
+    };
+
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    writer.join().unwrap();
+    assert_eq!(1, p.frames.len());
+}
+
+#[test]
+fn decode_buffer_test() {
+    let mut input = encode(1, &[0x09, 0x41, 0x09]);
+    input.extend(encode(2, &[0x09, 0x5a])); // Another stream
+    input.extend(encode(1, &[0x42, 0x0a, 0x43, 0x44]));
+
+    /* The packet straddling the two frames is completed from the second */
+    let mut frames = Vec::new();
+    decode_buffer(&input, 1, true, |f| frames.push(f));
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x42,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x4443,
+                len: 2,
+                ts: None
+            },
+        ],
+        frames
+    );
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn mmap_test() {
+    let mut input = encode(1, &[0x09, 0x41]);
+    input.extend(encode(1, &[0x09, 0x42]));
+    let capture = temp_capture("mmap", &input);
+    let url = capture.replacen(FILE_PREFIX, MMAP_PREFIX, 1);
+
+    let mut p = Counter::default();
+    let mut c = Collect::new_collector(&url, true, 1).unwrap();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    assert_eq!(2, p.frames.len());
+    assert_eq!(1, p.reads);
+
+    /* The whole file went in one go, so there's nothing left */
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    assert_eq!(2, p.frames.len());
+}
+
+#[test]
+fn auto_framing_test() {
+    let inst = ITMFrame::Instrumentation {
+        addr: 1,
+        data: 0x41,
+        len: 1,
+        ts: None,
+    };
+
+    /* OFLOW in COBS, arriving a byte at a time */
+    let mut input = encode(1, &[0x09, 0x41]);
+    input.extend(encode(1, &[0x09, 0x41]));
+    let mut c = Collect::with_stream(Framing::Auto, Box::new(std::io::empty()), true, 1);
+    let mut p = Counter::default();
+    for b in &input {
+        c.process_bytes(&[*b], &mut p);
+    }
+    assert_eq!(Framing::Cobs, c.framing);
+    assert_eq!(vec![inst.clone(), inst.clone()], p.frames);
+
+    /* Raw ITM, with a sync */
+    let mut input = ITM_SYNC_SEQUENCE.to_vec();
+    input.extend([0x09, 0x41]);
+    let mut c = Collect::with_stream(Framing::Auto, Box::new(std::io::empty()), false, 1);
+    let mut p = Counter::default();
+    c.process_bytes(&input, &mut p);
+    assert_eq!(Framing::Itm, c.framing);
+    assert_eq!(vec![ITMFrame::Sync { count: 1 }, inst.clone()], p.frames);
+
+    /* ...which is still found when it arrives a byte at a time */
+    let mut c = Collect::with_stream(Framing::Auto, Box::new(std::io::empty()), false, 1);
+    let mut p = Counter::default();
+    for b in &input {
+        c.process_bytes(&[*b], &mut p);
+    }
+    assert_eq!(Framing::Itm, c.framing);
+    assert_eq!(vec![ITMFrame::Sync { count: 1 }, inst.clone()], p.frames);
+
+    /* Neither, so it's taken as ITM once enough has been seen */
+    let mut c = Collect::with_stream(Framing::Auto, Box::new(std::io::empty()), true, 1);
+    c.process_bytes(&vec![0x55; SNIFF_LEN], &mut Counter::default());
+    assert_eq!(Framing::Itm, c.framing);
+
+    /* Too short to tell, so what there is gets decoded as ITM at the end */
+    let source = std::io::Cursor::new(vec![0x09, 0x41]);
+    let mut c = Collect::with_stream(Framing::Auto, Box::new(source), true, 1);
+    let mut p = Counter::default();
+    assert!(matches!(c.collect_data(&mut p), CollectError::Reset));
+    assert_eq!(Framing::Itm, c.framing);
+    assert_eq!(vec![inst], p.frames);
+}
+
+/// Handler that keeps the arrival time of each frame
+#[cfg(test)]
+#[derive(Default)]
+struct Arrivals(Vec<SystemTime>);
+
+#[cfg(test)]
+impl FrameHandler for Arrivals {
+    fn process(&mut self, _i: ITMFrame) -> bool {
+        panic!("Frame without its arrival time");
+    }
+    fn process_at(&mut self, _i: ITMFrame, at: SystemTime) -> bool {
+        self.0.push(at);
+        true
+    }
+    fn state_ind(&self, _e: &CollectError) {}
+}
+
+#[test]
+fn arrival_time_test() {
+    let mut c = Collect::from_reader(Box::new(std::io::empty()), false, 1, true);
+    c.set_arrival_time(true);
+    c.set_timestamp_stream(Some(2));
+    let mut p = Arrivals::default();
+    for _ in 0..3 {
+        let mut read = encode(1, &[0x09, 0x41, 0x09, 0x42]);
+        read.extend(encode(2, &1234u64.to_le_bytes()));
+        c.process_bytes(&read, &mut p);
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    /* Host timestamps come with their time too, and everything from one read shares it */
+    assert_eq!(9, p.0.len());
+    assert!(p.0.chunks(3).all(|r| r[0] == r[1] && r[1] == r[2]));
+    assert!(p.0.windows(2).all(|w| w[0] <= w[1]));
+    assert!(p.0[8] > p.0[0]);
+}
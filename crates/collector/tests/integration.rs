@@ -1,42 +1,83 @@
+use cobs::Cobs;
 use collector::*;
 use itm::*;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn, LevelFilter};
-use simple_logger::SimpleLogger;
-use std::thread;
-use std::time::Duration;
+use oflow::OFlow;
+
+/// Wrap an ITM flow in an OFLOW frame for the specified stream, then COBS encode it
+fn encode(stream_number: u8, itm: &[u8]) -> Vec<u8> {
+    let frame = OFlow::new()
+        .encode_to_vec(stream_number, itm.to_vec())
+        .unwrap();
+    Cobs::new().cobs_encode_into_vec(&[&frame[..]]).unwrap()
+}
+
+/// Any empty file will do as a source, since data are fed in directly
+fn make_collector() -> Collect {
+    let path = std::env::temp_dir().join(format!("integration_{}", std::process::id()));
+    std::fs::write(&path, []).unwrap();
+    Collect::new_collector(
+        &Collect::calculate_url(&Some(path.to_str().unwrap().to_string()), &None, &None),
+        true,
+        1,
+    )
+    .unwrap()
+}
 
 #[test]
 fn main() {
-    SimpleLogger::new()
+    let _ = simple_logger::SimpleLogger::new()
         .with_level(LevelFilter::Debug)
-        .init()
-        .expect("Could not start logger");
-    warn!("Orbuculum server must be running in default config before this test");
-    warn!("otherwise you will see no output.");
+        .init();
 
     let mut p = Process::new();
-    loop {
-        let mut collect_data = match Collect::new_collector("oflow://localhost:3402", true, 1) {
-            Ok(x) => x,
-            Err(y) => {
-                error!("{:?}", y);
-                panic!("Failed to open source");
-            }
-        };
-        error!("At main loop:{:?}", collect_data.collect_data(&mut p));
-        thread::sleep(Duration::from_secs(1));
-    }
+    let mut collect_data = make_collector();
+
+    let mut ip = encode(1, &[0x01, 0x22, 0x0a, 0x33, 0x44]);
+    ip.extend(encode(2, &[0x01, 0x55])); // Not our stream
+    ip.extend(encode(1, &[0x09, 0x66]));
+
+    /* Split the data so a frame straddles two calls */
+    let (a, b) = ip.split_at(ip.len() - 3);
+    collect_data.process_bytes(a, &mut p);
+    collect_data.process_bytes(b, &mut p);
+
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 0,
+                data: 0x22,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x4433,
+                len: 2,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x66,
+                len: 1,
+                ts: None
+            },
+        ],
+        p.frames
+    );
 }
 
 ///////////////////////////////////////////////////////////////////////////
 // Process individual packets
 ///////////////////////////////////////////////////////////////////////////
-struct Process {}
+struct Process {
+    frames: Vec<ITMFrame>,
+}
 
 impl Process {
     fn new() -> Self {
-        Process {}
+        Process { frames: Vec::new() }
     }
 }
 
@@ -50,6 +91,7 @@ impl FrameHandler for Process {
 
     fn process(&mut self, i: ITMFrame) -> bool {
         info!("{:?}", i);
+        self.frames.push(i);
         true
     }
 }
@@ -6,9 +6,12 @@
 /// it into individual messages for processing by higher layers.
 ///
 use bitmatch::bitmatch;
+use std::collections::VecDeque;
 use std::default::Default;
 use std::fmt;
 use std::fmt::Debug;
+use std::io::{BufRead, ErrorKind};
+use std::ops::Deref;
 
 #[path = "test_lib.rs"]
 mod test_lib;
@@ -17,6 +20,10 @@ const ITM_SYNCMASK: u64 = 0xFFFFFFFFFFFF;
 const ITM_SYNCPATTERN: u64 = 0x000000000080;
 const TPIU_SYNCMASK: u64 = 0xFFFFFFFF;
 const TPIU_SYNCPATTERN: u64 = 0xFFFFFF7F;
+const TPIU_FRAME_LEN: usize = 16;
+
+/// The canonical ITM sync sequence, as matched by `ITM_SYNCMASK`/`ITM_SYNCPATTERN`
+pub const ITM_SYNC_SEQUENCE: [u8; 6] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
 
 /// Errors from use of this crate
 #[derive(Debug, Clone, Eq, Copy, PartialEq)]
@@ -27,6 +34,10 @@ pub enum ITMError {
     Unimplemented,
     /// Error in the processing of the frame
     ProcessingError,
+    /// Input could not be parsed
+    BadInput,
+    /// Error reading from the source
+    Io(ErrorKind),
 }
 
 impl fmt::Display for ITMError {
@@ -35,6 +46,8 @@ impl fmt::Display for ITMError {
             ITMError::ShortData => write!(f, "Packet is too short"),
             ITMError::Unimplemented => write!(f, "Unimplemented"),
             ITMError::ProcessingError => write!(f, "Processing error"),
+            ITMError::BadInput => write!(f, "Input could not be parsed"),
+            ITMError::Io(k) => write!(f, "IO error: {}", k),
         }
     }
 }
@@ -75,14 +88,23 @@ pub enum ITMFrame {
     /// No content
     Empty,
 
-    /// A valid (local) timestamp
-    Timestamp { ttype: TSType, ts: u64 },
+    /// A valid (local) timestamp, with indication if the local timestamp counter overflowed
+    Timestamp {
+        ttype: TSType,
+        ts: u64,
+        overflow: bool,
+    },
 
     /// A global timestamp, with indication if it has wrapped
     Globaltimestamp { has_wrapped: bool, ts: u64 },
 
-    /// A general instrumentation packet
-    Instrumentation { addr: u8, data: u32, len: u8 },
+    /// A general instrumentation packet, with the local time it follows (only attached if enabled)
+    Instrumentation {
+        addr: u8,
+        data: u32,
+        len: u8,
+        ts: Option<u64>,
+    },
 
     /// An exception, and the event that occured on that exception
     Exception { no: u16, event: ExceptionEvent },
@@ -113,6 +135,9 @@ pub enum ITMFrame {
     /// Extension packet with source and ex value
     Xtn { source: bool, len: u8, ex: u32 },
 
+    /// Stimulus port page register has been set (only reported if enabled)
+    PageSet { page: u8 },
+
     /// Indication that a TPIU sync has been received (this is not a good thing in an ITM flow)
     TPIUSync { count: u64 },
 
@@ -133,7 +158,124 @@ pub enum ITMFrame {
     },
 
     /// PMU overflow indication
-    PMUOverflow { ovf: u8 },
+    ///
+    /// `overflowed[n]` is set if PMU event counter n overflowed, `ovf` is the raw bitfield.
+    PMUOverflow { ovf: u8, overflowed: [bool; 8] },
+
+    /// Host time, in ns, from a separate timestamp stream (never produced by the ITM decoder)
+    HostTimestamp { ns: u64 },
+
+    /// A byte received while waiting for sync (only reported if enabled)
+    Unsynced { byte: u8 },
+}
+
+/// Payload of an instrumentation packet, as the bytes that were on the wire
+///
+/// Dereferences to a slice of the length of the packet, so a 3 byte payload is distinct from a
+/// zero padded 4 byte one.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Payload {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl ITMFrame {
+    /// Return the payload of an instrumentation packet in wire order, `None` for anything else
+    ///
+    /// # Example
+    /// ```
+    /// use itm::ITMFrame;
+    /// let f = ITMFrame::Instrumentation { addr: 1, data: 0x4241, len: 2, ts: None };
+    /// assert_eq!(b"AB", &f.payload().unwrap()[..]);
+    /// ```
+    pub fn payload(&self) -> Option<Payload> {
+        match *self {
+            ITMFrame::Instrumentation { data, len, .. } => Some(Payload {
+                bytes: data.to_le_bytes(),
+                len: len.min(4),
+            }),
+            _ => None,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Kinds of frame that the decoder can return
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FrameMask: u32 {
+        /// Local timestamps
+        const TIMESTAMP = 1 << 0;
+        /// Global timestamps
+        const GLOBAL_TIMESTAMP = 1 << 1;
+        /// Instrumentation (software) packets
+        const INSTRUMENTATION = 1 << 2;
+        /// Exception trace
+        const EXCEPTION = 1 << 3;
+        /// Data trace of all kinds
+        const DATA_TRACE = 1 << 4;
+        /// PC samples and sleep indications
+        const PC_SAMPLE = 1 << 5;
+        /// Extension packets and page register changes
+        const EXTENSION = 1 << 6;
+        /// ITM and TPIU syncs
+        const SYNC = 1 << 7;
+        /// Overflow indications
+        const OVERFLOW = 1 << 8;
+        /// Event counter wraparound
+        const EVENT_COUNTER = 1 << 9;
+        /// PMU overflow indications
+        const PMU_OVERFLOW = 1 << 10;
+        /// Host timestamps
+        const HOST_TIMESTAMP = 1 << 11;
+        /// Bytes received while waiting for sync
+        const UNSYNCED = 1 << 12;
+    }
+}
+
+impl Default for FrameMask {
+    fn default() -> Self {
+        FrameMask::all()
+    }
+}
+
+impl FrameMask {
+    /// Return the kind of the frame
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{FrameMask, ITMFrame};
+    /// assert_eq!(FrameMask::SYNC, FrameMask::of(&ITMFrame::Sync { count: 1 }));
+    /// ```
+    pub fn of(f: &ITMFrame) -> FrameMask {
+        match f {
+            ITMFrame::Empty => FrameMask::empty(),
+            ITMFrame::Timestamp { .. } => FrameMask::TIMESTAMP,
+            ITMFrame::Globaltimestamp { .. } => FrameMask::GLOBAL_TIMESTAMP,
+            ITMFrame::Instrumentation { .. } => FrameMask::INSTRUMENTATION,
+            ITMFrame::Exception { .. } => FrameMask::EXCEPTION,
+            ITMFrame::DataTracePC { .. }
+            | ITMFrame::DataTraceAddr { .. }
+            | ITMFrame::DataTraceValue { .. }
+            | ITMFrame::DataTraceMatch { .. } => FrameMask::DATA_TRACE,
+            ITMFrame::PCSleep { .. } | ITMFrame::PCSample { .. } => FrameMask::PC_SAMPLE,
+            ITMFrame::Xtn { .. } | ITMFrame::PageSet { .. } => FrameMask::EXTENSION,
+            ITMFrame::TPIUSync { .. } | ITMFrame::Sync { .. } => FrameMask::SYNC,
+            ITMFrame::Overflow { .. } => FrameMask::OVERFLOW,
+            ITMFrame::EventC { .. } => FrameMask::EVENT_COUNTER,
+            ITMFrame::PMUOverflow { .. } => FrameMask::PMU_OVERFLOW,
+            ITMFrame::HostTimestamp { .. } => FrameMask::HOST_TIMESTAMP,
+            ITMFrame::Unsynced { .. } => FrameMask::UNSYNCED,
+        }
+    }
 }
 
 /// Statistics about decode that are maintained
@@ -147,20 +289,58 @@ pub struct ITMStats {
     pub tpiusync: u64,
     /// Number of ITM sync messages received
     pub itmsync: u64,
+    /// Number of ITM sync messages received since the last other packet. A target that
+    /// keeps this high is sending sync continuously, wasting bandwidth
+    pub consecutive_syncs: u64,
     /// Number of Instrumentation packets received
     pub instrupkts: u64,
+    /// Number of data trace packets received
+    pub datatrace: u64,
+    /// Number of exception trace packets received
+    pub exceptions: u64,
+    /// Number of PC sample packets received
+    pub pcsamples: u64,
     /// Number of overflow packets received
     pub overflow: u64,
     /// Number of local timestamp packets received
     pub ts: u64,
+    /// Number of local timestamp packets indicating counter overflow
+    pub ts_overflow: u64,
     /// Number of noise bytes received
     pub noise: u64,
 }
 
 /// Processing specific to a state - in this case, token handling
-trait State: Debug {
+trait State: Debug + StateClone {
     fn token(&mut self, tok: u8, i: &mut ITMInternal)
         -> (Option<Box<dyn State>>, Option<ITMFrame>);
+
+    /// Is the next token part of a packet already started (rather than a header)?
+    fn in_packet(&self) -> bool {
+        true
+    }
+
+    /// Most payload bytes a packet with continuation bits can have, `None` if it's fixed length
+    fn max_payload(&self) -> Option<u8> {
+        None
+    }
+}
+
+/// Copying of boxed states, so the decoder can be checkpointed
+trait StateClone {
+    fn clone_box(&self) -> Box<dyn State>;
+}
+
+impl<T: State + Clone + 'static> StateClone for T {
+    fn clone_box(&self) -> Box<dyn State> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn State> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
 /// Processing for state creation
@@ -175,8 +355,111 @@ struct ITMInternal {
     timestamp: u32,    // Local timestamp last valid value
     gtimestamp: u64,   // Global timestamp last valid value
 
+    ignore_tpiu_sync: bool,    // Count TPIU syncs but don't lose sync on them
+    page_register: u8,         // Current stimulus port page
+    report_page: bool,         // Report changes to the page register as frames
+    payload_run: u8,           // Number of payload bytes received for the packet in progress
+    noise_resync: Option<u64>, // Consecutive noise bytes that cause loss of sync
+    noise_run: u64,            // Consecutive noise bytes received
+    swo_mode: Option<SwoMode>, // How SWO is carried, if known
+    emit_unsynced: bool,       // Report bytes received while waiting for sync
+    attach_ts: bool,           // Attach local time to instrumentation
+    local_time: Option<u64>,   // Total of local timestamps, while attaching
+    tolerant: bool,            // Re-dispatch packets that overrun their maximum length
+
     stats: ITMStats, // Statistics maintenance
 }
+
+// Extraction of a single stream from TPIU frames
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+struct TpiuPassthrough {
+    stream: u8,                  // Stream to be extracted
+    engaged: bool,               // Has TPIU framing been seen?
+    frame: [u8; TPIU_FRAME_LEN], // Frame being collected
+    len: usize,                  // Bytes of frame collected so far
+    id: u8,                      // Stream carried by the current data bytes
+    last_bytes: u64,             // Sequence of last framed bytes, for finding syncs
+    out: VecDeque<u8>,           // Extracted bytes awaiting decode
+}
+
+impl TpiuPassthrough {
+    // Unpack a complete frame, keeping the bytes for our stream. Even bytes are either a
+    // stream id change (bit 0 set) or data with its bit 0 held in the last byte of the frame.
+    // That same bit of an id change says if it applies after the following data byte.
+    fn unpack(&mut self) {
+        let f = self.frame;
+        let aux = f[TPIU_FRAME_LEN - 1];
+        for n in 0..(TPIU_FRAME_LEN / 2) {
+            let (b, late) = (f[2 * n], (aux >> n) & 1 != 0);
+            let next = f.get(2 * n + 1).filter(|_| n < TPIU_FRAME_LEN / 2 - 1);
+            if b & 1 == 0 {
+                self.keep(b | late as u8);
+            } else if !late {
+                self.id = b >> 1;
+            } else {
+                if let Some(d) = next {
+                    self.keep(*d);
+                }
+                self.id = b >> 1;
+                continue;
+            }
+            if let Some(d) = next {
+                self.keep(*d);
+            }
+        }
+    }
+
+    fn keep(&mut self, d: u8) {
+        if self.id == self.stream {
+            self.out.push_back(d);
+        }
+    }
+}
+
+/// How SWO is carried on the wire
+///
+/// The bytes decode the same either way, but the encodings fail differently. Manchester
+/// carries its clock with the data, so a receiver rarely slips, while NRZ (UART) relies on
+/// both ends agreeing on the baud rate and a framing error or a glitched start bit can
+/// corrupt a run of bytes until it recovers. Set with [`ITMDecoder::set_swo_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwoMode {
+    /// Manchester encoded SWO
+    Manchester,
+    /// NRZ (UART) encoded SWO
+    Uart,
+}
+
+impl SwoMode {
+    /// Consecutive noise bytes that cause loss of sync in this mode, if any
+    ///
+    /// # Example
+    /// ```
+    /// use itm::SwoMode;
+    /// assert_eq!(None, SwoMode::Manchester.noise_resync_threshold());
+    /// assert_eq!(Some(16), SwoMode::Uart.noise_resync_threshold());
+    /// ```
+    pub fn noise_resync_threshold(&self) -> Option<u64> {
+        match self {
+            SwoMode::Manchester => None,
+            SwoMode::Uart => Some(16),
+        }
+    }
+}
+
+/// A copy of the state of an [`ITMDecoder`], to go back to later
+///
+/// Created by [`ITMDecoder::checkpoint`] and put back with [`ITMDecoder::restore`].
+#[derive(Debug, Clone)]
+pub struct DecoderCheckpoint {
+    state: Box<dyn State>,
+    i: ITMInternal,
+    raw: Vec<u8>,
+    tpiu: Option<TpiuPassthrough>,
+    packet: Vec<u8>,
+    replay: VecDeque<u8>,
+}
+
 /// The stateful ITM decoder
 ///
 /// This maintains sticky state information and statistics of packets decoded by the ITM machine.
@@ -185,6 +468,12 @@ struct ITMInternal {
 pub struct ITMDecoder {
     state: Box<dyn State>,
     i: ITMInternal,
+    raw: Vec<u8>,         // Bytes collected towards the next frame by get_frame_with_raw
+    filter: FrameMask,    // Kinds of frame to be returned
+    packet: Vec<u8>,      // Bytes of the packet in progress, kept in tolerant mode
+    replay: VecDeque<u8>, // Bytes of an abandoned packet waiting to be dispatched again
+
+    tpiu: Option<TpiuPassthrough>, // Stream extracted from TPIU frames, if set
 }
 
 impl Default for ITMDecoder {
@@ -205,15 +494,41 @@ impl ITMDecoder {
             ITMDecoder {
                 state: Box::new(Idle),
                 i: Default::default(),
+                raw: Vec::new(),
+                filter: FrameMask::all(),
+                tpiu: None,
+                packet: Vec::new(),
+                replay: VecDeque::new(),
             }
         } else {
             ITMDecoder {
                 state: Box::new(Unsynced),
                 i: Default::default(),
+                raw: Vec::new(),
+                filter: FrameMask::all(),
+                tpiu: None,
+                packet: Vec::new(),
+                replay: VecDeque::new(),
             }
         }
     }
 
+    /// The byte sequence that brings a decoder into sync
+    ///
+    /// Anything producing ITM, or a test feeding a decoder, can emit this rather than
+    /// hardcoding the pattern.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(false);
+    /// let g = i.get_frame(&mut ITMDecoder::sync_sequence().iter());
+    /// assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
+    /// ```
+    pub fn sync_sequence() -> &'static [u8] {
+        &ITM_SYNC_SEQUENCE
+    }
+
     /// Provide statistical information about the performance of the decoder instance.
     ///
     /// # Return value
@@ -244,6 +559,253 @@ impl ITMDecoder {
         self.i.context_idlen = l;
     }
 
+    /// Set if TPIU syncs should cause loss of sync
+    ///
+    /// By default a TPIU sync in the flow is taken to mean the decoder is not looking at a
+    /// clean ITM flow, so it drops to unsynced. When set, TPIU syncs are still counted and
+    /// reported, but decode continues. This is useful where TPIU framing has been stripped
+    /// but the syncs remain.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::ITMDecoder;
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_ignore_tpiu_sync(true);
+    /// ```
+    pub fn set_ignore_tpiu_sync(&mut self, ignore: bool) {
+        self.i.ignore_tpiu_sync = ignore;
+    }
+
+    /// Decode the ITM carried on one stream of a TPIU formatted flow
+    ///
+    /// Once a TPIU sync is seen, the input is taken to be 16 byte TPIU frames. Only the bytes
+    /// for `stream_id` are decoded as ITM, and those of other streams are ignored. TPIU syncs
+    /// are still counted and reported, and realign the frames, but don't cause loss of sync.
+    /// Until the first TPIU sync the input is decoded as plain ITM. A stream id of 0 (which
+    /// TPIU reserves for null data) turns this off.
+    ///
+    /// This is much cheaper than a full demux, but covers only the common case of a single
+    /// source over TPIU. Halfword syncs aren't recognised, so the flow must be made of whole
+    /// frames between full syncs, and the other streams are discarded rather than made
+    /// available.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::ITMDecoder;
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_tpiu_passthrough(1);
+    /// ```
+    pub fn set_tpiu_passthrough(&mut self, stream_id: u8) {
+        self.tpiu = (stream_id != 0).then(|| TpiuPassthrough {
+            stream: stream_id,
+            ..Default::default()
+        });
+    }
+
+    /// Set the number of consecutive noise bytes that cause loss of sync
+    ///
+    /// A decoder that has slipped out of step with the flow sees a lot of bytes that aren't
+    /// valid packets, and emits garbage frames built from those that are. With a threshold set,
+    /// that many noise bytes in a row drop the decoder to unsynced, and it waits for a fresh
+    /// sync before decoding again. Any valid packet resets the count. Off (`None`) by default.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::ITMDecoder;
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_noise_resync_threshold(Some(16));
+    /// ```
+    pub fn set_noise_resync_threshold(&mut self, threshold: Option<u64>) {
+        self.i.noise_resync = threshold.filter(|t| *t != 0);
+        self.i.noise_run = 0;
+    }
+
+    /// Get the number of consecutive noise bytes that cause loss of sync, if set
+    pub fn noise_resync_threshold(&self) -> Option<u64> {
+        self.i.noise_resync
+    }
+
+    /// Set how SWO is carried on the wire, to suit the sync tolerance to it
+    ///
+    /// This sets the noise resync threshold to the one for the mode (see
+    /// [`SwoMode::noise_resync_threshold`]). UART is more prone to runs of corrupt bytes, so
+    /// the decoder gives up on them sooner and waits for a fresh sync, while Manchester leaves
+    /// it off. A later call to [`ITMDecoder::set_noise_resync_threshold`] overrides it. The
+    /// mode is otherwise informational, and is unset by default.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, SwoMode};
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_swo_mode(SwoMode::Uart);
+    /// assert_eq!(Some(SwoMode::Uart), i.swo_mode());
+    /// assert_eq!(Some(16), i.noise_resync_threshold());
+    /// ```
+    pub fn set_swo_mode(&mut self, mode: SwoMode) {
+        self.i.swo_mode = Some(mode);
+        self.set_noise_resync_threshold(mode.noise_resync_threshold());
+    }
+
+    /// Get how SWO is carried on the wire, if set
+    pub fn swo_mode(&self) -> Option<SwoMode> {
+        self.i.swo_mode
+    }
+
+    /// Set if bytes received while waiting for sync are reported
+    ///
+    /// Normally everything ahead of sync is silently discarded. For link bring-up it's useful
+    /// to see what is arriving, so when set each of those bytes is reported as an
+    /// [`ITMFrame::Unsynced`]. Off by default.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(false);
+    /// i.set_emit_unsynced_bytes(true);
+    /// assert_eq!(Ok(ITMFrame::Unsynced { byte: 0x55 }), i.get_frame(&mut [0x55].iter()));
+    /// ```
+    pub fn set_emit_unsynced_bytes(&mut self, emit: bool) {
+        self.i.emit_unsynced = emit;
+    }
+
+    /// Set if instrumentation frames carry the local time they follow
+    ///
+    /// Instrumentation packets are usually accompanied by the local timestamp that applies to
+    /// them, but the two arrive as separate frames. When set, the decoder keeps a running total
+    /// of local timestamps and attaches it to each instrumentation frame as `ts`, so every
+    /// message carries its own time, in ticks since this was set. Instrumentation ahead of the
+    /// first timestamp has `ts` of `None`, as it is whenever this is off (the default).
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_attach_timestamp(true);
+    /// i.set_frame_filter(itm::FrameMask::INSTRUMENTATION);
+    /// assert_eq!(
+    ///     Ok(ITMFrame::Instrumentation { addr: 1, data: 0x41, len: 1, ts: Some(3) }),
+    ///     i.get_frame(&mut [0x30, 0x09, 0x41].iter())
+    /// );
+    /// ```
+    pub fn set_attach_timestamp(&mut self, attach: bool) {
+        self.i.attach_ts = attach;
+        self.i.local_time = None;
+    }
+
+    /// Set if changes to the stimulus port page register are reported
+    ///
+    /// The page register is always tracked and applied to the addresses of subsequent
+    /// instrumentation packets. When set, each change is also reported as an
+    /// [`ITMFrame::PageSet`].
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_report_page(true);
+    /// assert_eq!(Ok(ITMFrame::PageSet { page: 1 }), i.get_frame(&mut [0x1c].iter()));
+    /// ```
+    pub fn set_report_page(&mut self, report: bool) {
+        self.i.report_page = report;
+    }
+
+    /// Find the first sync in a capture that starts part way through the flow
+    ///
+    /// Returns the offset in `bytes` of the first ITM sync sequence, or `None` if there isn't
+    /// one. Nothing is decoded, so none of the noise ahead of it is reported or counted. The
+    /// decoder is left waiting for a sync, so decoding can start from `bytes[offset..]`, with
+    /// the sync as the first frame. A capture that starts many KB before its first sync can be
+    /// fast forwarded like this.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(false);
+    /// let ip = [0x12, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x09, 0x41];
+    /// let offset = i.scan_to_sync(&ip).unwrap();
+    /// assert_eq!(2, offset);
+    /// assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut ip[offset..].iter()));
+    /// ```
+    pub fn scan_to_sync(&mut self, bytes: &[u8]) -> Option<usize> {
+        self.resync(false);
+        self.i.last_bytes = 0;
+        bytes
+            .windows(ITM_SYNC_SEQUENCE.len())
+            .position(|w| w == ITM_SYNC_SEQUENCE)
+    }
+
+    /// Set if the decoder recovers from packets that don't terminate
+    ///
+    /// A corrupt byte between packets can look like the header of a packet with continuation
+    /// bits (a timestamp or extension packet), which then swallows good bytes until one
+    /// happens to have its top bit clear. When tolerant, a packet that is still continuing
+    /// once it has reached the most payload the architecture allows for its type is taken to
+    /// have started on a corrupt byte. That header byte is counted as noise, and decode starts
+    /// again from the byte after it, so the bytes it swallowed are decoded as packets in their
+    /// own right. Fixed length packets always terminate, so the heuristic can't catch a corrupt
+    /// byte that looks like one of them; the damage from those is limited to their length.
+    /// Off by default.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_tolerant(true);
+    /// /* 0xc0 is a corrupt byte, read as the header of a local timestamp */
+    /// let ip = [0xc0, 0x94, 0x81, 0x82, 0x83, 0x04];
+    /// assert!(matches!(
+    ///     i.get_frame(&mut ip.iter()),
+    ///     Ok(ITMFrame::Globaltimestamp { .. })
+    /// ));
+    /// ```
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.i.tolerant = tolerant;
+        self.packet.clear();
+    }
+
+    /// Take a copy of the decoder state, to restore later
+    ///
+    /// This captures everything the decode of the following bytes depends on: the phase of
+    /// any packet in progress, the sticky timestamp, page and sync state, the settings and the
+    /// statistics. A viewer that lets the user scrub backwards through a capture can keep
+    /// checkpoints along with their offsets, and decode again from the nearest one rather than
+    /// from the start.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// let cp = i.checkpoint();
+    /// let ip = [0x09, 0x41];
+    /// let first = i.get_frame(&mut ip.iter());
+    /// i.restore(cp);
+    /// assert_eq!(first, i.get_frame(&mut ip.iter()));
+    /// ```
+    pub fn checkpoint(&self) -> DecoderCheckpoint {
+        DecoderCheckpoint {
+            state: self.state.clone(),
+            i: self.i.clone(),
+            raw: self.raw.clone(),
+            tpiu: self.tpiu.clone(),
+            packet: self.packet.clone(),
+            replay: self.replay.clone(),
+        }
+    }
+
+    /// Put the decoder back to the state of a checkpoint
+    ///
+    /// Decode carries on as it would have from the point the checkpoint was taken, so the
+    /// bytes that followed it should be fed next. The frame filter is not part of the
+    /// checkpoint, and is left as it is.
+    pub fn restore(&mut self, cp: DecoderCheckpoint) {
+        self.state = cp.state;
+        self.i = cp.i;
+        self.raw = cp.raw;
+        self.tpiu = cp.tpiu;
+        self.packet = cp.packet;
+        self.replay = cp.replay;
+    }
+
     /// Interate through the packet assembler, returning an ITM message or exhaustion
     ///
     /// Feeds iterated bytes through the packet assembler, until either the stream expires or
@@ -273,6 +835,9 @@ impl ITMDecoder {
         I: Iterator<Item = &'a u8>,
     {
         loop {
+            if let Some(s) = self.extracted() {
+                return Ok(s);
+            }
             match iter.next() {
                 Some(t) => match self.token(*t) {
                     Some(s) => return Ok(s),
@@ -285,6 +850,160 @@ impl ITMDecoder {
         }
     }
 
+    /// Decode a buffer that should hold nothing but complete packets
+    ///
+    /// For test fixtures and validation, where a buffer that stops part way through a packet
+    /// means something has been lost. Every frame in `bytes` is returned, as it would be by
+    /// [`ITMDecoder::get_frame`], unless the buffer ends within a packet (or within a TPIU
+    /// frame, when passing through TPIU).
+    ///
+    /// # Errors
+    /// `ITMError::ShortData` if the buffer ends part way through a packet.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMError};
+    /// let mut i = ITMDecoder::new(true);
+    /// assert_eq!(1, i.decode_complete(&[0x09, 0x41]).unwrap().len());
+    /// assert_eq!(Err(ITMError::ShortData), i.decode_complete(&[0x09]));
+    /// ```
+    ///
+    pub fn decode_complete(&mut self, bytes: &[u8]) -> Result<Vec<ITMFrame>, ITMError> {
+        let mut frames = Vec::new();
+        let mut i = bytes.iter();
+        while let Ok(f) = self.get_frame(&mut i) {
+            frames.push(f);
+        }
+        if self.state.in_packet()
+            || !self.replay.is_empty()
+            || self.tpiu.as_ref().is_some_and(|tp| tp.len != 0)
+        {
+            Err(ITMError::ShortData)
+        } else {
+            Ok(frames)
+        }
+    }
+
+    /// Interate through the packet assembler, returning an ITM message and the bytes that made it
+    ///
+    /// As [`ITMDecoder::get_frame`], but also returns the raw bytes consumed since the previous
+    /// frame was returned by this call. These include any noise or unreported packets (e.g. page
+    /// register changes) ahead of the frame, so passing on the raw bytes of every frame
+    /// reproduces the input exactly. If the stream expires before a frame is complete the bytes
+    /// are held over, and returned with the frame when it does complete.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// let ip = vec![0x09, 0x41];
+    /// let (f, raw) = i.get_frame_with_raw(&mut ip.iter()).unwrap();
+    /// assert_eq!(ITMFrame::Instrumentation { addr: 1, data: 0x41, len: 1, ts: None }, f);
+    /// assert_eq!(ip, raw);
+    /// ```
+    pub fn get_frame_with_raw<'a, I>(
+        &mut self,
+        iter: &mut I,
+    ) -> Result<(ITMFrame, Vec<u8>), ITMError>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        if let Some(s) = self.extracted() {
+            return Ok((s, std::mem::take(&mut self.raw)));
+        }
+        for t in iter {
+            self.raw.push(*t);
+            if let Some(s) = self.token(*t) {
+                return Ok((s, std::mem::take(&mut self.raw)));
+            }
+        }
+        Err(ITMError::ShortData)
+    }
+
+    /// Decode ITM from a text capture of hex bytes
+    ///
+    /// Some capture tools (logic analysers, `xxd -p` and the like) emit the flow as ASCII hex.
+    /// Each line is split on whitespace, and each part is taken as a run of hex bytes (two
+    /// digits per byte, with an optional `0x` prefix). The bytes are fed through the decoder
+    /// and the frames returned by the iterator, which ends with the input.
+    ///
+    /// # Errors
+    ///
+    /// `ITMError::BadInput` is returned for a part that isn't hex, after any frames from the
+    /// line before it, and the rest of that line is skipped. `ITMError::Io` is returned if
+    /// reading from the source fails. In either case iteration can continue.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(false);
+    /// let ip = "00 00 00 00 00 80\n09 41\n";
+    /// let frames: Vec<_> = i.decode_hex_reader(ip.as_bytes()).collect();
+    /// assert_eq!(
+    ///     Ok(ITMFrame::Instrumentation { addr: 1, data: 0x41, len: 1, ts: None }),
+    ///     frames[1]
+    /// );
+    /// ```
+    pub fn decode_hex_reader<'a, R: BufRead + 'a>(
+        &'a mut self,
+        r: R,
+    ) -> impl Iterator<Item = Result<ITMFrame, ITMError>> + 'a {
+        let mut lines = r.lines();
+        let mut pending: Vec<u8> = Vec::new();
+        let mut pos = 0;
+        let mut bad = false;
+        std::iter::from_fn(move || loop {
+            /* Drain anything we already have */
+            if let Some(f) = self.extracted() {
+                return Some(Ok(f));
+            }
+            while pos < pending.len() {
+                pos += 1;
+                if let Some(f) = self.token(pending[pos - 1]) {
+                    return Some(Ok(f));
+                }
+            }
+
+            /* ...report if the line was cut short... */
+            if bad {
+                bad = false;
+                return Some(Err(ITMError::BadInput));
+            }
+
+            /* ...then get the next line */
+            pending.clear();
+            pos = 0;
+            let line = match lines.next()? {
+                Ok(l) => l,
+                Err(e) => return Some(Err(ITMError::Io(e.kind()))),
+            };
+            for part in line.split_whitespace() {
+                match ITMDecoder::parse_hex(part) {
+                    Some(b) => pending.extend(b),
+                    None => {
+                        bad = true;
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    // Convert a run of hex digits into the bytes they represent
+    fn parse_hex(part: &str) -> Option<Vec<u8>> {
+        let digits = part
+            .strip_prefix("0x")
+            .or_else(|| part.strip_prefix("0X"))
+            .unwrap_or(part);
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|n| u8::from_str_radix(digits.get(n..n + 2)?, 16).ok())
+            .collect()
+    }
+
     /// Force synchronisation
     ///
     /// Force sync for the case that no sync is available in the stream.
@@ -301,30 +1020,166 @@ impl ITMDecoder {
     ///
     pub fn sync(&mut self) {
         self.i.stats.itmsync += 1;
+        self.i.payload_run = 0;
+        self.i.noise_run = 0;
+        self.raw.clear();
+        self.packet.clear();
+        self.replay.clear();
         self.state = Box::new(Idle);
     }
 
-    // Process single token from the stream and see if it returned a frame
+    /// Resynchronise the decoder
+    ///
+    /// Abandons any packet in progress and returns to the state a new decoder would start in,
+    /// either idle (if `start_synced` is set) or awaiting a sync sequence. Unlike
+    /// [`ITMDecoder::sync`] this isn't counted as a sync, and statistics and configuration
+    /// (context id length, page register) are kept.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::ITMDecoder;
+    /// let mut i = ITMDecoder::new(true);
+    /// i.resync(false);
+    /// ```
+    ///
+    pub fn resync(&mut self, start_synced: bool) {
+        self.i.payload_run = 0;
+        self.i.noise_run = 0;
+        self.raw.clear();
+        self.packet.clear();
+        self.replay.clear();
+        self.state = if start_synced {
+            Box::new(Idle)
+        } else {
+            Box::new(Unsynced)
+        };
+    }
+
+    /// Set which kinds of frame are returned
+    ///
+    /// Frames of other kinds are still decoded (so the decoder stays in step with the flow,
+    /// and they are counted in the statistics) but are not returned. By default all kinds are
+    /// returned.
+    ///
+    /// # Example
+    /// ```
+    /// use itm::{FrameMask, ITMDecoder, ITMFrame};
+    /// let mut i = ITMDecoder::new(true);
+    /// i.set_frame_filter(FrameMask::INSTRUMENTATION);
+    /// let ip = vec![0x30, 0x09, 0x41];
+    /// assert_eq!(
+    ///     Ok(ITMFrame::Instrumentation { addr: 1, data: 0x41, len: 1, ts: None }),
+    ///     i.get_frame(&mut ip.iter())
+    /// );
+    /// ```
+    pub fn set_frame_filter(&mut self, mask: FrameMask) {
+        self.filter = mask;
+    }
+
+    // Process single token from the stream and see if it returned a frame that is wanted
     fn token(&mut self, tok: u8) -> Option<ITMFrame> {
+        if self.tpiu.is_some() {
+            return self
+                .tpiu_token(tok)
+                .filter(|f| self.filter.contains(FrameMask::of(f)))
+                .or_else(|| self.extracted());
+        }
+        self.decode_token(tok)
+            .filter(|f| self.filter.contains(FrameMask::of(f)))
+            .or_else(|| self.extracted())
+    }
+
+    // Decode bytes held over, from an abandoned packet or extracted from TPIU frames, until
+    // one returns a frame that is wanted
+    fn extracted(&mut self) -> Option<ITMFrame> {
+        loop {
+            let f = if let Some(b) = self.replay.pop_front() {
+                self.dispatch(b)
+            } else {
+                let b = self.tpiu.as_mut()?.out.pop_front()?;
+                self.decode_token(b)
+            };
+            if f.as_ref()
+                .is_some_and(|f| self.filter.contains(FrameMask::of(f)))
+            {
+                return f;
+            }
+        }
+    }
+
+    // Collect a token into the TPIU frame, or pass it on if no TPIU framing has been seen yet
+    fn tpiu_token(&mut self, tok: u8) -> Option<ITMFrame> {
+        let tp = self.tpiu.as_mut()?;
+        if tp.engaged {
+            tp.frame[tp.len] = tok;
+            tp.len += 1;
+            if tp.len == TPIU_FRAME_LEN {
+                tp.unpack();
+                tp.len = 0;
+            }
+        }
+
+        /* A TPIU sync restarts the frame, and when it's the first, abandons any ITM in progress */
+        tp.last_bytes = tp.last_bytes << 8 | tok as u64;
+        if tp.last_bytes & TPIU_SYNCMASK == TPIU_SYNCPATTERN {
+            self.i.stats.tpiusync += 1;
+            self.i.stats.inpackets += 1;
+            if !tp.engaged {
+                self.i.last_bytes = 0;
+                if self.state.in_packet() {
+                    self.i.payload_run = 0;
+                    self.state = Box::new(Idle);
+                }
+            }
+            tp.engaged = true;
+            tp.len = 0;
+            return Some(ITMFrame::TPIUSync {
+                count: self.i.stats.tpiusync,
+            });
+        }
+
+        if tp.engaged {
+            None
+        } else {
+            self.decode_token(tok)
+        }
+    }
+
+    // Process single token from the stream and see if it returned a frame
+    fn decode_token(&mut self, tok: u8) -> Option<ITMFrame> {
         //print!("{:02x} ", tok);
         // Keep a record of last 8 bytes...these are used for checking syncs
         self.i.last_bytes = self.i.last_bytes << 8 | tok as u64;
         self.i.stats.inbytestotal += 1;
 
-        // ---- Check for TPIU sync. Shouldn't occur, so reset to unsynced case if it does
-        if self.i.last_bytes & TPIU_SYNCMASK == TPIU_SYNCPATTERN {
+        // ---- Check for TPIU sync. Shouldn't occur, so reset to unsynced case if it does.
+        // The sync is all ones, so it can also be legitimate payload (e.g. an instrumentation
+        // value of 0x7fffffff, or an overflowed local timestamp). Payload starts after its
+        // header, so if the whole pattern falls within a single packet it's let through. A
+        // real TPIU sync starts from a header position, as 0xff is never a valid payload start.
+        let in_packet = self.state.in_packet();
+        if self.i.last_bytes & TPIU_SYNCMASK == TPIU_SYNCPATTERN
+            && !(in_packet && self.i.payload_run >= 3)
+        {
+            self.i.payload_run = 0;
             self.i.stats.tpiusync += 1;
+            self.i.stats.consecutive_syncs = 0;
             self.i.stats.inpackets += 1;
-            self.state = Box::new(Unsynced);
+            if !self.i.ignore_tpiu_sync {
+                self.state = Box::new(Unsynced);
+            }
             return Some(ITMFrame::TPIUSync {
                 count: self.i.stats.tpiusync,
             });
         }
 
-        // ---- Check for ITMSync
+        // ---- Check for ITMSync. No packet has more than four bytes of payload, and a header is
+        // never zero, so five zeros can't be found in a legitimate flow other than in a sync.
         if self.i.last_bytes & ITM_SYNCMASK == ITM_SYNCPATTERN {
             self.i.stats.itmsync += 1;
+            self.i.stats.consecutive_syncs += 1;
             self.i.stats.inpackets += 1;
+            self.i.payload_run = 0;
             self.state = Box::new(Idle);
             //println!("Sync");
             return Some(ITMFrame::Sync {
@@ -332,18 +1187,76 @@ impl ITMDecoder {
             });
         }
 
-        // ---- Call the current state for processing, updating as needed
-        let (newstate, retval) = self.state.token(tok, &mut self.i);
+        self.dispatch(tok)
+    }
 
-        if retval.is_some() {
+    // Pass a token to the current state for processing, updating as needed
+    fn dispatch(&mut self, tok: u8) -> Option<ITMFrame> {
+        let in_packet = self.state.in_packet();
+        self.i.payload_run = if in_packet {
+            self.i.payload_run.saturating_add(1)
+        } else {
+            0
+        };
+        let noise = self.i.stats.noise;
+        let (newstate, mut retval) = self.state.token(tok, &mut self.i);
+
+        if retval
+            .as_ref()
+            .is_some_and(|f| !matches!(f, ITMFrame::Unsynced { .. }))
+        {
             self.i.stats.inpackets += 1;
+            self.i.stats.consecutive_syncs = 0;
         }
-        if newstate.is_some() {
+        if let Some(s) = newstate {
             //print!("Transition from {:?} ", self.state);
-            self.state = newstate.unwrap();
+            self.state = s;
             //println!("to {:?} ", self.state);
         }
 
+        // ---- A packet running past its longest must have started on a corrupt byte
+        if self.i.tolerant {
+            if !in_packet {
+                self.packet.clear();
+            }
+            self.packet.push(tok);
+            if self.state.in_packet()
+                && self
+                    .state
+                    .max_payload()
+                    .is_some_and(|m| self.i.payload_run >= m)
+            {
+                self.i.stats.noise += 1;
+                self.i.payload_run = 0;
+                self.state = Box::new(Idle);
+                for b in self.packet.drain(1..).rev() {
+                    self.replay.push_front(b);
+                }
+            }
+        }
+
+        // ---- Keep local time for instrumentation, if it's wanted
+        if self.i.attach_ts {
+            match &mut retval {
+                Some(ITMFrame::Timestamp { ts, .. }) => {
+                    self.i.local_time = Some(self.i.local_time.unwrap_or(0).saturating_add(*ts))
+                }
+                Some(ITMFrame::Instrumentation { ts, .. }) => *ts = self.i.local_time,
+                _ => (),
+            }
+        }
+
+        // ---- Too much noise in a row means we've lost step with the flow
+        if self.i.stats.noise == noise {
+            self.i.noise_run = 0;
+        } else {
+            self.i.noise_run += 1;
+            if self.i.noise_resync.is_some_and(|t| self.i.noise_run >= t) {
+                self.i.noise_run = 0;
+                self.state = Box::new(Unsynced);
+            }
+        }
+
         retval
     }
 }
@@ -372,6 +1285,7 @@ impl State for Idle {
             "11??_0000" => Lts::matches(tok, i),
             "????_1?00" => Xtn::matches(tok, i),
             "0000_0101" => Event::matches(tok, i),
+            /* Includes instrumentation headers with the reserved payload size of 0 */
             "????_??00" => {
                 i.stats.noise += 1;
                 (None, None)
@@ -388,6 +1302,9 @@ impl State for Idle {
             }
         }
     }
+    fn in_packet(&self) -> bool {
+        false
+    }
 }
 
 /* ---- Unsynchronised ---------------------------------------- */
@@ -399,11 +1316,18 @@ struct Unsynced;
 impl State for Unsynced {
     fn token(
         &mut self,
-        _tok: u8,
-        _i: &mut ITMInternal,
+        tok: u8,
+        i: &mut ITMInternal,
     ) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
-        // Don't actually do anything, the dispatcher deals with this case
-        (None, None)
+        // Nothing to decode, the dispatcher deals with finding sync
+        (
+            None,
+            i.emit_unsynced.then_some(ITMFrame::Unsynced { byte: tok }),
+        )
+    }
+
+    fn in_packet(&self) -> bool {
+        false
     }
 }
 
@@ -435,6 +1359,7 @@ impl State for Instrumentation {
                     addr: self.addr,
                     data: self.data,
                     len: self.target,
+                    ts: None,
                 }),
             )
         } else {
@@ -451,7 +1376,7 @@ impl StateMatch for Instrumentation {
             Some(Box::new(Instrumentation {
                 target: if tok & 3 == 3 { 4 } else { tok & 3 },
                 count: 0,
-                addr: (tok >> 3) & 0x1f,
+                addr: (i.page_register << 5) | ((tok >> 3) & 0x1f),
                 data: 0,
             })),
             None,
@@ -499,11 +1424,30 @@ impl State for Xtn {
             (None, None)
         }
     }
+
+    fn max_payload(&self) -> Option<u8> {
+        Some(4)
+    }
 }
 
 impl StateMatch for Xtn {
-    fn matches(tok: u8, _i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
-        if tok & 0x80 == 0 {
+    fn matches(tok: u8, i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+        if tok & 0x84 == 0x04 {
+            /* This is a stimulus port page register update */
+            i.page_register = (tok >> 4) & 7;
+            if i.report_page {
+                (
+                    Some(Box::new(Idle)),
+                    Some(ITMFrame::PageSet {
+                        page: i.page_register,
+                    }),
+                )
+            } else {
+                /* Not reported, but still a packet */
+                i.stats.inpackets += 1;
+                (Some(Box::new(Idle)), None)
+            }
+        } else if tok & 0x80 == 0 {
             (
                 Some(Box::new(Idle)),
                 Some(ITMFrame::Xtn {
@@ -529,6 +1473,10 @@ impl StateMatch for Xtn {
 /* ---- A Local Timestamp packet ------------------------------ */
 /* Section F1.2.11 and F1.2.12 of DDI0553B.v                    */
 /* ------------------------------------------------------------ */
+
+/* A full length LTS1 of all ones indicates the counter overflowed */
+const LTS_OVERFLOW: u64 = 0x0fff_ffff;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct Lts {
     count: u8,
@@ -540,7 +1488,7 @@ impl State for Lts {
     fn token(
         &mut self,
         tok: u8,
-        _i: &mut ITMInternal,
+        i: &mut ITMInternal,
     ) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
         if self.count < 4 {
             self.ts |= ((tok & 0x7f) as u64) << (7 * self.count);
@@ -548,6 +1496,10 @@ impl State for Lts {
         }
 
         if tok & 0x80 == 0 {
+            let overflow = self.count == 4 && self.ts == LTS_OVERFLOW;
+            if overflow {
+                i.stats.ts_overflow += 1;
+            }
             (
                 Some(Box::new(Idle)),
                 Some(ITMFrame::Timestamp {
@@ -560,12 +1512,17 @@ impl State for Lts {
                     },
 
                     ts: self.ts,
+                    overflow,
                 }),
             )
         } else {
             (None, None)
         }
     }
+
+    fn max_payload(&self) -> Option<u8> {
+        Some(4)
+    }
 }
 
 impl StateMatch for Lts {
@@ -578,6 +1535,7 @@ impl StateMatch for Lts {
                 Some(ITMFrame::Timestamp {
                     ttype: TSType::Sync,
                     ts: ((tok >> 4) & 7) as u64,
+                    overflow: false,
                 }),
             )
         } else {
@@ -628,6 +1586,10 @@ impl State for Gts2 {
             (None, None)
         }
     }
+
+    fn max_payload(&self) -> Option<u8> {
+        Some(6)
+    }
 }
 
 impl StateMatch for Gts2 {
@@ -675,6 +1637,10 @@ impl State for Gts1 {
             (None, None)
         }
     }
+
+    fn max_payload(&self) -> Option<u8> {
+        Some(4)
+    }
 }
 
 impl StateMatch for Gts1 {
@@ -713,6 +1679,8 @@ impl State for Exception {
                 (None, None)
             }
             2 => {
+                /* Exception packets always have a two byte payload, so the 9 bits here cover
+                 * the architectural maximum of 511. Other bits of this byte are reserved. */
                 self.no |= (tok as u16 & 1) << 8;
                 let e = match (tok >> 4) & 3 {
                     1 => ExceptionEvent::Entry,
@@ -734,7 +1702,8 @@ impl State for Exception {
 }
 
 impl StateMatch for Exception {
-    fn matches(_tok: u8, _i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+    fn matches(_tok: u8, i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+        i.stats.exceptions += 1;
         (
             Some(Box::new(Exception {
                 no: 0,
@@ -829,7 +1798,8 @@ impl State for DataTrace {
 
 impl StateMatch for DataTrace {
     #[bitmatch]
-    fn matches(tok: u8, _i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+    fn matches(tok: u8, i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+        i.stats.datatrace += 1;
         (
             Some(Box::new(DataTrace {
                 index: (tok >> 4) & 3,
@@ -894,7 +1864,8 @@ impl State for PCSample {
 }
 
 impl StateMatch for PCSample {
-    fn matches(tok: u8, _i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+    fn matches(tok: u8, i: &mut ITMInternal) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
+        i.stats.pcsamples += 1;
         (
             Some(Box::new(PCSample {
                 addr: 0,
@@ -952,7 +1923,10 @@ impl State for PMUOverflow {
     ) -> (Option<Box<dyn State>>, Option<ITMFrame>) {
         (
             Some(Box::new(Idle)),
-            Some(ITMFrame::PMUOverflow { ovf: tok }),
+            Some(ITMFrame::PMUOverflow {
+                ovf: tok,
+                overflowed: std::array::from_fn(|n| tok & (1 << n) != 0),
+            }),
         )
     }
 }
@@ -4,16 +4,41 @@ use super::*;
 #[test]
 fn test_sync() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
+    let ip = [0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
 
     let g = i.get_frame(&mut ip.iter());
     assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
 }
 
+#[test]
+fn test_sync_sequence() {
+    /* From unsynced, and again when already in sync */
+    let mut i = ITMDecoder::new(false);
+    let ip = [
+        ITMDecoder::sync_sequence(),
+        &[0x09, 0x41],
+        ITMDecoder::sync_sequence(),
+    ]
+    .concat();
+    let mut v = ip.iter();
+
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }),
+        i.get_frame(&mut v)
+    );
+    assert_eq!(Ok(ITMFrame::Sync { count: 2 }), i.get_frame(&mut v));
+}
+
 #[test]
 fn test_nosync() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![0x00, 0x00, 0x00, 0x00, 0x20, 0x80];
+    let ip = [0x00, 0x00, 0x00, 0x00, 0x20, 0x80];
 
     let g = i.get_frame(&mut ip.iter());
     assert_eq!(Err(ITMError::ShortData), g);
@@ -22,16 +47,49 @@ fn test_nosync() {
 #[test]
 fn test_tpiusync() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![0x00, 0xff, 0xff, 0xff, 0x7f, 0x80];
+    let ip = [0x00, 0xff, 0xff, 0xff, 0x7f, 0x80];
 
     let g = i.get_frame(&mut ip.iter());
     assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), g);
 }
 
+#[test]
+fn test_tpiusync_ignored() {
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0xff, 0xff, 0xff, 0x7f, // TPIU Sync
+        0x01, 0x22, // Simple software source packet
+    ];
+
+    /* By default the TPIU sync loses the sync */
+    let mut i = ITMDecoder::new(false);
+    let mut v = ip.iter();
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(Err(ITMError::ShortData), i.get_frame(&mut v));
+
+    /* ...but when ignored decode carries on */
+    let mut i = ITMDecoder::new(false);
+    i.set_ignore_tpiu_sync(true);
+    let mut v = ip.iter();
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 0,
+            data: 0x22,
+            len: 1,
+            ts: None
+        }),
+        i.get_frame(&mut v)
+    );
+    assert_eq!(1, i.stats().tpiusync);
+}
+
 #[test]
 fn test_overflow() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x70];
+    let ip = [0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x70];
     let mut v = ip.iter();
 
     let g = i.get_frame(&mut v);
@@ -43,7 +101,7 @@ fn test_overflow() {
 #[test]
 fn test_local_ts_2() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x30];
+    let ip = [0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x30];
     let mut v = ip.iter();
 
     let g = i.get_frame(&mut v);
@@ -52,7 +110,8 @@ fn test_local_ts_2() {
     assert_eq!(
         Ok(ITMFrame::Timestamp {
             ttype: TSType::Sync,
-            ts: 3
+            ts: 3,
+            overflow: false
         }),
         g
     );
@@ -74,7 +133,8 @@ fn test_local_ts_1() {
     assert_eq!(
         Ok(ITMFrame::Timestamp {
             ttype: TSType::TSDelayed,
-            ts: 0x4105
+            ts: 0x4105,
+            overflow: false
         }),
         g
     );
@@ -82,7 +142,8 @@ fn test_local_ts_1() {
     assert_eq!(
         Ok(ITMFrame::Timestamp {
             ttype: TSType::DataDelayed,
-            ts: 0xa14285
+            ts: 0xa14285,
+            overflow: false
         }),
         g
     );
@@ -203,6 +264,7 @@ fn test_instrumentation_source() {
             addr: 0,
             data: 0x22,
             len: 1,
+            ts: None
         }),
         g,
         "Single byte to port 0"
@@ -214,6 +276,7 @@ fn test_instrumentation_source() {
             addr: 18,
             data: 0x44332211,
             len: 4,
+            ts: None
         }),
         g,
         "Four bytes to port 18"
@@ -225,6 +288,7 @@ fn test_instrumentation_source() {
             addr: 30,
             data: 0x1299,
             len: 2,
+            ts: None
         }),
         g,
         "Two bytes to port 30"
@@ -251,6 +315,7 @@ fn test_sw_page_no() {
             addr: 32,
             data: 0x22,
             len: 1,
+            ts: None
         }),
         g,
         "Single byte to port 0"
@@ -262,12 +327,93 @@ fn test_sw_page_no() {
             addr: 224 + 18,
             data: 0x44332211,
             len: 4,
+            ts: None
         }),
         g,
         "Four bytes to port 242"
     );
 }
 
+#[test]
+fn test_instrumentation_reserved_size() {
+    let mut i = ITMDecoder::new(false);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x84, // Size bits 00 are reserved
+        0x09, 0x41, // Simple software source packet
+    ];
+    let mut v = ip.iter();
+    let g = i.get_frame(&mut v);
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }),
+        g,
+        "Reserved size ignored"
+    );
+    assert_eq!(1, i.stats().noise);
+    assert_eq!(1, i.stats().instrupkts);
+}
+
+#[test]
+fn test_sw_page_reported() {
+    let mut i = ITMDecoder::new(false);
+    i.set_report_page(true);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x1C, // Set page 1 (Nos 32..63)
+        0x01, 0x22, // Simple software source packet
+        0x0C, // Set page 0
+        0x01, 0x33, // Simple software source packet
+    ];
+    let mut v = ip.iter();
+    let g = i.get_frame(&mut v);
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(Ok(ITMFrame::PageSet { page: 1 }), g);
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 32,
+            data: 0x22,
+            len: 1,
+            ts: None
+        }),
+        g,
+        "Single byte to port 32"
+    );
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(Ok(ITMFrame::PageSet { page: 0 }), g);
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 0,
+            data: 0x33,
+            len: 1,
+            ts: None
+        }),
+        g,
+        "Single byte to port 0"
+    );
+    assert_eq!(5, i.stats().inpackets);
+
+    /* Page sets that aren't reported are still counted */
+    let mut i = ITMDecoder::new(false);
+    let mut v = ip.iter();
+    while i.get_frame(&mut v).is_ok() {}
+    assert_eq!(5, i.stats().inpackets);
+}
+
 #[test]
 fn test_xtn() {
     let mut i = ITMDecoder::new(false);
@@ -449,7 +595,7 @@ fn test_event() {
 #[test]
 fn test_pmuovf() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![
+    let ip = [
         0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
         0x1d, 0x42, // PMU Overflow
     ];
@@ -458,7 +604,13 @@ fn test_pmuovf() {
     assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
 
     let g = i.get_frame(&mut v);
-    assert_eq!(Ok(ITMFrame::PMUOverflow { ovf: 0x42 }), g);
+    assert_eq!(
+        Ok(ITMFrame::PMUOverflow {
+            ovf: 0x42,
+            overflowed: [false, true, false, false, false, false, true, false],
+        }),
+        g
+    );
 }
 
 #[test]
@@ -499,7 +651,11 @@ fn test_exception() {
             event: ExceptionEvent::Returned
         }),
         g
-    )
+    );
+
+    assert_eq!(3, i.stats().exceptions);
+    assert_eq!(0, i.stats().datatrace);
+    assert_eq!(4, i.stats().inpackets);
 }
 
 #[test]
@@ -564,6 +720,10 @@ fn test_datatrace_pc() {
         }),
         g
     );
+
+    assert_eq!(3, i.stats().datatrace);
+    assert_eq!(0, i.stats().exceptions);
+    assert_eq!(4, i.stats().inpackets);
 }
 
 #[test]
@@ -683,12 +843,16 @@ fn test_trace_pc() {
 
     let g = i.get_frame(&mut v);
     assert_eq!(Ok(ITMFrame::PCSample { addr: 0xfdfcfbfa }), g);
+
+    assert_eq!(4, i.stats().pcsamples);
+    assert_eq!(0, i.stats().datatrace);
+    assert_eq!(5, i.stats().inpackets);
 }
 
 #[test]
 fn test_futz() {
     let mut i = ITMDecoder::new(false);
-    let ip = vec![
+    let ip = [
         0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
     ];
 
@@ -717,3 +881,601 @@ fn test_futz() {
     /* given that its 6 bytes long the chance is 1 in (1/256)^6 */
     assert_eq!(Ok(ITMFrame::Sync { count: 2 }), g);
 }
+
+#[test]
+fn test_local_ts_overflow() {
+    let mut i = ITMDecoder::new(false);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0xC0, 0xff, 0xff, 0xff, 0x7f, // TS Type 1 all ones, overflowed
+        0xC0, 0xff, 0xff, 0xff, 0x7e, // TS Type 1 just short of overflow
+    ];
+    let mut v = ip.iter();
+
+    let g = i.get_frame(&mut v);
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), g);
+    let g = i.get_frame(&mut v);
+    assert_eq!(
+        Ok(ITMFrame::Timestamp {
+            ttype: TSType::Sync,
+            ts: 0x0fffffff,
+            overflow: true
+        }),
+        g
+    );
+    let g = i.get_frame(&mut v);
+    assert_eq!(
+        Ok(ITMFrame::Timestamp {
+            ttype: TSType::Sync,
+            ts: 0x0fdfffff,
+            overflow: false
+        }),
+        g
+    );
+    assert_eq!(2, i.stats().ts);
+    assert_eq!(1, i.stats().ts_overflow);
+}
+
+#[test]
+fn test_exception_wide() {
+    let mut i = ITMDecoder::new(true);
+    let ip = [
+        0x0e, 0x2c, 0x11, // Exception 300, Entry
+        0x0e, 0xff, 0xef, // Exception 511, Exit, with reserved bits set
+        0x09, 0x41, // Following packet is unaffected
+    ];
+    let mut v = ip.iter();
+
+    assert_eq!(
+        Ok(ITMFrame::Exception {
+            no: 300,
+            event: ExceptionEvent::Entry
+        }),
+        i.get_frame(&mut v)
+    );
+    assert_eq!(
+        Ok(ITMFrame::Exception {
+            no: 511,
+            event: ExceptionEvent::Exit
+        }),
+        i.get_frame(&mut v)
+    );
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }),
+        i.get_frame(&mut v)
+    );
+}
+
+#[test]
+fn test_frame_with_raw() {
+    let mut i = ITMDecoder::new(false);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x0b, 0x01, 0x02, 0x03, 0x04, // Instrumentation, 4 bytes
+        0x0e, 0x2c, 0x11, // Exception 300, Entry
+        0xD0, 0x85, 0x82, 0x01, // TS Type 1 value 0x4105
+    ];
+
+    /* Split the input so frames straddle calls */
+    let mut frames = Vec::new();
+    let mut all = Vec::new();
+    for part in [&ip[..8], &ip[8..15], &ip[15..]] {
+        let mut v = part.iter();
+        while let Ok((f, raw)) = i.get_frame_with_raw(&mut v) {
+            all.extend_from_slice(&raw);
+            frames.push((f, raw));
+        }
+    }
+    assert_eq!(4, frames.len());
+    assert_eq!(&ip[..], &all[..]);
+
+    /* Each frame decodes the same on its own */
+    for (f, raw) in &frames[1..] {
+        let mut fresh = ITMDecoder::new(true);
+        assert_eq!(Ok(f.clone()), fresh.get_frame(&mut raw.iter()));
+    }
+}
+
+#[test]
+fn test_hex_reader() {
+    let mut i = ITMDecoder::new(false);
+    let ip = "00 00 00 00 00 80\n\
+              0x0b 01 02\n\
+              0304 09 41\n\
+              \n\
+              zz 09 42\n\
+              0x09 43\n";
+    let frames: Vec<_> = i.decode_hex_reader(ip.as_bytes()).collect();
+    assert_eq!(
+        vec![
+            Ok(ITMFrame::Sync { count: 1 }),
+            Ok(ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x04030201,
+                len: 4,
+                ts: None
+            }),
+            Ok(ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            }),
+            Err(ITMError::BadInput),
+            Ok(ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x43,
+                len: 1,
+                ts: None
+            }),
+        ],
+        frames
+    );
+}
+
+#[test]
+fn test_frame_filter() {
+    let mut i = ITMDecoder::new(false);
+    i.set_frame_filter(FrameMask::INSTRUMENTATION);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x09, 0x41, // Instrumentation
+        0x0e, 0x2c, 0x11, // Exception 300, Entry
+        0x30, // Local timestamp
+        0x17, 0x01, 0x02, 0x03, 0x04, // PC sample
+        0x70, // Overflow
+        0x0a, 0x42, 0x43, // Instrumentation
+    ];
+    let mut v = ip.iter();
+    let mut frames = Vec::new();
+    while let Ok(f) = i.get_frame(&mut v) {
+        frames.push(f);
+    }
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x4342,
+                len: 2,
+                ts: None
+            },
+        ],
+        frames
+    );
+
+    /* Everything is still counted */
+    assert_eq!(1, i.stats().itmsync);
+    assert_eq!(1, i.stats().exceptions);
+    assert_eq!(1, i.stats().ts);
+    assert_eq!(1, i.stats().pcsamples);
+    assert_eq!(1, i.stats().overflow);
+    assert_eq!(7, i.stats().inpackets);
+}
+
+#[test]
+fn test_instrumentation_payload() {
+    let mut i = ITMDecoder::new(true);
+    let ip = [
+        0x09, 0x41, // 1 byte
+        0x0a, 0x42, 0x43, // 2 bytes
+        0x0b, 0x00, 0x01, 0x02, 0x03, // 4 bytes
+        0x0b, 0x44, 0x00, 0x00, 0x00, // 4 bytes, zero padded
+    ];
+    let mut v = ip.iter();
+    let mut payloads = Vec::new();
+    while let Ok(f) = i.get_frame(&mut v) {
+        payloads.push(f.payload().unwrap().to_vec());
+    }
+    assert_eq!(
+        vec![
+            vec![0x41],
+            vec![0x42, 0x43],
+            vec![0x00, 0x01, 0x02, 0x03],
+            vec![0x44, 0x00, 0x00, 0x00]
+        ],
+        payloads
+    );
+    assert_eq!(None, ITMFrame::Sync { count: 0 }.payload());
+}
+
+#[test]
+fn test_sync_patterns_in_payload() {
+    let mut i = ITMDecoder::new(true);
+    let ip = [
+        0x0b, 0xff, 0xff, 0xff, 0x7f, // Instrumentation, payload matching TPIU sync
+        0x0b, 0x00, 0x00, 0x00, 0x00, // Instrumentation, all zero payload...
+        0x09, 0x80, // ...then one ending the same as an ITM sync
+        0x30, // Local timestamp
+    ];
+    let mut v = ip.iter();
+    let mut frames = Vec::new();
+    while let Ok(f) = i.get_frame(&mut v) {
+        frames.push(f);
+    }
+    assert_eq!(
+        vec![
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x7fff_ffff,
+                len: 4,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0,
+                len: 4,
+                ts: None
+            },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x80,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Timestamp {
+                ttype: TSType::Sync,
+                ts: 3,
+                overflow: false
+            },
+        ],
+        frames
+    );
+    assert_eq!(0, i.stats().tpiusync);
+    assert_eq!(0, i.stats().itmsync);
+
+    /* A real TPIU sync straight after a packet is still seen */
+    let mut v = [0x09, 0x41, 0xff, 0xff, 0xff, 0x7f].iter();
+    assert!(matches!(
+        i.get_frame(&mut v),
+        Ok(ITMFrame::Instrumentation { .. })
+    ));
+    assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), i.get_frame(&mut v));
+}
+
+#[test]
+fn test_noise_resync() {
+    let noise = [0x04u8; 8];
+    let good = [0x09, 0x41];
+    let sync = [0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
+
+    /* Below the threshold, and with it unset, decode carries on */
+    for threshold in [None, Some(9)] {
+        let mut i = ITMDecoder::new(true);
+        i.set_noise_resync_threshold(threshold);
+        let ip = [&noise[..], &good].concat();
+        assert!(matches!(
+            i.get_frame(&mut ip.iter()),
+            Ok(ITMFrame::Instrumentation { .. })
+        ));
+    }
+
+    /* At the threshold sync is lost, so nothing is decoded until the next sync */
+    let mut i = ITMDecoder::new(true);
+    i.set_noise_resync_threshold(Some(8));
+    let ip = [&noise[..], &good, &sync, &good].concat();
+    let mut v = ip.iter();
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+    assert!(matches!(
+        i.get_frame(&mut v),
+        Ok(ITMFrame::Instrumentation { .. })
+    ));
+    assert_eq!(8, i.stats().noise);
+
+    /* Only consecutive noise counts */
+    let mut i = ITMDecoder::new(true);
+    i.set_noise_resync_threshold(Some(8));
+    let ip = [&noise[..4], &good, &noise[..4], &good].concat();
+    let mut v = ip.iter();
+    for _ in 0..2 {
+        assert!(matches!(
+            i.get_frame(&mut v),
+            Ok(ITMFrame::Instrumentation { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_swo_mode() {
+    let noise = [0x04u8; 16];
+    let good = [0x09, 0x41];
+
+    /* UART gives up on a run of noise that Manchester decodes through */
+    let ip = [&noise[..], &good].concat();
+    let mut i = ITMDecoder::new(true);
+    i.set_swo_mode(SwoMode::Manchester);
+    assert_eq!(None, i.noise_resync_threshold());
+    assert!(matches!(
+        i.get_frame(&mut ip.iter()),
+        Ok(ITMFrame::Instrumentation { .. })
+    ));
+
+    let mut i = ITMDecoder::new(true);
+    i.set_swo_mode(SwoMode::Uart);
+    assert_eq!(Some(16), i.noise_resync_threshold());
+    assert!(i.get_frame(&mut ip.iter()).is_err());
+    assert_eq!(16, i.stats().noise);
+
+    /* An explicit threshold takes over from the mode */
+    i.set_noise_resync_threshold(None);
+    assert_eq!(None, i.noise_resync_threshold());
+    assert_eq!(Some(SwoMode::Uart), i.swo_mode());
+}
+
+#[test]
+fn test_tpiu_passthrough() {
+    #[rustfmt::skip]
+    let ip = [
+        0xff, 0xff, 0xff, 0x7f, // TPIU sync
+        0x03, 0x09,             // Stream 1 from here
+        0x40, 0x09,             // 0x41 with bit 0 in the last byte
+        0x05, 0x42,             // Stream 2 after the next byte
+        0x54, 0x66,             // 0x55 with bit 0 in the last byte
+        0x03, 0x70,             // Stream 1 from here, overflow
+        0x05, 0x77,             // Stream 2 from here
+        0x88, 0x99,
+        0x03,                   // Stream 1 from here, no data follows
+        0x0e,                   // Bit 0 of even data bytes, and when id changes apply
+        0x08, 0x43,             // 0x09 with bit 0 in the last byte
+        0x01, 0x09,             // Stream 0 (null) from here
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01,
+    ];
+    let mut i = ITMDecoder::new(true);
+    i.set_tpiu_passthrough(1);
+    let mut v = ip.iter();
+    let chr = |c: u8| {
+        Ok(ITMFrame::Instrumentation {
+            addr: 1,
+            data: c as u32,
+            len: 1,
+            ts: None,
+        })
+    };
+    assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(chr(b'A'), i.get_frame(&mut v));
+    assert_eq!(chr(b'B'), i.get_frame(&mut v));
+    assert_eq!(Ok(ITMFrame::Overflow { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(chr(b'C'), i.get_frame(&mut v));
+    assert_eq!(Err(ITMError::ShortData), i.get_frame(&mut v));
+
+    /* Without it, TPIU framing just loses sync */
+    let mut i = ITMDecoder::new(true);
+    let mut v = ip.iter();
+    assert_eq!(Ok(ITMFrame::TPIUSync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(Err(ITMError::ShortData), i.get_frame(&mut v));
+}
+
+#[test]
+fn test_emit_unsynced_bytes() {
+    let ip = [0x55, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x09, 0x41];
+
+    /* By default they're discarded */
+    let mut i = ITMDecoder::new(false);
+    let mut v = ip.iter();
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+
+    let mut i = ITMDecoder::new(false);
+    i.set_emit_unsynced_bytes(true);
+    let mut v = ip.iter();
+    for byte in [0x55, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00] {
+        assert_eq!(Ok(ITMFrame::Unsynced { byte }), i.get_frame(&mut v));
+    }
+    assert_eq!(Ok(ITMFrame::Sync { count: 1 }), i.get_frame(&mut v));
+    assert_eq!(
+        Ok(ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None
+        }),
+        i.get_frame(&mut v)
+    );
+    assert_eq!(2, i.stats().inpackets);
+}
+
+#[test]
+fn test_attach_timestamp() {
+    let ip = [
+        0x09, 0x41, // Before any timestamp
+        0x30, // Local timestamp of 3 ticks
+        0x09, 0x42, //
+        0xc0, 0x81, 0x01, // Local timestamp of 129 ticks
+        0x09, 0x43, //
+        0x09, 0x44, // Still the same time
+    ];
+    let chr = |c: u8, ts| ITMFrame::Instrumentation {
+        addr: 1,
+        data: c as u32,
+        len: 1,
+        ts,
+    };
+
+    let mut i = ITMDecoder::new(true);
+    i.set_attach_timestamp(true);
+    i.set_frame_filter(FrameMask::INSTRUMENTATION);
+    let mut v = ip.iter();
+    for f in [
+        chr(b'A', None),
+        chr(b'B', Some(3)),
+        chr(b'C', Some(132)),
+        chr(b'D', Some(132)),
+    ] {
+        assert_eq!(Ok(f), i.get_frame(&mut v));
+    }
+
+    /* By default nothing is attached */
+    let mut i = ITMDecoder::new(true);
+    i.set_frame_filter(FrameMask::INSTRUMENTATION);
+    let mut v = ip.iter();
+    for c in [b'A', b'B', b'C', b'D'] {
+        assert_eq!(Ok(chr(c, None)), i.get_frame(&mut v));
+    }
+}
+
+#[test]
+fn test_consecutive_syncs() {
+    let mut i = ITMDecoder::new(false);
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x01, 0x41, // Simple software source packet
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+    ];
+    let mut v = ip.iter();
+    for n in 1..=3 {
+        assert_eq!(Ok(ITMFrame::Sync { count: n }), i.get_frame(&mut v));
+    }
+    assert_eq!(3, i.stats().consecutive_syncs);
+
+    /* Anything else breaks the run */
+    assert!(matches!(
+        i.get_frame(&mut v),
+        Ok(ITMFrame::Instrumentation { .. })
+    ));
+    assert_eq!(0, i.stats().consecutive_syncs);
+    assert_eq!(Ok(ITMFrame::Sync { count: 4 }), i.get_frame(&mut v));
+    assert_eq!(1, i.stats().consecutive_syncs);
+    assert_eq!(4, i.stats().itmsync);
+}
+
+#[test]
+fn test_decode_complete() {
+    let ip = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+        0x09, 0x41, // Simple software source packet
+        0xc0, 0x81, 0x01, // Local timestamp of 129 ticks
+    ];
+    let frames = ITMDecoder::new(false).decode_complete(&ip).unwrap();
+    assert_eq!(
+        vec![
+            ITMFrame::Sync { count: 1 },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            },
+            ITMFrame::Timestamp {
+                ttype: TSType::Sync,
+                ts: 129,
+                overflow: false
+            },
+        ],
+        frames
+    );
+
+    /* Losing the end of the timestamp is caught */
+    assert_eq!(
+        Err(ITMError::ShortData),
+        ITMDecoder::new(false).decode_complete(&ip[..ip.len() - 1])
+    );
+}
+
+#[test]
+fn test_tolerant() {
+    let ip = [
+        0xc0, // Corrupt byte, taken as a local timestamp header
+        0x94, 0x81, 0x82, 0x83, 0x04, // Global timestamp
+        0x09, 0x41, // Simple software source packet
+    ];
+    let gts = ITMFrame::Globaltimestamp {
+        has_wrapped: false,
+        ts: 0x01 | 0x02 << 7 | 0x03 << 14 | 0x04 << 21,
+    };
+    let inst = ITMFrame::Instrumentation {
+        addr: 1,
+        data: 0x41,
+        len: 1,
+        ts: None,
+    };
+
+    /* Normally the timestamp swallows the global timestamp */
+    let frames = ITMDecoder::new(true).decode_complete(&ip).unwrap();
+    assert!(!frames.contains(&gts));
+
+    let mut i = ITMDecoder::new(true);
+    i.set_tolerant(true);
+    assert_eq!(vec![gts, inst], i.decode_complete(&ip).unwrap());
+    assert_eq!(1, i.stats().noise);
+    assert_eq!(ip.len() as u64, i.stats().inbytestotal);
+}
+
+#[test]
+fn test_scan_to_sync() {
+    /* Garbage without a run of zeros long enough to be a sync */
+    let mut seed = 0x1234_5678u32;
+    let mut ip: Vec<u8> = (0..1000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((seed >> 24) as u8).max(1)
+        })
+        .collect();
+    ip.extend(ITM_SYNC_SEQUENCE);
+    ip.extend([0x09, 0x41]);
+
+    let mut i = ITMDecoder::new(false);
+    assert_eq!(Some(1000), i.scan_to_sync(&ip));
+    assert_eq!(
+        vec![
+            ITMFrame::Sync { count: 1 },
+            ITMFrame::Instrumentation {
+                addr: 1,
+                data: 0x41,
+                len: 1,
+                ts: None
+            }
+        ],
+        i.decode_complete(&ip[1000..]).unwrap()
+    );
+    assert_eq!(0, i.stats().noise);
+    assert_eq!(None, i.scan_to_sync(&ip[..1000]));
+}
+
+#[test]
+fn test_checkpoint() {
+    #[rustfmt::skip]
+    let ip = [
+        0x09, 0x41,                   // Instrumentation
+        0xc0, 0x81, 0x02,             // Local timestamp
+        0x94, 0x81, 0x82, 0x83, 0x04, // Global timestamp 1
+        0x0b, 0x41, 0x42, 0x43, 0x44, // Instrumentation
+    ];
+    let frames = |i: &mut ITMDecoder, ip: &[u8]| {
+        let mut v = ip.iter();
+        std::iter::from_fn(|| i.get_frame(&mut v).ok()).collect::<Vec<_>>()
+    };
+
+    /* Checkpoint part way through the global timestamp */
+    let mut i = ITMDecoder::new(true);
+    i.set_attach_timestamp(true);
+    assert_eq!(2, frames(&mut i, &ip[..7]).len());
+    let cp = i.checkpoint();
+    let stats = i.stats().clone();
+
+    let rest = frames(&mut i, &ip[7..]);
+    assert_eq!(2, rest.len());
+    assert_ne!(stats, *i.stats());
+
+    i.restore(cp.clone());
+    assert_eq!(stats, *i.stats());
+    assert_eq!(rest, frames(&mut i, &ip[7..]));
+
+    /* A checkpoint can be restored into another decoder, and more than once */
+    let mut j = ITMDecoder::new(false);
+    j.restore(cp.clone());
+    assert_eq!(rest, frames(&mut j, &ip[7..]));
+    j.restore(cp);
+    assert_eq!(rest, frames(&mut j, &ip[7..]));
+}
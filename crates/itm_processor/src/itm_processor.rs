@@ -8,45 +8,183 @@ use inline_colorization::*;
 use itm::*;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn, LevelFilter};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::io::{self, Write};
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 #[path = "test_lib.rs"]
 mod test_lib;
 
 /// Number of ITM channels that will be considered
-pub const MAX_CHANNELS: usize = 32;
+///
+/// The 32 stimulus ports, on each of the 8 pages selectable by the page register.
+pub const MAX_CHANNELS: usize = 256;
 
 /// Trigger character for resetting time print
 pub const DEFAULT_TRIGGER_CHAR: char = '\n';
 
+/// Separator between channel number and format in a channel specification
+pub const CHANNEL_DELIMITER: char = ',';
+
+/// Unit suffixes accepted for a CPU frequency, with their multiplier to Hz
+const FREQ_UNITS: [(&str, f64); 3] = [("mhz", 1_000_000.0), ("khz", 1_000.0), ("hz", 1.0)];
+
+/// Time to wait for the second half of a 64-bit value before writing out the first alone
+pub const PAIR_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Largest delta a single local timestamp can carry, anything bigger came from a corrupt packet
+const MAX_TS_DELTA: u64 = (1 << 28) - 1;
+
+/// Default size at which buffered output is flushed
+pub const DEFAULT_FLUSH_THRESHOLD: usize = 8192;
+
+/// When output is passed on to the writer
+///
+/// With `Immediate` every substitution is written straight through, which for a terminal
+/// means a system call for every record. `Buffered` holds output until the trigger character
+/// is seen or `threshold` bytes have accumulated, so a flow of single character records
+/// makes one write to the output per line rather than one per record.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write every substitution through to the output as it is made
+    #[default]
+    Immediate,
+    /// Buffer output, flushing on the trigger character or when `threshold` bytes are held
+    Buffered { threshold: usize },
+}
+
+// Output sink, applying the flush policy
+struct Output {
+    w: Box<dyn std::io::Write>,    // Where output finally goes
+    policy: FlushPolicy,           // When to pass output on
+    buf: Vec<u8>,                  // Output held for writing
+    failed: Option<io::ErrorKind>, // First fatal error from the writer
+}
+
+impl Output {
+    // Pass on anything held in the buffer
+    fn drain(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let buf = mem::take(&mut self.buf);
+            let r = self.write_through(&buf);
+            self.buf = buf;
+            self.buf.clear();
+            r?;
+        }
+        Ok(())
+    }
+
+    // Write all of the data to the writer. A writer that would block is retried until it
+    // accepts it, anything else is fatal, and once failed nothing more is written.
+    fn write_through(&mut self, mut data: &[u8]) -> io::Result<()> {
+        if let Some(kind) = self.failed {
+            return Err(kind.into());
+        }
+        loop {
+            match self.w.write(data) {
+                Ok(n) if n == data.len() => return Ok(()),
+                Ok(0) => return Err(self.fail(io::ErrorKind::WriteZero.into())),
+                Ok(n) => data = &data[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(self.fail(e)),
+            }
+        }
+    }
+
+    // Record a fatal error from the writer
+    fn fail(&mut self, e: io::Error) -> io::Error {
+        warn!("Output failed: {}", e);
+        self.failed = Some(e.kind());
+        e
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self.policy {
+            FlushPolicy::Immediate => self.write_through(data).map(|_| data.len()),
+            FlushPolicy::Buffered { threshold } => {
+                self.buf.extend_from_slice(data);
+                if self.buf.len() >= threshold {
+                    self.drain()?;
+                }
+                Ok(data.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.drain()?;
+        if self.failed.is_some() {
+            return Ok(());
+        }
+        self.w.flush().map_err(|e| self.fail(e))
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Callback for frames the processor doesn't render, see [`ITMProcessor::set_unhandled_hook`]
+pub type UnhandledHook = Box<dyn FnMut(&ITMFrame)>;
+
 /// Main object for the ITM processor
 //#[derive(Debug, Clone)]
 pub struct ITMProcessor {
     ac: AhoCorasick, // Substitution engine
 
     exlist: HashSet<i32>, // List of exceptions and ints to be considered
+    irq_names: HashMap<u16, String>, // Names for interrupts, by interrupt number
+
+    trigger: Vec<u8>, // Byte sequence to be used for time output trigger
+    recent: Vec<u8>,  // Latest instrumentation bytes, to match against the trigger
+    storing: bool,    // am I currently storing a time?
+    armed: bool,      // Waiting for a time
 
-    trigger: char, // Character to be used for time output trigger
-    storing: bool, // am I currently storing a time?
-    armed: bool,   // Waiting for a time
+    channel_prefix: bool,     // Prefix output with the channel number
+    raw_radix: Option<Radix>, // How channels without a format are shown, if they are
+    last_chan: Option<u8>,    // Channel that output was last written for
+
+    pc_bucket: Option<u32>,      // Size of PC histogram buckets, if collecting
+    pc_hist: BTreeMap<u32, u64>, // PC sample counts, by bucket start address
+
+    unhandled: Option<UnhandledHook>, // Called with frames that aren't rendered
+    totals: Totals,                   // Running counts for the summary
+    pending: Option<Pending>,         // Half of a 64-bit value awaiting its partner
+    clock: fn() -> Instant,           // Time source for the pair timeout
+    lines: Option<Vec<String>>,       // Partial line for each channel, when line buffering
+    line_text: Option<Vec<Vec<u8>>>,  // Characters of each channel's line, when formatting per line
+    ex_trace: Option<ExceptionTrace>, // Exceptions are written as a Chrome trace, if set
+    ex_timing: ExceptionTimer,        // How long each exception has run for
+    ring: Option<Ring>,               // Latest lines of each channel, if they're kept
 
     channel: ChanSpec, // The individual channels
     t: TimeTrack,      // Timestamp records for deltas
-    output: Box<dyn std::io::Write>,
+    output: Output,    // Where formatted output is sent
 }
 
 /// Substitutions that can be made into the pattern string & descriptions of them
-// because of the for loop below, ensure this remains an even number.
-pub const PATTERNS: [&str; 10] = [
-    "{char}", "\\n", "\\t", "\\a", "{x08}", "{x04}", "{x02}", "{i32}", "{u32}", "{unic}",
+pub const PATTERNS: [&str; 13] = [
+    "{char}", "\\n", "\\t", "\\a", "{x08}", "{x04}", "{x02}", "{i32}", "{u32}", "{unic}", "{f32}",
+    "{u64}", "{x016}",
 ];
 
 /// Convinience indicator that special case of CHAR is held in 0'th index
 const IS_8BIT_CHAR: u64 = 1 << 0;
 
+/// Substitutions that need a 64-bit value, assembled from a pair of packets
+const IS_64BIT: u64 = (1 << 11) | (1 << 12);
+
 /// Textual descriptions of what each string substitution represents (align with PATTERNS)
-pub const DESCRIPTION: [&str; 10] = [
+pub const DESCRIPTION: [&str; 13] = [
     "Legacy 8-bit character",
     "New Line",
     "Tab",
@@ -57,6 +195,9 @@ pub const DESCRIPTION: [&str; 10] = [
     "32-bit signed integer",
     "32-bit unsigned integer",
     "Unicode character",
+    "32-bit float",
+    "64-bit unsigned pair",
+    "64-bit hex pair",
 ];
 
 /// Types of timestamp that can be applied to ITM data
@@ -80,17 +221,217 @@ pub enum HandleAs {
     #[default]
     None,
     Normal,
+    /// No format, the bytes are shown as they are, see [`ITMProcessor::set_raw_radix`]
+    Raw,
+}
+
+/// How the bytes of a raw channel are shown
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    /// Two hex digits per byte
+    Hex,
+    /// Decimal value of each byte
+    Dec,
+    /// Eight binary digits per byte
+    Bin,
 }
 
-/// Definition for channel behaviours
+/// Definition for channel behaviours, indexed by channel number
+///
+/// Arrays this long have no `Default`, so start from [`empty_chan_spec`], or get one from
+/// [`parse_channel_spec`].
 pub type ChanSpec = [Chan; MAX_CHANNELS];
 
+/// A channel specification with no channel set up
+///
+/// # Example
+/// ```
+/// use itm_processor::{empty_chan_spec, MAX_CHANNELS};
+/// let c = empty_chan_spec();
+/// assert_eq!(MAX_CHANNELS, c.len());
+/// assert!(c.iter().all(|ch| ch.fmt.is_none()));
+/// ```
+pub fn empty_chan_spec() -> ChanSpec {
+    std::array::from_fn(|_| Chan::default())
+}
+
+/// Errors from parsing a channel specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelSpecError {
+    /// Specification isn't of the form channel,"format"
+    BadFormat(String),
+    /// Channel number could not be identified
+    BadChannel(String),
+    /// Channel number is beyond the supported range
+    OutOfRange(usize),
+    /// No format was provided for the channel
+    EmptyFormat(usize),
+    /// Channel was specified more than once
+    Duplicate(usize),
+}
+
+impl fmt::Display for ChannelSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelSpecError::BadFormat(x) => write!(
+                f,
+                "Badly formed channel expression [{}], should be [channel,\"format\"]",
+                x
+            ),
+            ChannelSpecError::BadChannel(x) => write!(f, "Cannot identify channel in [{}]", x),
+            ChannelSpecError::OutOfRange(x) => {
+                write!(f, "Channel {} out of range 0..{}", x, MAX_CHANNELS - 1)
+            }
+            ChannelSpecError::EmptyFormat(x) => write!(f, "Empty format for channel {}", x),
+            ChannelSpecError::Duplicate(x) => write!(f, "Channel {} specified more than once", x),
+        }
+    }
+}
+
+impl std::error::Error for ChannelSpecError {}
+
 /// Structure for a single ITM channel
 #[derive(Debug, Default, Clone)]
 pub struct Chan {
-    pub fmt: Option<String>, // Format for the channel
-    pub active: u64,         // Translations that are active
-    pub handling: HandleAs,  // Fast-flag if this should be handled as chars
+    pub fmt: Option<String>,          // Format for the channel
+    pub active: u64,                  // Translations that are active
+    pub handling: HandleAs,           // Fast-flag if this should be handled as chars
+    pub transform: Option<Transform>, // Conversion to engineering units
+    pub pair: Option<PairOrder>,      // Assemble 64-bit values from pairs of packets
+}
+
+/// Order in which the halves of a 64-bit value are written to the stimulus port
+///
+/// A channel using `{u64}` or `{x016}` combines each two consecutive 4-byte packets into one
+/// value. By default the low half is expected first, which is how a little-endian target
+/// naturally writes out a 64-bit quantity.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PairOrder {
+    /// Least significant 32 bits are written first
+    #[default]
+    LowFirst,
+    /// Most significant 32 bits are written first
+    HighFirst,
+}
+
+// First half of a 64-bit value, waiting for the second
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    addr: u8,    // Channel it arrived on
+    data: u32,   // Value of this half
+    at: Instant, // When it arrived, for the timeout
+}
+
+/// Affine transform applied to the value of a channel before numeric substitution
+///
+/// When set, the channel is taken to carry a raw (signed for `{i32}`, otherwise unsigned)
+/// integer count, which is shown as `value * scale + offset`. `{i32}` and `{u32}` are
+/// rounded to the nearest integer, `{f32}` is shown as is. Character and hex substitutions
+/// always show the raw value.
+///
+/// # Example
+/// ```
+/// use itm_processor::Transform;
+/// let t = Transform { scale: 0.5, offset: -10.0 };
+/// assert_eq!(t.apply(67.0), 23.5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub scale: f64,  // Multiplier for the raw value
+    pub offset: f64, // Added after scaling
+}
+
+impl Transform {
+    /// Convert a raw value into engineering units
+    pub fn apply(&self, v: f64) -> f64 {
+        v * self.scale + self.offset
+    }
+}
+
+/// Running counts of what has been processed, for the summary report
+#[derive(Default)]
+struct Totals {
+    frames: u64,             // All frames
+    chan: BTreeMap<u8, u64>, // Instrumentation frames, by channel
+    exceptions: u64,         // Exception and interrupt frames
+    overflow: u64,           // ITM overflow frames
+    ts_overflow: u64,        // Local timestamps where the target counter overflowed
+}
+
+/// How long an exception has run for, in target ticks
+///
+/// Durations are from entry to exit, less any time spent in exceptions that preempted it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionTiming {
+    /// Number of times it has run to completion
+    pub count: u64,
+    /// Total time it has run for
+    pub total: u64,
+    /// Shortest run
+    pub min: u64,
+    /// Longest run
+    pub max: u64,
+    /// Most recent run
+    pub last: u64,
+}
+
+impl ExceptionTiming {
+    // Add a completed run
+    fn record(&mut self, d: u64) {
+        self.min = if self.count == 0 { d } else { self.min.min(d) };
+        self.max = self.max.max(d);
+        self.total = self.total.saturating_add(d);
+        self.last = d;
+        self.count += 1;
+    }
+}
+
+// An exception that has been entered and not yet exited
+#[derive(Debug)]
+struct OpenException {
+    no: u16,        // Exception number
+    start: u64,     // Target time it was entered
+    preempted: u64, // Time spent in exceptions that preempted it
+}
+
+// Exception run times, with those currently running as a stack
+#[derive(Debug, Default)]
+struct ExceptionTimer {
+    open: Vec<OpenException>,               // Innermost last
+    timing: BTreeMap<u16, ExceptionTiming>, // Completed runs, by exception number
+}
+
+// Exceptions being written out as a Chrome trace
+#[derive(Debug, Default)]
+struct ExceptionTrace {
+    open: Vec<u16>, // Exceptions that have been entered and not exited, innermost last
+    events: u64,    // Number of events written
+}
+
+// Most recent complete lines of each channel, for viewers that show the latest of each
+#[derive(Debug)]
+struct Ring {
+    capacity: usize,              // Lines kept for each channel
+    lines: Vec<VecDeque<String>>, // Complete lines of each channel, oldest first
+    partial: Vec<String>,         // Line being built for each channel
+}
+
+impl Ring {
+    // Add formatted text for a channel, keeping the line if it's now complete
+    fn add(&mut self, addr: u8, text: &str, complete: bool) {
+        let partial = &mut self.partial[addr as usize];
+        partial.push_str(text);
+        if complete {
+            let line = partial.trim_end_matches(['\r', '\n']).to_string();
+            partial.clear();
+            let lines = &mut self.lines[addr as usize];
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
 }
 
 // Timing related data for running process
@@ -98,10 +439,159 @@ pub struct Chan {
 struct TimeTrack {
     interval: IntervalType,          // Type of intervals to be reported
     donefirst: bool,                 // Is this the first iteration?
-    cpu_freq_div: usize,             // CPU frequency divider
+    cpu_freq_div: usize,             // CPU frequency in KHz (1 for ticks)
     time: u64,                       // Latest calculated time from target
     old_time: u64,                   // Last time delta start
+    synthetic: Option<u64>,          // Ticks between PC samples, when timing from them
     old_dt: chrono::DateTime<Local>, // Host-side timing
+    arrival: Option<SystemTime>,     // Host time the frame being handled arrived, if known
+}
+
+/// Parse a CPU frequency into the KHz divider used for target time
+///
+/// Accepts a bare value in Hz (e.g. `168000000`) or a value with a `Hz`, `KHz` or `MHz`
+/// suffix (e.g. `72000KHz` or `168MHz`, case insensitive). The frequency is that of the
+/// (scaled) timestamp clock. A divider of 1 stands for showing raw ticks, so a frequency
+/// that rounds to 1KHz is rejected rather than being mistaken for it.
+///
+/// # Example
+/// ```
+/// use itm_processor::parse_cpu_freq;
+/// assert_eq!(Ok(168000), parse_cpu_freq("168MHz"));
+/// assert_eq!(Ok(72000), parse_cpu_freq("72000KHz"));
+/// assert_eq!(Ok(168000), parse_cpu_freq("168000000"));
+/// ```
+pub fn parse_cpu_freq(s: &str) -> Result<usize, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let (num, mult) = FREQ_UNITS
+        .iter()
+        .find_map(|(suffix, mult)| lower.strip_suffix(suffix).map(|n| (n, *mult)))
+        .unwrap_or((lower.as_str(), 1.0));
+
+    let v: f64 = match num.trim().parse() {
+        Ok(x) => x,
+        Err(_) => return Err(format!("Cannot identify frequency in [{}]", s)),
+    };
+    if !v.is_finite() || v <= 0.0 {
+        return Err(format!("Frequency [{}] must be positive", s));
+    }
+
+    let khz = (v * mult / 1000.0).round();
+    if khz < 2.0 {
+        return Err(format!("Frequency [{}] must be at least 2KHz", s));
+    }
+    Ok(khz as usize)
+}
+
+/// Parse a trigger sequence given on the command line
+///
+/// A single character is the trigger on its own, as the trigger character has always been.
+/// Anything longer is a sequence of bytes, where `\n`, `\r`, `\t`, `\0`, `\\` and `\xHH`
+/// escapes give the bytes that can't easily be typed, so `\r\n` triggers on a CR+LF pair.
+///
+/// # Example
+/// ```
+/// use itm_processor::parse_trigger;
+/// assert_eq!(Ok(vec![b'\n']), parse_trigger("\n"));
+/// assert_eq!(Ok(vec![0x0d, 0x0a]), parse_trigger("\\r\\n"));
+/// assert_eq!(Ok(vec![0x00, b'!']), parse_trigger("\\x00!"));
+/// ```
+pub fn parse_trigger(s: &str) -> Result<Vec<u8>, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => return Err("Trigger cannot be empty".to_string()),
+        (Some(c), None) => return Ok(trigger_bytes(c)),
+        _ => (),
+    }
+
+    let mut seq = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut b = [0u8; 4];
+            seq.extend_from_slice(c.encode_utf8(&mut b).as_bytes());
+            continue;
+        }
+        seq.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) if hex.len() == 2 => b,
+                    _ => return Err(format!("Bad hex escape in trigger [{}]", s)),
+                }
+            }
+            _ => return Err(format!("Unknown escape in trigger [{}]", s)),
+        });
+    }
+    Ok(seq)
+}
+
+// A trigger character as the byte(s) to match. Instrumentation bytes were always compared
+// with the character directly, so one that fits in a byte is that byte.
+fn trigger_bytes(c: char) -> Vec<u8> {
+    match u8::try_from(c) {
+        Ok(b) => vec![b],
+        Err(_) => c.to_string().into_bytes(),
+    }
+}
+
+/// Parse channel specifications into the formats for each channel
+///
+/// Each specification is of the form `channel,"format"`, where the format may contain any of
+/// the substitutions in [`PATTERNS`]. No state is changed, so this can also be used to
+/// validate a set of specifications before they are used.
+///
+/// # Example
+/// ```
+/// use itm_processor::parse_channel_spec;
+/// let c = parse_channel_spec(&["1,{char}".to_string()]).unwrap();
+/// assert_eq!(Some("{char}".to_string()), c[1].fmt);
+/// ```
+pub fn parse_channel_spec(specs: &[String]) -> Result<ChanSpec, ChannelSpecError> {
+    let mut channel = empty_chan_spec();
+
+    for ip in specs {
+        let parts: Vec<&str> = ip.split(CHANNEL_DELIMITER).collect();
+        /* Always expect a channel and format */
+        if 2 != parts.len() {
+            return Err(ChannelSpecError::BadFormat(ip.clone()));
+        }
+        /* Grab the channel number */
+        let ch: usize = match parts[0].trim().parse() {
+            Ok(x) => x,
+            Err(_) => return Err(ChannelSpecError::BadChannel(parts[0].to_string())),
+        };
+        if ch >= MAX_CHANNELS {
+            return Err(ChannelSpecError::OutOfRange(ch));
+        }
+        if parts[1].is_empty() {
+            return Err(ChannelSpecError::EmptyFormat(ch));
+        }
+        if channel[ch].fmt.is_some() {
+            return Err(ChannelSpecError::Duplicate(ch));
+        }
+        /* Identify the active translations that are needed */
+        let mut active: u64 = 0;
+        for (x, p) in PATTERNS.iter().enumerate() {
+            if parts[1].contains(p) {
+                active |= 1 << x;
+            }
+        }
+        /* Now load the format into the channel */
+        channel[ch] = Chan {
+            fmt: Some(parts[1].to_string()),
+            active,
+            handling: HandleAs::Normal,
+            transform: None,
+            pair: (active & IS_64BIT != 0).then_some(PairOrder::LowFirst),
+        };
+    }
+    Ok(channel)
 }
 
 // Names for system exceptions
@@ -140,25 +630,499 @@ impl ITMProcessor {
     ) -> ITMProcessor {
         ITMProcessor {
             ac: AhoCorasick::new(PATTERNS).unwrap(),
-            trigger,
+            trigger: trigger_bytes(trigger),
+            recent: Vec::new(),
             exlist,
+            irq_names: HashMap::new(),
             storing: false,
             armed: false,
+            channel_prefix: false,
+            raw_radix: None,
+            last_chan: None,
+            pc_bucket: None,
+            pc_hist: BTreeMap::new(),
+            unhandled: None,
+            totals: Totals::default(),
+            pending: None,
+            clock: Instant::now,
+            lines: None,
+            line_text: None,
+            ex_trace: None,
+            ex_timing: ExceptionTimer::default(),
+            ring: None,
             channel,
-            output: Box::new(output),
+            output: Output {
+                w: Box::new(output),
+                policy: FlushPolicy::Immediate,
+                buf: Vec::new(),
+                failed: None,
+            },
             t: TimeTrack {
                 interval,
                 cpu_freq_div,
                 old_dt: Local::now(),
+                arrival: None,
                 donefirst: false,
                 time: 0,
                 old_time: 0,
+                synthetic: None,
             },
         }
     }
 
+    /// Set the byte sequence that triggers the time output
+    ///
+    /// The trigger given to [`ITMProcessor::new`] is a single character. This allows any
+    /// sequence of bytes instead, for targets that end their lines with something else (e.g.
+    /// CR+LF, or a 0x00). Bytes are matched across the instrumentation stream as a whole, and
+    /// the trigger happens on the last byte of the sequence. An empty sequence never triggers.
+    ///
+    pub fn set_trigger(&mut self, trigger: Vec<u8>) {
+        self.trigger = trigger;
+        self.recent.clear();
+    }
+
+    /// Prefix instrumentation output with the channel number
+    ///
+    /// When set, output from each channel is prefixed by `[ch NN]`. The prefix follows the
+    /// time column at the start of each line, and is repeated if a different channel
+    /// writes before the line is complete.
+    ///
+    pub fn set_channel_prefix(&mut self, prefix: bool) {
+        self.channel_prefix = prefix;
+    }
+
+    /// Show instrumentation on channels without a format as raw bytes
+    ///
+    /// Normally a channel without a format is ignored. When a radix is set, each packet on such
+    /// a channel is shown on a line of its own, as `[ch NN]` followed by the bytes of the
+    /// packet in that radix. Channels with a format are not affected. `None` (the default)
+    /// ignores them again.
+    ///
+    pub fn set_raw_radix(&mut self, radix: Option<Radix>) {
+        self.raw_radix = radix;
+        for c in self.channel.iter_mut().filter(|c| c.fmt.is_none()) {
+            c.handling = match radix {
+                Some(_) => HandleAs::Raw,
+                None => HandleAs::None,
+            };
+        }
+    }
+
+    /// Hold each channel's output until its line is complete
+    ///
+    /// Channels that write characters without a break otherwise come out interleaved on the
+    /// same line. With line buffering each channel builds its own line, including the time and
+    /// channel prefix, and it is written out whole when the trigger character arrives. Lines
+    /// still in progress are written out when this is turned off and when the processor is
+    /// dropped, see [`ITMProcessor::flush_lines`].
+    ///
+    pub fn set_line_buffering(&mut self, on: bool) {
+        if !on {
+            self.flush_lines();
+        }
+        self.lines = on.then(|| {
+            self.lines
+                .take()
+                .unwrap_or_else(|| vec![String::new(); MAX_CHANNELS])
+        });
+    }
+
+    /// Apply the format to whole lines rather than to each character
+    ///
+    /// Normally the format of a `{char}` channel is applied to every character, so a format
+    /// of `"> {char}"` puts `> ` in front of each of them. With this set, characters are
+    /// gathered until the trigger and the format is applied once to the whole line, with
+    /// `{char}` standing for the line less the trigger itself. The format decides how the line
+    /// ends, so it will usually end in `\n`. This is also cheaper, as each line is formatted
+    /// once. Other substitutions in the format have no single value for a line, and show as
+    /// zero. Lines still being gathered are written out when this is turned off and when the
+    /// processor is dropped, see [`ITMProcessor::flush_line_text`].
+    ///
+    /// # Example
+    /// ```
+    /// use itm_processor::{parse_channel_spec, IntervalType, ITMProcessor};
+    /// let chans = parse_channel_spec(&["1,> {char}\\n".to_string()]).unwrap();
+    /// let mut p = ITMProcessor::new(
+    ///     '\n',
+    ///     IntervalType::None,
+    ///     1,
+    ///     Default::default(),
+    ///     chans,
+    ///     std::io::sink(),
+    /// );
+    /// p.set_format_per_line(true);
+    /// ```
+    ///
+    pub fn set_format_per_line(&mut self, on: bool) {
+        if !on {
+            self.flush_line_text();
+        }
+        self.line_text = on.then(|| {
+            self.line_text
+                .take()
+                .unwrap_or_else(|| vec![Vec::new(); MAX_CHANNELS])
+        });
+    }
+
+    /// Keep the latest `n` lines of each channel
+    ///
+    /// For interactive viewers, which show the most recent lines of each channel rather than a
+    /// scrolling log. Each complete line of formatted output (ended by the trigger, without
+    /// the time, channel prefix or line ending) is kept against its channel, and once a channel
+    /// has `n` the oldest is dropped. They are read back with [`ITMProcessor::channel_lines`].
+    /// Output is still written as normal, so give the processor `std::io::sink()` if the lines
+    /// are all that's wanted. Setting a capacity starts again with no lines, and 0 (the
+    /// default) stops keeping them.
+    ///
+    pub fn set_ring_capacity(&mut self, n: usize) {
+        self.ring = (n != 0).then(|| Ring {
+            capacity: n,
+            lines: vec![VecDeque::with_capacity(n); MAX_CHANNELS],
+            partial: vec![String::new(); MAX_CHANNELS],
+        });
+    }
+
+    /// The latest complete lines of a channel, oldest first, see [`ITMProcessor::set_ring_capacity`]
+    pub fn channel_lines(&self, ch: u8) -> impl DoubleEndedIterator<Item = &str> + '_ {
+        self.ring
+            .iter()
+            .flat_map(move |r| r.lines[ch as usize].iter().map(String::as_str))
+    }
+
+    /// Write out any lines still being built by line buffering, in channel order
+    pub fn flush_lines(&mut self) {
+        if let Some(lines) = self.lines.as_mut() {
+            for line in lines.iter_mut().filter(|l| !l.is_empty()) {
+                let _ = self.output.write(line.as_bytes());
+                line.clear();
+            }
+        }
+    }
+
+    /// Set when output is passed on to the writer
+    ///
+    /// Any output already buffered is written out before the new policy takes effect.
+    ///
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        let _ = self.output.drain();
+        self.output.policy = policy;
+    }
+
+    /// Write out any buffered output, and flush the writer
+    ///
+    /// The processor never flushes of its own accord (other than as the [`FlushPolicy`]
+    /// requires, and when it's dropped), so the caller decides when output must be seen, e.g.
+    /// after each read of the source (see [`collector::FrameHandler::read_done`]) or on
+    /// shutdown. Lines still being built by line buffering
+    /// are written out as they stand.
+    ///
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flush_lines();
+        self.output.flush()
+    }
+
+    /// Return the kind of error that stopped output, if it has stopped
+    ///
+    /// A writer that would block is retried until it accepts the output. Any other error
+    /// (e.g. a broken pipe to a pager that has exited) is fatal; nothing more is written and
+    /// [`collector::FrameHandler::process`] returns false so the layer above can stop.
+    ///
+    pub fn output_failed(&self) -> Option<io::ErrorKind> {
+        self.output.failed
+    }
+
+    /// Collect PC samples into a histogram by address range
+    ///
+    /// Each PC sample is counted in the bucket of `bucket_size` bytes that contains it. This
+    /// gives a coarse profile without needing symbols. The histogram is written out by
+    /// [`ITMProcessor::flush_histogram`], and when the processor is dropped. A size of 0 stops
+    /// collection, and any samples so far are discarded.
+    ///
+    pub fn set_pc_histogram(&mut self, bucket_size: u32) {
+        self.pc_bucket = (bucket_size != 0).then_some(bucket_size);
+        self.pc_hist.clear();
+    }
+
+    /// Advance target time by `ticks` for every PC sample
+    ///
+    /// Configurations that don't emit local timestamps can still get usable timing when PC
+    /// samples are taken at a known interval, as set by the DWT cycle count tap. Each PC
+    /// sample (including a sleep sample) then adds the interval to the target time, alongside
+    /// any timestamps that do arrive. An interval of 0 stops this.
+    ///
+    pub fn set_synthetic_interval(&mut self, ticks: u64) {
+        self.t.synthetic = (ticks != 0).then_some(ticks);
+    }
+
+    /// Write out the PC sample histogram, then start a new one
+    ///
+    /// Each line gives the address range of a bucket, the number of samples that fell in it,
+    /// and the percentage of all samples that represents. Empty buckets are not shown. While
+    /// an exception trace is being written nothing is written, as it would corrupt the JSON,
+    /// and the samples are kept until the trace is turned off.
+    ///
+    pub fn flush_histogram(&mut self) {
+        if self.ex_trace.is_some() {
+            if !self.pc_hist.is_empty() {
+                warn!("PC histogram not written during an exception trace");
+            }
+            return;
+        }
+        let total: u64 = self.pc_hist.values().sum();
+        if let (Some(size), true) = (self.pc_bucket, total != 0) {
+            for (base, count) in &self.pc_hist {
+                let _ = writeln!(
+                    self.output,
+                    "0x{:08x}-0x{:08x} {:10} {:6.2}%",
+                    base,
+                    base.saturating_add(size - 1),
+                    count,
+                    *count as f64 * 100.0 / total as f64
+                );
+            }
+            let _ = self.output.flush();
+        }
+        self.pc_hist.clear();
+    }
+
+    /// Name interrupts, so they are reported by name rather than number
+    ///
+    /// The names are keyed by interrupt number (i.e. exception number - 16), as used by the
+    /// `IRQn` values of a CMSIS device header, so a name table from the BSP or SVD can be used
+    /// directly. Interrupts without a name are still reported by number.
+    ///
+    pub fn set_irq_names(&mut self, names: HashMap<u16, String>) {
+        self.irq_names = names;
+    }
+
+    // Name of an interrupt, or its number if it doesn't have one
+    fn irq_name(names: &HashMap<u16, String>, irq: u16) -> String {
+        names.get(&irq).cloned().unwrap_or_else(|| irq.to_string())
+    }
+
+    /// Set a hook to be called with each frame the processor doesn't render
+    ///
+    /// Frames such as data trace or PMU overflow are otherwise dropped. The hook sees them so
+    /// they can be handled elsewhere (e.g. forwarded to a separate analyser). PC samples are
+    /// passed to the hook unless a PC histogram is being collected.
+    ///
+    pub fn set_unhandled_hook(&mut self, hook: UnhandledHook) {
+        self.unhandled = Some(hook);
+    }
+
+    /// Write exceptions out as a Chrome trace, for viewing in Perfetto or chrome://tracing
+    ///
+    /// Each exception entry and exit (for exceptions selected for reporting) becomes a begin
+    /// or end event in Trace Event Format JSON, at the target time, so each exception shows as
+    /// a slice with any that preempted it nested inside. Times are in µs if a CPU frequency is
+    /// set, otherwise they are in ticks. Instrumentation still held back (lines being built,
+    /// or half of a 64-bit value) is written out before the trace starts, and instrumentation
+    /// is dropped while the trace is on, as it would corrupt the JSON.
+    ///
+    /// An exit without an entry (e.g. from an exception already running when the capture
+    /// started) is skipped. An exit from an exception that was preempted closes anything still
+    /// open inside it. When the trace is turned off, or the processor is dropped, anything
+    /// still open is closed and the JSON is completed. A PC histogram is not written while
+    /// the trace is on, see [`ITMProcessor::flush_histogram`].
+    ///
+    pub fn set_exception_trace(&mut self, on: bool) {
+        if on {
+            if self.ex_trace.is_none() {
+                self.flush_partial();
+                self.flush_line_text();
+                self.flush_lines();
+                let _ = self.output.write(b"[");
+                self.ex_trace = Some(ExceptionTrace::default());
+            }
+        } else if let Some(mut tr) = self.ex_trace.take() {
+            while let Some(no) = tr.open.pop() {
+                self.trace_event(&mut tr, no, "E");
+            }
+            let _ = self.output.write(b"\n]\n");
+            let _ = self.output.flush();
+        }
+    }
+
+    // Record an exception event in the Chrome trace
+    fn trace_exception(&mut self, no: u16, event: ExceptionEvent) {
+        let Some(mut tr) = self.ex_trace.take() else {
+            return;
+        };
+        match event {
+            ExceptionEvent::Entry => {
+                tr.open.push(no);
+                self.trace_event(&mut tr, no, "B");
+            }
+            ExceptionEvent::Exit if tr.open.contains(&no) => {
+                while let Some(n) = tr.open.pop() {
+                    self.trace_event(&mut tr, n, "E");
+                    if n == no {
+                        break;
+                    }
+                }
+            }
+            ExceptionEvent::Exit => debug!("Exit from {} without entry", no),
+            _ => (),
+        }
+        self.ex_trace = Some(tr);
+    }
+
+    // Write a single Chrome trace event
+    fn trace_event(&mut self, tr: &mut ExceptionTrace, no: u16, ph: &str) {
+        let name = if no < 16 {
+            EXNAMES[no as usize].to_string()
+        } else {
+            format!("IRQ {}", ITMProcessor::irq_name(&self.irq_names, no - 16))
+        };
+        let ts = if self.t.cpu_freq_div != 1 {
+            self.t.time as f64 * 1000.0 / self.t.cpu_freq_div as f64
+        } else {
+            self.t.time as f64
+        };
+        let _ = write!(
+            self.output,
+            "{}\n{{\"name\":\"{}\",\"ph\":\"{}\",\"ts\":{},\"pid\":1,\"tid\":1}}",
+            if tr.events == 0 { "" } else { "," },
+            ITMProcessor::json_escape(&name),
+            ph,
+            ts
+        );
+        tr.events += 1;
+    }
+
+    // Make a string safe to put between quotes in JSON, names from a BSP can contain anything
+    fn json_escape(s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// Target time reconstructed from the timestamps seen so far, in ticks
+    pub fn current_target_time(&self) -> u64 {
+        self.t.time
+    }
+
+    /// Restart target time from zero
+    ///
+    /// For when the target's timestamp counter is known to have restarted, such as across a
+    /// target reset, so target time and the deltas between records start again from here.
+    /// The host side reference for deltas is also reset to now.
+    ///
+    pub fn reset_time_base(&mut self) {
+        self.t.time = 0;
+        self.t.old_time = 0;
+        self.t.old_dt = Local::now();
+    }
+
+    /// How long each exception has run for, by exception number
+    ///
+    /// Timing is kept for every exception, whether or not it's selected for reporting, from
+    /// the target time of its entry and exit events. Exceptions are treated as a stack, so
+    /// time spent in one that preempted another is not counted against the one it preempted.
+    /// An exit without an entry is ignored, and an exit from an exception that was preempted
+    /// also ends anything still open inside it. Runs that haven't finished aren't included.
+    ///
+    pub fn exception_stats(&self) -> Vec<(u16, ExceptionTiming)> {
+        self.ex_timing
+            .timing
+            .iter()
+            .map(|(no, t)| (*no, *t))
+            .collect()
+    }
+
+    // Keep the run time of exceptions
+    fn time_exception(&mut self, no: u16, event: &ExceptionEvent) {
+        let now = self.t.time;
+        let ex = &mut self.ex_timing;
+        match event {
+            ExceptionEvent::Entry => ex.open.push(OpenException {
+                no,
+                start: now,
+                preempted: 0,
+            }),
+            ExceptionEvent::Exit if ex.open.iter().any(|o| o.no == no) => {
+                while let Some(o) = ex.open.pop() {
+                    let elapsed = now.saturating_sub(o.start);
+                    ex.timing
+                        .entry(o.no)
+                        .or_default()
+                        .record(elapsed.saturating_sub(o.preempted));
+                    if let Some(outer) = ex.open.last_mut() {
+                        outer.preempted = outer.preempted.saturating_add(elapsed);
+                    }
+                    if o.no == no {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Report of what has been processed so far
+    ///
+    /// Gives the total number of frames, instrumentation frames per channel (for channels that
+    /// have seen any), exception and overflow counts, and the target time elapsed. The target
+    /// time is in ticks, or seconds if a CPU frequency was set. It is a lower bound if any
+    /// local timestamp overflowed.
+    ///
+    pub fn summary(&self) -> String {
+        let tot = &self.totals;
+        let mut r = format!("Frames: {}\n", tot.frames);
+        for (ch, n) in &tot.chan {
+            r += &format!("Channel {:02}: {}\n", ch, n);
+        }
+        r += &format!("Exceptions: {}\n", tot.exceptions);
+        r += &format!("Overflows: {}\n", tot.overflow);
+        let bound = if tot.ts_overflow != 0 {
+            " (at least)"
+        } else {
+            ""
+        };
+        if self.t.cpu_freq_div != 1 {
+            let d = self.t.time.saturating_mul(1000) / self.t.cpu_freq_div as u64;
+            r += &format!(
+                "Target time: {}.{:03}_{:03}s{}\n",
+                d / 1000000,
+                (d / 1000) % 1000,
+                d % 1000,
+                bound
+            );
+        } else {
+            r += &format!("Target time: {} ticks{}\n", self.t.time, bound);
+        }
+        r
+    }
+
+    // Keep the counts reported by summary()
+    fn count(&mut self, i: &ITMFrame) {
+        self.totals.frames += 1;
+        match *i {
+            ITMFrame::Instrumentation { addr, .. } => {
+                *self.totals.chan.entry(addr).or_default() += 1
+            }
+            ITMFrame::Exception { .. } => self.totals.exceptions += 1,
+            ITMFrame::Overflow { .. } => self.totals.overflow += 1,
+            ITMFrame::Timestamp { overflow: true, .. } => self.totals.ts_overflow += 1,
+            _ => (),
+        }
+    }
+
     // Evaluate exception/interrupt and produce record
-    fn check_exception(t: &mut TimeTrack, no: u16, event: ExceptionEvent) -> String {
+    fn check_exception(
+        t: &mut TimeTrack,
+        names: &HashMap<u16, String>,
+        no: u16,
+        event: ExceptionEvent,
+    ) -> String {
         if no < 16 {
             format!(
                 "{}{color_bright_blue}EXCEPTION {} {}{color_reset}",
@@ -170,12 +1134,17 @@ impl ITMProcessor {
             format!(
                 "{}{color_bright_blue}INTERRUPT {} {}{color_reset}",
                 ITMProcessor::check_time_trigger(t),
-                no as usize - 16,
+                ITMProcessor::irq_name(names, no - 16),
                 EXEVENT[event as usize],
             )
         }
     }
 
+    // Host time for the frame being handled; when it arrived if that's known, otherwise now
+    fn host_now(t: &TimeTrack) -> chrono::DateTime<Local> {
+        t.arrival.map_or_else(Local::now, chrono::DateTime::from)
+    }
+
     // Check if time trigger occured, and output formatted time record if appropriate
     fn check_time_trigger(t: &mut TimeTrack) -> String {
         let mut r = String::new();
@@ -184,7 +1153,7 @@ impl ITMProcessor {
             // -------------------------------------------------------------------------
             // === Absolute System local time and date
             IntervalType::Absolute => {
-                let dt = Local::now();
+                let dt = ITMProcessor::host_now(t);
                 r = format!(
                     "{color_bright_yellow}{}|{color_reset}",
                     dt.format("%Y-%m-%d %H:%M:%S%.3f")
@@ -196,7 +1165,7 @@ impl ITMProcessor {
                 if !t.donefirst {
                     r = format!("{color_bright_yellow}       Relative|{color_reset}");
                 } else {
-                    let dt = Local::now();
+                    let dt = ITMProcessor::host_now(t);
                     let delta = dt.timestamp_millis() - t.old_dt.timestamp_millis();
                     r = format!(
                         "{color_bright_yellow}{:11}.{:03}|{color_reset}",
@@ -211,7 +1180,7 @@ impl ITMProcessor {
                 if !t.donefirst {
                     r = format!("{color_bright_yellow}          Delta|{color_reset}");
                 } else {
-                    let dt = Local::now();
+                    let dt = ITMProcessor::host_now(t);
                     let delta = dt.timestamp_millis() - t.old_dt.timestamp_millis();
                     t.old_dt = dt;
                     r = format!(
@@ -227,7 +1196,8 @@ impl ITMProcessor {
                 if !t.donefirst {
                     r = format!("{color_bright_yellow}   Target Delta|{color_reset}");
                 } else if t.cpu_freq_div != 1 {
-                    let d = (t.time - t.old_time) * 1000 / t.cpu_freq_div as u64;
+                    let d = t.time.saturating_sub(t.old_time).saturating_mul(1000)
+                        / t.cpu_freq_div as u64;
                     r = format!(
                         "{color_bright_yellow}{:7}.{:03}_{:03}|{color_reset}",
                         d / 1000000,
@@ -237,7 +1207,7 @@ impl ITMProcessor {
                 } else {
                     r = format!(
                         "{color_bright_yellow}{:15}|{color_reset}",
-                        t.time - t.old_time
+                        t.time.saturating_sub(t.old_time)
                     );
                 }
                 t.old_time = t.time;
@@ -248,7 +1218,7 @@ impl ITMProcessor {
                 if !t.donefirst {
                     r = format!("{color_bright_yellow}Target Relative|{color_reset}");
                 } else if t.cpu_freq_div != 1 {
-                    let d = t.time * 1000 / t.cpu_freq_div as u64;
+                    let d = t.time.saturating_mul(1000) / t.cpu_freq_div as u64;
                     r = format!(
                         "{color_bright_yellow}{:7}.{:03}_{:03}|{color_reset}",
                         d / 1000000,
@@ -267,141 +1237,436 @@ impl ITMProcessor {
 
     const NOTRANSLATE: String = String::new();
 
+    // Pair up halves of 64-bit values, then write out the instrumentation record
+    fn instrumentation(&mut self, addr: u8, data: u32, len: u8) {
+        /* A half left waiting is written alone if its partner isn't next */
+        if self.pending.is_some_and(|p| p.addr != addr || len != 4) {
+            self.flush_partial();
+        }
+
+        let order = match self.channel.get(addr as usize).and_then(|c| c.pair) {
+            Some(order) if len == 4 => order,
+            _ => return self.write_instrumentation(addr, data, len, data as u64),
+        };
+
+        match self.pending.take() {
+            None => {
+                self.pending = Some(Pending {
+                    addr,
+                    data,
+                    at: (self.clock)(),
+                })
+            }
+            Some(first) => {
+                let (low, high) = match order {
+                    PairOrder::LowFirst => (first.data, data),
+                    PairOrder::HighFirst => (data, first.data),
+                };
+                self.write_instrumentation(addr, low, len, (high as u64) << 32 | low as u64);
+            }
+        }
+    }
+
+    /// Write out the first half of a 64-bit value that is still waiting for its partner
+    ///
+    /// The missing half is taken as zero. This also happens if the partner doesn't arrive
+    /// within [`PAIR_TIMEOUT`], if a different channel writes in the meantime, and when the
+    /// processor is dropped.
+    ///
+    pub fn flush_partial(&mut self) {
+        if let Some(p) = self.pending.take() {
+            warn!("Incomplete 64-bit value on channel {}", p.addr);
+            let wide = match self.channel[p.addr as usize].pair {
+                Some(PairOrder::HighFirst) => (p.data as u64) << 32,
+                _ => p.data as u64,
+            };
+            self.write_instrumentation(p.addr, wide as u32, 4, wide);
+        }
+    }
+
+    // Format an instrumentation record, wide is the value for the 64-bit substitutions
+    fn write_instrumentation(&mut self, addr: u8, mut data: u32, mut len: u8, wide: u64) {
+        if (addr as usize) < self.channel.len() {
+            if let (HandleAs::Raw, Some(radix)) =
+                (&self.channel[addr as usize].handling, self.raw_radix)
+            {
+                self.write_raw(addr, data, len, radix);
+            } else if let Some(fmt) = &self.channel[addr as usize].fmt {
+                let act = self.channel[addr as usize].active;
+                let xf = self.channel[addr as usize].transform;
+                let mut done = Vec::new();
+                loop {
+                    let cv = if (act & IS_8BIT_CHAR) != 0 {
+                        data & 0xff
+                    } else {
+                        data
+                    };
+                    if let Some(pending) =
+                        self.line_text.as_mut().filter(|_| act & IS_8BIT_CHAR != 0)
+                    {
+                        // === Gather the line, and only format it once it's complete
+                        let line = &mut pending[addr as usize];
+                        line.push(cv as u8);
+                        if ITMProcessor::is_trigger(&self.trigger, &mut self.recent, cv as u8) {
+                            line.truncate(line.len().saturating_sub(self.trigger.len()));
+                            done.push(ITMProcessor::format_line(&self.ac, fmt, act, xf, line));
+                        }
+                    } else {
+                        let replace = &ITMProcessor::replacements(act, cv, xf, wide);
+
+                        let triggered =
+                            ITMProcessor::is_trigger(&self.trigger, &mut self.recent, cv as u8);
+                        let text = self.ac.replace_all(fmt, replace);
+                        if let Some(ring) = self.ring.as_mut() {
+                            ring.add(addr, &text, triggered);
+                        }
+
+                        if let Some(lines) = self.lines.as_mut() {
+                            // === Each channel builds its own line, written out whole on the trigger
+                            let line = &mut lines[addr as usize];
+                            if line.is_empty() && !triggered {
+                                line.push_str(&ITMProcessor::check_time_trigger(&mut self.t));
+                                if self.channel_prefix {
+                                    line.push_str(&format!("[ch {:02}] ", addr));
+                                }
+                            }
+                            line.push_str(&text);
+                            if triggered {
+                                let _ = self.output.write(line.as_bytes());
+                                line.clear();
+                            }
+                        } else {
+                            // === Check to see if a trigger occured, and adjust timing appropriately
+                            let mut newline = false;
+                            if triggered {
+                                self.storing = false;
+                            } else if !self.storing {
+                                self.armed = true;
+                                self.storing = true;
+                                newline = true;
+                                let _ = self.output.write(
+                                    ITMProcessor::check_time_trigger(&mut self.t).as_bytes(),
+                                );
+                            }
+
+                            // === Identify the channel at the start of a line, or if it changed
+                            if self.channel_prefix && (newline || self.last_chan != Some(addr)) {
+                                let _ = write!(self.output, "[ch {:02}] ", addr);
+                            }
+                            self.last_chan = Some(addr);
+
+                            let _ = self.output.write(text.as_bytes());
+                        }
+
+                        // === A completed record is a good point to pass on buffered output
+                        if triggered && self.output.policy != FlushPolicy::Immediate {
+                            let _ = self.output.flush();
+                        }
+                    }
+
+                    // === If we are in char mode treat each 8 element as a character
+                    if (act & IS_8BIT_CHAR) == 0 || len == 1 {
+                        break;
+                    }
+                    len -= 1;
+                    data >>= 8;
+                }
+                for text in done {
+                    self.write_line(addr, &text);
+                }
+            }
+        } else {
+            warn!("Illegal channel {}", addr);
+        }
+    }
+
+    // Format a line gathered for a channel, emptying it. Only {char} takes the whole line.
+    fn format_line(
+        ac: &AhoCorasick,
+        fmt: &str,
+        act: u64,
+        xf: Option<Transform>,
+        line: &mut Vec<u8>,
+    ) -> String {
+        let mut replace = ITMProcessor::replacements(act & !IS_8BIT_CHAR, 0, xf, 0);
+        replace[0] = line.drain(..).map(|b| b as char).collect();
+        ac.replace_all(fmt, &replace)
+    }
+
+    // Write a whole formatted line for a channel, as a record of its own
+    fn write_line(&mut self, addr: u8, text: &str) {
+        let mut line = String::new();
+        if self.storing && self.lines.is_none() {
+            line.push('\n');
+            self.storing = false;
+        }
+        line.push_str(&ITMProcessor::check_time_trigger(&mut self.t));
+        if self.channel_prefix {
+            line.push_str(&format!("[ch {:02}] ", addr));
+        }
+        line.push_str(text);
+        let _ = self.output.write(line.as_bytes());
+        self.last_chan = None;
+        if let Some(ring) = self.ring.as_mut() {
+            ring.add(addr, text, true);
+        }
+        if self.output.policy != FlushPolicy::Immediate {
+            let _ = self.output.flush();
+        }
+    }
+
+    /// Write out any lines still being gathered by [`ITMProcessor::set_format_per_line`]
+    pub fn flush_line_text(&mut self) {
+        let Some(mut pending) = self.line_text.take() else {
+            return;
+        };
+        for (addr, line) in pending.iter_mut().enumerate() {
+            if let (false, Some(fmt)) = (line.is_empty(), self.channel[addr].fmt.as_deref()) {
+                let c = &self.channel[addr];
+                let text = ITMProcessor::format_line(&self.ac, fmt, c.active, c.transform, line);
+                self.write_line(addr as u8, &text);
+            }
+        }
+        self.line_text = Some(pending);
+    }
+
+    // Substitutions for each of PATTERNS, for value `cv` (or `wide`) on a channel with `act`
+    fn replacements(act: u64, cv: u32, xf: Option<Transform>, wide: u64) -> [String; 13] {
+        // This replace structure needs to match PATTERNS above. Yes, it's yuk, but it's Rust-y.
+        // Perhaps one day there will be some print formatting that doesn't require string literals?
+        // This code allows each format to only be run if the format string contains any matches.
+        // With 13 potential matches this is a ~3 times decrease in CPU utilisation.
+        [
+            if act & (1 << 0) != 0 {
+                format!("{}", char::from_u32(cv).unwrap_or('?'))
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 1) != 0 {
+                "\x0a".to_string()
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 2) != 0 {
+                "\x09".to_string()
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 3) != 0 {
+                "\x07".to_string()
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 4) != 0 {
+                format!("{:08x}", cv)
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 5) != 0 {
+                format!("{:04x}", cv & 0xffff)
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 6) != 0 {
+                format!("{:02x}", cv & 0xff)
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 7) != 0 {
+                match xf {
+                    Some(t) => {
+                        format!("{}", t.apply(cv as i32 as f64).round() as i64)
+                    }
+                    None => format!("{}", cv as i32),
+                }
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 8) != 0 {
+                match xf {
+                    Some(t) => format!("{}", t.apply(cv as f64).round() as i64),
+                    None => format!("{}", cv),
+                }
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 9) != 0 {
+                format!("{}", char::from_u32(cv).unwrap_or('?'))
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 10) != 0 {
+                match xf {
+                    Some(t) => format!("{}", t.apply(cv as f64)),
+                    None => format!("{}", f32::from_bits(cv)),
+                }
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 11) != 0 {
+                format!("{}", wide)
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+            if act & (1 << 12) != 0 {
+                format!("{:016x}", wide)
+            } else {
+                ITMProcessor::NOTRANSLATE
+            },
+        ]
+    }
+
+    // Take the next instrumentation byte, and see if it completes the trigger sequence
+    fn is_trigger(trigger: &[u8], recent: &mut Vec<u8>, b: u8) -> bool {
+        if trigger.is_empty() {
+            return false;
+        }
+        if recent.len() == trigger.len() {
+            recent.remove(0);
+        }
+        recent.push(b);
+        recent == trigger
+    }
+
+    // Write the bytes of a packet on a raw channel as a line of its own
+    fn write_raw(&mut self, addr: u8, data: u32, len: u8, radix: Radix) {
+        let bytes: Vec<String> = data.to_le_bytes()[..(len as usize).min(4)]
+            .iter()
+            .map(|b| match radix {
+                Radix::Hex => format!("{:02x}", b),
+                Radix::Dec => format!("{}", b),
+                Radix::Bin => format!("{:08b}", b),
+            })
+            .collect();
+
+        /* Don't tack it onto the end of a formatted line that's in progress */
+        let mut line = String::new();
+        if self.storing && self.lines.is_none() {
+            line.push('\n');
+            self.storing = false;
+        }
+        line.push_str(&ITMProcessor::check_time_trigger(&mut self.t));
+        line.push_str(&format!("[ch {:02}] {}\n", addr, bytes.join(" ")));
+        let _ = self.output.write(line.as_bytes());
+        self.last_chan = None;
+        if let Some(ring) = self.ring.as_mut() {
+            ring.add(addr, &bytes.join(" "), true);
+        }
+    }
+
     // Object internal processor for itm events
     fn process_internal(&mut self, i: ITMFrame) -> bool {
+        self.count(&i);
+        let now = (self.clock)();
+        if self
+            .pending
+            .is_some_and(|p| now.saturating_duration_since(p.at) > PAIR_TIMEOUT)
+        {
+            self.flush_partial();
+        }
+        if let (Some(ticks), ITMFrame::PCSample { .. } | ITMFrame::PCSleep { .. }) =
+            (self.t.synthetic, &i)
+        {
+            self.t.time = self.t.time.saturating_add(ticks);
+            self.armed = false;
+        }
         match i {
             // -------------------------------------------------------------------------
             // === Timestamp, update our records
-            ITMFrame::Timestamp { ttype, ts } => {
+            ITMFrame::Timestamp {
+                ttype,
+                ts,
+                overflow,
+            } => {
                 debug!("Timestamp packet type {:?} +{}", ttype, ts);
-                self.t.time += ts;
+                /* Counter wrapped on the target, so at least this much time passed */
+                if overflow {
+                    warn!("Local timestamp overflow, target time is a lower bound");
+                }
+                let ts = if ts > MAX_TS_DELTA {
+                    warn!(
+                        "Timestamp delta {} is impossible, clamped to {}",
+                        ts, MAX_TS_DELTA
+                    );
+                    MAX_TS_DELTA
+                } else {
+                    ts
+                };
+                self.t.time = self.t.time.saturating_add(ts);
                 self.armed = false;
             }
             // -------------------------------------------------------------------------
             // Exception, if active then check report
             ITMFrame::Exception { no, event } => {
+                self.time_exception(no, &event);
                 if self.exlist.contains(&(no as i32)) {
-                    let _ = self
-                        .output
-                        .write(ITMProcessor::check_exception(&mut self.t, no, event).as_bytes());
+                    if self.ex_trace.is_some() {
+                        self.trace_exception(no, event);
+                    } else {
+                        let _ = self.output.write(
+                            ITMProcessor::check_exception(&mut self.t, &self.irq_names, no, event)
+                                .as_bytes(),
+                        );
+                    }
                 }
             }
             // -------------------------------------------------------------------------
             // === Instrumentation, extract data and format
             ITMFrame::Instrumentation {
-                addr,
-                mut data,
-                mut len,
+                addr, data, len, ..
             } => {
                 debug!("Instrumentation packet {:02x}:{}:{:08x}", addr, len, data);
-                if (addr as usize) < MAX_CHANNELS {
-                    if let Some(fmt) = &self.channel[addr as usize].fmt {
-                        let act = self.channel[addr as usize].active;
-                        loop {
-                            let cv = if (act & IS_8BIT_CHAR) != 0 {
-                                data & 0xff
-                            } else {
-                                data
-                            };
-                            // This replace structure needs to match PATTERNS above. Yes, it's yuk, but it's Rust-y.
-                            // Perhaps one day there will be some print formatting that doesn't require string literals?
-                            // This code allows each format to only be run if the format string contains any matches.
-                            // With 10 potential matches this is a ~3 times decrease in CPU utilisation.
-                            let replace = &[
-                                if act & (1 << 0) != 0 {
-                                    format!("{}", char::from_u32(cv).unwrap_or('?'))
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 1) != 0 {
-                                    "\x0a".to_string()
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 2) != 0 {
-                                    "\x09".to_string()
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 3) != 0 {
-                                    "\x07".to_string()
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 4) != 0 {
-                                    format!("{:08x}", cv)
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 5) != 0 {
-                                    format!("{:04x}", cv & 0xffff)
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 6) != 0 {
-                                    format!("{:02x}", cv & 0xff)
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 7) != 0 {
-                                    format!("{}", cv as i32)
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 8) != 0 {
-                                    format!("{}", cv)
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                                if act & (1 << 9) != 0 {
-                                    format!("{}", char::from_u32(cv).unwrap_or('?'))
-                                } else {
-                                    ITMProcessor::NOTRANSLATE
-                                },
-                            ];
-
-                            // === Check to see if a trigger occured, and adjust timing appropriately
-                            if cv as u8 as char == self.trigger {
-                                self.storing = false;
-                            } else if !self.storing {
-                                self.armed = true;
-                                self.storing = true;
-                                let _ = self.output.write(
-                                    ITMProcessor::check_time_trigger(&mut self.t).as_bytes(),
-                                );
-                            }
-
-                            let _ = self
-                                .output
-                                .write(self.ac.replace_all(fmt, replace).as_bytes());
-
-                            // === If we are in char mode treat each 8 element as a character
-                            if (act & IS_8BIT_CHAR) == 0 || len == 1 {
-                                break;
-                            }
-                            len -= 1;
-                            data >>= 8;
-                        }
-                    }
-                } else {
-                    warn!("Illegal channel {}", addr);
+                if self.ex_trace.is_none() {
+                    self.instrumentation(addr, data, len);
+                }
+            }
+            // -------------------------------------------------------------------------
+            // === PC sample, count it if we're building a histogram
+            ITMFrame::PCSample { addr } => {
+                if let Some(size) = self.pc_bucket {
+                    *self.pc_hist.entry(addr - addr % size).or_default() += 1;
+                } else if let Some(hook) = self.unhandled.as_mut() {
+                    hook(&i);
                 }
             }
             _ => {
                 debug! {"Dropped ITMFrame {:?}",i};
+                if let Some(hook) = self.unhandled.as_mut() {
+                    hook(&i);
+                }
             }
         }
-        true
+        self.output.failed.is_none()
+    }
+}
+
+// Don't lose the profile, partial lines or half of a 64-bit value when we're done. Any
+// exception trace is completed last, so nothing follows the JSON
+impl Drop for ITMProcessor {
+    fn drop(&mut self) {
+        self.flush_partial();
+        self.flush_line_text();
+        self.flush_lines();
+        self.flush_histogram();
+        self.set_exception_trace(false);
     }
 }
 
 // Collect the itm frames from the decoder, and process them
 impl collector::FrameHandler for ITMProcessor {
     fn process(&mut self, i: ITMFrame) -> bool {
+        self.t.arrival = None;
+        self.process_internal(i)
+    }
+
+    // Host time output is for when the frame arrived, rather than when it's formatted
+    fn process_at(&mut self, i: ITMFrame, at: SystemTime) -> bool {
+        self.t.arrival = Some(at);
         self.process_internal(i)
     }
 
-    // State callback indication from decoder
+    // State callback indication from decoder. Output isn't flushed here, that's up to the
+    // caller, see ITMProcessor::flush()
     fn state_ind(&self, e: &CollectError) {
         match e {
             CollectError::NoError => (),
@@ -409,6 +1674,5 @@ impl collector::FrameHandler for ITMProcessor {
                 info!("Decode error reported {:?}", e);
             }
         };
-        io::stdout().flush().expect("Cannot flush stdout");
     }
 }
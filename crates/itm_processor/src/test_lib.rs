@@ -1,5 +1,1021 @@
 #[cfg(test)]
 use super::*;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+
+/// Output sink that can be inspected after the processor has written to it
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SharedBuf {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+/// Create a processor with the specified channels set to the specified formats
+#[cfg(test)]
+fn make_processor(chans: &[(usize, &str)]) -> (ITMProcessor, SharedBuf) {
+    let specs: Vec<String> = chans
+        .iter()
+        .map(|(ch, fmt)| format!("{}{}{}", ch, CHANNEL_DELIMITER, fmt))
+        .collect();
+    let channel = parse_channel_spec(&specs).unwrap();
+    let buf = SharedBuf::default();
+    let p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::new(),
+        channel,
+        buf.clone(),
+    );
+    (p, buf)
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Time added to the test clock, so timeouts can be tested without real sleeps
+    static ADVANCED: std::cell::Cell<Duration> = const { std::cell::Cell::new(Duration::ZERO) };
+}
+
+/// Clock for the processor, that moves on when a test advances it as well as with time
+#[cfg(test)]
+fn test_clock() -> Instant {
+    Instant::now() + ADVANCED.get()
+}
+
+/// Feed a string into a channel as a sequence of single byte instrumentation frames
+#[cfg(test)]
+fn feed_chars(p: &mut ITMProcessor, addr: u8, s: &str) {
+    for c in s.bytes() {
+        p.process(ITMFrame::Instrumentation {
+            addr,
+            data: c as u32,
+            len: 1,
+            ts: None,
+        });
+    }
+}
+
+#[test]
+fn test_cpu_freq_mhz() {
+    assert_eq!(Ok(168000), parse_cpu_freq("168MHz"));
+    assert_eq!(Ok(168000), parse_cpu_freq("168mhz"));
+    assert_eq!(Ok(2625), parse_cpu_freq("2.625MHz"));
+}
+
+#[test]
+fn test_cpu_freq_khz() {
+    assert_eq!(Ok(72000), parse_cpu_freq("72000KHz"));
+    assert_eq!(Ok(72000), parse_cpu_freq("72000khz"));
+}
+
+#[test]
+fn test_cpu_freq_hz() {
+    assert_eq!(Ok(168000), parse_cpu_freq("168000000"));
+    assert_eq!(Ok(168000), parse_cpu_freq("168000000Hz"));
+}
+
+#[test]
+fn test_cpu_freq_bad() {
+    assert!(parse_cpu_freq("0").is_err());
+    assert!(parse_cpu_freq("-72MHz").is_err());
+    assert!(parse_cpu_freq("999Hz").is_err());
+    /* These would be taken as the divider of 1 that means raw ticks */
+    assert!(parse_cpu_freq("1KHz").is_err());
+    assert!(parse_cpu_freq("1499Hz").is_err());
+    assert_eq!(Ok(2), parse_cpu_freq("1500Hz"));
+    assert!(parse_cpu_freq("fastMHz").is_err());
+    assert!(parse_cpu_freq("").is_err());
+}
+
+#[test]
+fn test_channel_prefix() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (2, "{char}")]);
+    p.set_channel_prefix(true);
+
+    feed_chars(&mut p, 1, "ab\n");
+    feed_chars(&mut p, 2, "xy\n");
+    /* A single four byte packet is prefixed once */
+    p.process(ITMFrame::Instrumentation {
+        addr: 2,
+        data: u32::from_le_bytes(*b"pqr\n"),
+        len: 4,
+        ts: None,
+    });
+    feed_chars(&mut p, 1, "c");
+    feed_chars(&mut p, 2, "z");
+    assert_eq!(
+        "[ch 01] ab\n[ch 02] xy\n[ch 02] pqr\n[ch 01] c[ch 02] z",
+        buf.contents()
+    );
+}
+
+#[test]
+fn test_no_channel_prefix() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (2, "{char}")]);
+    feed_chars(&mut p, 1, "ab\n");
+    feed_chars(&mut p, 2, "xy\n");
+    assert_eq!("ab\nxy\n", buf.contents());
+}
+
+#[test]
+fn test_channel_spec() {
+    let c =
+        parse_channel_spec(&["1,{char}".to_string(), "4,v={u32} h={x04}\\n".to_string()]).unwrap();
+    assert_eq!(Some("{char}".to_string()), c[1].fmt);
+    assert_eq!(1 << 0, c[1].active);
+    assert_eq!(Some("v={u32} h={x04}\\n".to_string()), c[4].fmt);
+    assert_eq!((1 << 1) | (1 << 5) | (1 << 8), c[4].active);
+    assert_eq!(HandleAs::Normal, c[4].handling);
+    assert_eq!(None, c[0].fmt);
+    assert_eq!(HandleAs::None, c[0].handling);
+}
+
+#[test]
+fn test_channel_spec_errors() {
+    let p = |s: &[&str]| {
+        parse_channel_spec(&s.iter().map(|x| x.to_string()).collect::<Vec<_>>()).unwrap_err()
+    };
+    assert_eq!(ChannelSpecError::BadFormat("1".to_string()), p(&["1"]));
+    assert_eq!(
+        ChannelSpecError::BadFormat("1,a,b".to_string()),
+        p(&["1,a,b"])
+    );
+    assert_eq!(
+        ChannelSpecError::BadChannel("x".to_string()),
+        p(&["x,{char}"])
+    );
+    assert_eq!(ChannelSpecError::OutOfRange(256), p(&["256,{char}"]));
+    assert_eq!(ChannelSpecError::EmptyFormat(3), p(&["3,"]));
+    assert_eq!(ChannelSpecError::Duplicate(3), p(&["3,{char}", "3,{u32}"]));
+}
+
+/// Run a raw ITM byte stream through a decoder and into the processor
+#[cfg(test)]
+fn feed_itm(p: &mut ITMProcessor, ip: &[u8]) {
+    let mut d = ITMDecoder::new(true);
+    let mut v = ip.iter();
+    while let Ok(f) = d.get_frame(&mut v) {
+        p.process(f);
+    }
+}
+
+#[test]
+fn test_char_mode_multibyte_packet() {
+    /* One four byte packet to channel 18 */
+    let (mut p, buf) = make_processor(&[(18, "{char}")]);
+    feed_itm(&mut p, &[0x93, b'a', b'b', b'c', b'd']);
+    assert_eq!("abcd", buf.contents());
+
+    /* ...must match four single byte packets */
+    let (mut p, buf) = make_processor(&[(18, "{char}")]);
+    feed_itm(&mut p, &[0x91, b'a', 0x91, b'b', 0x91, b'c', 0x91, b'd']);
+    assert_eq!("abcd", buf.contents());
+
+    /* ...and a two byte packet followed by two single bytes */
+    let (mut p, buf) = make_processor(&[(18, "{char}")]);
+    feed_itm(&mut p, &[0x92, b'a', b'b', 0x91, b'c', 0x91, b'd']);
+    assert_eq!("abcd", buf.contents());
+}
+
+/// Output sink that counts the number of writes made to it
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct CountingBuf(SharedBuf, Rc<RefCell<usize>>);
+
+#[cfg(test)]
+impl Write for CountingBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        *self.1.borrow_mut() += 1;
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Output sink that would block on every other write, and breaks after `limit` bytes
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct FailingBuf(SharedBuf, Rc<RefCell<(bool, usize)>>);
+
+#[cfg(test)]
+impl Write for FailingBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.1.borrow_mut();
+        state.0 = !state.0;
+        if state.0 {
+            Err(io::ErrorKind::WouldBlock.into())
+        } else if state.1 == 0 {
+            Err(io::ErrorKind::BrokenPipe.into())
+        } else {
+            let n = buf.len().min(state.1);
+            state.1 -= n;
+            self.0.write(&buf[..n])
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn make_counting_processor(policy: FlushPolicy) -> (ITMProcessor, CountingBuf) {
+    let channel = parse_channel_spec(&["1,{char}".to_string()]).unwrap();
+    let buf = CountingBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::new(),
+        channel,
+        buf.clone(),
+    );
+    p.set_flush_policy(policy);
+    (p, buf)
+}
+
+#[test]
+fn test_flush_immediate() {
+    let (mut p, buf) = make_counting_processor(FlushPolicy::Immediate);
+    feed_chars(&mut p, 1, "Hello\nWorld\n");
+    assert_eq!("Hello\nWorld\n", buf.0.contents());
+    /* One write per record, plus the (empty) time column at the start of each line */
+    assert_eq!(14, *buf.1.borrow());
+}
+
+#[test]
+fn test_flush_on_trigger() {
+    let (mut p, buf) = make_counting_processor(FlushPolicy::Buffered {
+        threshold: DEFAULT_FLUSH_THRESHOLD,
+    });
+    feed_chars(&mut p, 1, "Hello\nWor");
+    assert_eq!("Hello\n", buf.0.contents());
+    assert_eq!(1, *buf.1.borrow());
+
+    /* Whatever is left is written out on request */
+    p.flush().unwrap();
+    assert_eq!("Hello\nWor", buf.0.contents());
+    assert_eq!(2, *buf.1.borrow());
+}
+
+#[test]
+fn test_flush_on_threshold() {
+    let (mut p, buf) = make_counting_processor(FlushPolicy::Buffered { threshold: 4 });
+    feed_chars(&mut p, 1, "abcdefghij");
+    assert_eq!("abcdefgh", buf.0.contents());
+    assert_eq!(2, *buf.1.borrow());
+
+    /* ...and anything remaining when the processor goes away */
+    drop(p);
+    assert_eq!("abcdefghij", buf.0.contents());
+}
+
+#[test]
+fn test_transform() {
+    let mut channel = parse_channel_spec(&[
+        "1,temp={f32} raw={x04} i={i32} u={u32}\n".to_string(),
+        "2,{f32}\n".to_string(),
+    ])
+    .unwrap();
+    channel[1].transform = Some(Transform {
+        scale: 0.5,
+        offset: -10.0,
+    });
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::new(),
+        channel,
+        buf.clone(),
+    );
+    p.process(ITMFrame::Instrumentation {
+        addr: 1,
+        data: 67,
+        len: 4,
+        ts: None,
+    });
+    /* Untransformed channels take the value as an IEEE float */
+    p.process(ITMFrame::Instrumentation {
+        addr: 2,
+        data: 1.25f32.to_bits(),
+        len: 4,
+        ts: None,
+    });
+    assert_eq!("temp=23.5 raw=0043 i=24 u=24\n1.25\n", buf.contents());
+}
+
+#[test]
+fn test_pc_histogram() {
+    let (mut p, buf) = make_processor(&[]);
+    p.set_pc_histogram(0x100);
+    for addr in [
+        0x0800_0010,
+        0x0800_00ff,
+        0x0800_0100,
+        0x0800_0010,
+        0x2000_0000,
+    ] {
+        p.process(ITMFrame::PCSample { addr });
+    }
+    assert_eq!("", buf.contents());
+
+    p.flush_histogram();
+    assert_eq!(
+        "0x08000000-0x080000ff          3  60.00%\n\
+         0x08000100-0x080001ff          1  20.00%\n\
+         0x20000000-0x200000ff          1  20.00%\n",
+        buf.contents()
+    );
+
+    /* A new histogram starts after each flush, and is written when the processor goes */
+    p.process(ITMFrame::PCSample { addr: 0xffff_fff0 });
+    drop(p);
+    assert!(buf
+        .contents()
+        .ends_with("0xffffff00-0xffffffff          1 100.00%\n"));
+}
+
+#[test]
+fn test_unhandled_hook() {
+    let (mut p, buf) = make_processor(&[(1, "{char}")]);
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let s = seen.clone();
+    p.set_unhandled_hook(Box::new(move |f: &ITMFrame| s.borrow_mut().push(f.clone())));
+
+    let dt = ITMFrame::DataTracePC {
+        index: 1,
+        addr: 0x0800_1234,
+        len: 4,
+    };
+    p.process(dt.clone());
+    feed_chars(&mut p, 1, "A");
+    let pmu = ITMFrame::PMUOverflow {
+        ovf: 3,
+        overflowed: [true, true, false, false, false, false, false, false],
+    };
+    p.process(pmu.clone());
+
+    /* Rendered frames don't reach the hook */
+    assert_eq!("A", buf.contents());
+    assert_eq!(vec![dt, pmu], *seen.borrow());
+}
+
+#[test]
+fn test_summary() {
+    let (mut p, _buf) = make_processor(&[(1, "{char}"), (2, "{x02}")]);
+    feed_itm(
+        &mut p,
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+            0x09, 0x41, 0x09, 0x42, 0x09, 0x43, // Three on channel 1
+            0x11, 0x55, // One on channel 2
+            0x0e, 0x42, 0x11, // Exception 0x142, Entry
+            0x70, // Overflow
+            0x30, // Local timestamp of 3 ticks
+        ],
+    );
+
+    let s = p.summary();
+    assert!(s.contains("Frames: 8\n"));
+    assert!(s.contains("Channel 01: 3\n"));
+    assert!(s.contains("Channel 02: 1\n"));
+    assert!(!s.contains("Channel 00"));
+    assert!(s.contains("Exceptions: 1\n"));
+    assert!(s.contains("Overflows: 1\n"));
+    assert!(s.contains("Target time: 3 ticks\n"));
+}
+
+#[test]
+fn test_u64_pair() {
+    let (mut p, buf) = make_processor(&[(1, "{x016} {u64}\n"), (2, "{char}")]);
+    for data in [0x89ab_cdef, 0x0123_4567] {
+        p.process(ITMFrame::Instrumentation {
+            addr: 1,
+            data,
+            len: 4,
+            ts: None,
+        });
+    }
+    assert_eq!("0123456789abcdef 81985529216486895\n", buf.contents());
+}
+
+#[test]
+fn test_u64_pair_high_first() {
+    let mut channel = parse_channel_spec(&["1,{x016}\n".to_string()]).unwrap();
+    channel[1].pair = Some(PairOrder::HighFirst);
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::new(),
+        channel,
+        buf.clone(),
+    );
+    for data in [0x0123_4567, 0x89ab_cdef] {
+        p.process(ITMFrame::Instrumentation {
+            addr: 1,
+            data,
+            len: 4,
+            ts: None,
+        });
+    }
+    assert_eq!("0123456789abcdef\n", buf.contents());
+}
+
+#[test]
+fn test_u64_partial() {
+    let (mut p, buf) = make_processor(&[(1, "{x016}\n"), (2, "{char}")]);
+    p.clock = test_clock;
+    p.process(ITMFrame::Instrumentation {
+        addr: 1,
+        data: 0x1234,
+        len: 4,
+        ts: None,
+    });
+    assert_eq!("", buf.contents());
+
+    /* Another channel writing means the partner isn't coming */
+    feed_chars(&mut p, 2, "x");
+    assert_eq!("0000000000001234\nx", buf.contents());
+
+    /* ...as does running out of time */
+    p.process(ITMFrame::Instrumentation {
+        addr: 1,
+        data: 0x5678,
+        len: 4,
+        ts: None,
+    });
+    p.process(ITMFrame::Sync { count: 0 });
+    assert_eq!("0000000000001234\nx", buf.contents());
+    ADVANCED.set(ADVANCED.get() + PAIR_TIMEOUT * 2);
+    p.process(ITMFrame::Sync { count: 0 });
+    assert_eq!("0000000000001234\nx0000000000005678\n", buf.contents());
+}
 
 #[test]
-fn test_sync() {}
+fn test_line_buffering() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (2, "{char}")]);
+    p.set_channel_prefix(true);
+    p.set_line_buffering(true);
+    for (a, b) in "abc\n".chars().zip("xyz\n".chars()) {
+        feed_chars(&mut p, 1, &a.to_string());
+        feed_chars(&mut p, 2, &b.to_string());
+    }
+    assert_eq!("[ch 01] abc\n[ch 02] xyz\n", buf.contents());
+
+    /* Anything incomplete comes out when buffering stops */
+    feed_chars(&mut p, 2, "pq");
+    feed_chars(&mut p, 1, "rs");
+    assert_eq!("[ch 01] abc\n[ch 02] xyz\n", buf.contents());
+    p.set_line_buffering(false);
+    assert_eq!(
+        "[ch 01] abc\n[ch 02] xyz\n[ch 01] rs[ch 02] pq",
+        buf.contents()
+    );
+}
+
+#[test]
+fn test_exception_trace() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::from([15, 16 + 5]),
+        parse_channel_spec(&["1,{char}".to_string()]).unwrap(),
+        buf.clone(),
+    );
+    p.set_exception_trace(true);
+
+    let ts = |ts| ITMFrame::Timestamp {
+        ttype: TSType::Sync,
+        ts,
+        overflow: false,
+    };
+    let ex = |no, event| ITMFrame::Exception { no, event };
+    for f in [
+        ex(16 + 5, ExceptionEvent::Exit), // Exit without entry is skipped
+        ts(10),
+        ex(15, ExceptionEvent::Entry),
+        ts(5),
+        ex(16 + 5, ExceptionEvent::Entry),
+        ITMFrame::Instrumentation {
+            addr: 1,
+            data: 0x41,
+            len: 1,
+            ts: None,
+        },
+        ts(3),
+        ex(16 + 5, ExceptionEvent::Exit),
+        ex(15, ExceptionEvent::Returned),
+        ex(14, ExceptionEvent::Entry), // Not selected for reporting
+        ts(2),
+        ex(15, ExceptionEvent::Exit),
+        ex(15, ExceptionEvent::Entry), // Still open at the end
+    ] {
+        p.process(f);
+    }
+    p.set_exception_trace(false);
+
+    assert_eq!(
+        "[\n\
+        {\"name\":\"SysTick\",\"ph\":\"B\",\"ts\":10,\"pid\":1,\"tid\":1},\n\
+        {\"name\":\"IRQ 5\",\"ph\":\"B\",\"ts\":15,\"pid\":1,\"tid\":1},\n\
+        {\"name\":\"IRQ 5\",\"ph\":\"E\",\"ts\":18,\"pid\":1,\"tid\":1},\n\
+        {\"name\":\"SysTick\",\"ph\":\"E\",\"ts\":20,\"pid\":1,\"tid\":1},\n\
+        {\"name\":\"SysTick\",\"ph\":\"B\",\"ts\":20,\"pid\":1,\"tid\":1},\n\
+        {\"name\":\"SysTick\",\"ph\":\"E\",\"ts\":20,\"pid\":1,\"tid\":1}\n\
+        ]\n",
+        buf.contents()
+    );
+}
+
+#[test]
+fn test_exception_trace_with_histogram() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::from([15]),
+        empty_chan_spec(),
+        buf.clone(),
+    );
+    p.set_pc_histogram(0x100);
+    p.set_exception_trace(true);
+    for f in [
+        ITMFrame::Exception {
+            no: 15,
+            event: ExceptionEvent::Entry,
+        },
+        ITMFrame::PCSample { addr: 0x0800_0010 },
+    ] {
+        p.process(f);
+    }
+
+    /* The histogram waits for the trace, so the output is still a valid trace */
+    p.flush_histogram();
+    drop(p);
+    let events: serde_json::Value = serde_json::from_str(&buf.contents()).unwrap();
+    assert_eq!(2, events.as_array().unwrap().len());
+}
+
+#[test]
+fn test_exception_trace_held_output() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (2, "{x016}\n")]);
+    p.set_line_buffering(true);
+    feed_chars(&mut p, 1, "ab");
+    p.process(ITMFrame::Instrumentation {
+        addr: 2,
+        data: 0x1234,
+        len: 4,
+        ts: None,
+    });
+    assert_eq!("", buf.contents());
+
+    /* What's held comes out ahead of the trace, and nothing is added to it afterwards */
+    p.set_exception_trace(true);
+    feed_chars(&mut p, 1, "cd\n");
+    drop(p);
+    let out = buf.contents();
+    let (held, trace) = out.split_at(out.find('[').unwrap());
+    assert_eq!("ab0000000000001234\n", held);
+    let events: serde_json::Value = serde_json::from_str(trace).unwrap();
+    assert!(events.as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_exception_trace_irq_name_escaped() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::from([16 + 3]),
+        empty_chan_spec(),
+        buf.clone(),
+    );
+    p.set_irq_names(HashMap::from([(3, "Odd \"name\" \\ here\t".to_string())]));
+    p.set_exception_trace(true);
+    p.process(ITMFrame::Exception {
+        no: 16 + 3,
+        event: ExceptionEvent::Entry,
+    });
+    drop(p);
+
+    let events: serde_json::Value = serde_json::from_str(&buf.contents()).unwrap();
+    assert_eq!("IRQ Odd \"name\" \\ here\t", events[0]["name"]);
+}
+
+#[test]
+fn test_bogus_timestamp() {
+    let ts = |ts| ITMFrame::Timestamp {
+        ttype: TSType::Sync,
+        ts,
+        overflow: false,
+    };
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::TargetDelta,
+        1,
+        HashSet::new(),
+        parse_channel_spec(&["1,{char}".to_string()]).unwrap(),
+        buf.clone(),
+    );
+
+    /* An oversized delta is clamped to what a timestamp can carry */
+    p.process(ts(u64::MAX));
+    assert!(p
+        .summary()
+        .contains(&format!("Target time: {} ticks\n", (1u64 << 28) - 1)));
+
+    /* ...and later ones still accumulate, with the deltas reported as normal */
+    for _ in 0..100 {
+        p.process(ts(u64::MAX));
+    }
+    p.process(ts(5));
+    for c in "A\nB\n".chars() {
+        p.process(ITMFrame::Instrumentation {
+            addr: 1,
+            data: c as u32,
+            len: 1,
+            ts: None,
+        });
+    }
+    assert!(buf.contents().contains("A\n") && buf.contents().contains("B\n"));
+    assert!(p.summary().contains(&format!(
+        "Target time: {} ticks\n",
+        101 * ((1u64 << 28) - 1) + 5
+    )));
+}
+
+#[test]
+fn test_output_failure() {
+    use collector::FrameHandler;
+    let buf = FailingBuf::default();
+    buf.1.borrow_mut().1 = 3;
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::new(),
+        parse_channel_spec(&["1,{char}".to_string()]).unwrap(),
+        buf.clone(),
+    );
+    let chr = |c: char| ITMFrame::Instrumentation {
+        addr: 1,
+        data: c as u32,
+        len: 1,
+        ts: None,
+    };
+
+    /* Writes that would block are retried */
+    for c in "ABC".chars() {
+        assert!(p.process(chr(c)));
+    }
+    assert_eq!("ABC", buf.0.contents());
+    assert_eq!(None, p.output_failed());
+
+    /* ...but a broken pipe stops everything */
+    assert!(!p.process(chr('D')));
+    assert_eq!(Some(io::ErrorKind::BrokenPipe), p.output_failed());
+    assert!(!p.process(chr('E')));
+    assert_eq!("ABC", buf.0.contents());
+}
+
+#[test]
+fn test_synthetic_interval() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::TargetDelta,
+        1,
+        HashSet::new(),
+        parse_channel_spec(&["1,{char}".to_string()]).unwrap(),
+        buf.clone(),
+    );
+    p.set_synthetic_interval(100);
+    for (samples, line) in [(0, "A\n"), (3, "B\n"), (1, "C\n")] {
+        for _ in 0..samples {
+            p.process(ITMFrame::PCSample { addr: 0x0800_1000 });
+        }
+        feed_chars(&mut p, 1, line);
+    }
+    /* Sleeping is a sample too */
+    p.process(ITMFrame::PCSleep { prohibited: false });
+    feed_chars(&mut p, 1, "D\n");
+
+    /* ...and once stopped, samples don't move time on */
+    p.set_synthetic_interval(0);
+    p.process(ITMFrame::PCSample { addr: 0x0800_1000 });
+    feed_chars(&mut p, 1, "E\n");
+
+    let lines: Vec<_> = buf.contents().lines().map(str::to_string).collect();
+    assert_eq!(5, lines.len());
+    for (line, delta) in lines[1..].iter().zip([" 300|", " 100|", " 100|", " 0|"]) {
+        assert!(line.contains(delta), "{line:?} should have delta{delta}");
+    }
+}
+
+#[test]
+fn test_raw_radix() {
+    for (radix, one, four) in [
+        (Radix::Hex, "a5", "41 42 00 ff"),
+        (Radix::Dec, "165", "65 66 0 255"),
+        (
+            Radix::Bin,
+            "10100101",
+            "01000001 01000010 00000000 11111111",
+        ),
+    ] {
+        let (mut p, buf) = make_processor(&[(1, "{char}")]);
+        p.set_raw_radix(Some(radix));
+        for (addr, data, len) in [(3, 0xa5, 1), (1, 0x5a, 1), (3, 0xff00_4241, 4)] {
+            p.process(ITMFrame::Instrumentation {
+                addr,
+                data,
+                len,
+                ts: None,
+            });
+        }
+        /* The formatted channel is unaffected, but its line is ended before raw output */
+        assert_eq!(
+            format!("[ch 03] {one}\nZ\n[ch 03] {four}\n"),
+            buf.contents()
+        );
+    }
+
+    /* Without a radix, channels without a format are ignored */
+    let (mut p, buf) = make_processor(&[(1, "{char}")]);
+    p.process(ITMFrame::Instrumentation {
+        addr: 3,
+        data: 0xa5,
+        len: 1,
+        ts: None,
+    });
+    assert_eq!("", buf.contents());
+}
+
+#[test]
+fn test_exception_stats() {
+    let (mut p, _buf) = make_processor(&[]);
+    let ts = |ts| ITMFrame::Timestamp {
+        ttype: TSType::Sync,
+        ts,
+        overflow: false,
+    };
+    let ex = |no, event| ITMFrame::Exception { no, event };
+    for f in [
+        ex(16 + 5, ExceptionEvent::Exit), // Exit without entry is ignored
+        ts(10),
+        ex(15, ExceptionEvent::Entry),
+        ts(100),
+        ex(15, ExceptionEvent::Exit),
+        ts(50),
+        ex(15, ExceptionEvent::Entry),
+        ts(20),
+        ex(16 + 5, ExceptionEvent::Entry), // Preempts SysTick
+        ts(30),
+        ex(16 + 5, ExceptionEvent::Exit),
+        ts(40),
+        ex(15, ExceptionEvent::Exit),
+        ex(15, ExceptionEvent::Entry), // Never finishes
+    ] {
+        p.process(f);
+    }
+
+    assert_eq!(
+        vec![
+            (
+                15,
+                ExceptionTiming {
+                    count: 2,
+                    total: 160,
+                    min: 60,
+                    max: 100,
+                    last: 60
+                }
+            ),
+            (
+                16 + 5,
+                ExceptionTiming {
+                    count: 1,
+                    total: 30,
+                    min: 30,
+                    max: 30,
+                    last: 30
+                }
+            ),
+        ],
+        p.exception_stats()
+    );
+}
+
+#[test]
+fn test_paged_channel() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (242, "[{x08}]")]);
+    feed_itm(
+        &mut p,
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Sync
+            0x09, 0x41, // 'A' to channel 1
+            0x7c, // Set page 7 (Nos 224..255)
+            0x93, 0x11, 0x22, 0x33, 0x44, // 4 Bytes to channel 224 + 18
+            0x0c, // Back to page 0
+            0x09, 0x42, // 'B' to channel 1
+        ],
+    );
+    assert_eq!("A[44332211]B", buf.contents());
+    assert!(p.summary().contains("Channel 242: 1\n"));
+}
+
+#[test]
+fn test_reset_time_base() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::TargetDelta,
+        1,
+        HashSet::new(),
+        parse_channel_spec(&["1,{char}".to_string()]).unwrap(),
+        buf.clone(),
+    );
+    let ts = |ts| ITMFrame::Timestamp {
+        ttype: TSType::Sync,
+        ts,
+        overflow: false,
+    };
+    feed_chars(&mut p, 1, "A\n");
+    p.process(ts(500));
+    feed_chars(&mut p, 1, "B\n");
+    p.process(ts(200));
+    assert_eq!(700, p.current_target_time());
+
+    /* Target restarted its counter, so the next delta is from the restart */
+    p.reset_time_base();
+    assert_eq!(0, p.current_target_time());
+    p.process(ts(30));
+    feed_chars(&mut p, 1, "C\n");
+    assert_eq!(30, p.current_target_time());
+
+    let lines: Vec<_> = buf.contents().lines().map(str::to_string).collect();
+    assert_eq!(3, lines.len());
+    for (line, delta) in lines[1..].iter().zip([" 500|", " 30|"]) {
+        assert!(line.contains(delta), "{line:?} should have delta{delta}");
+    }
+}
+
+#[test]
+fn test_irq_names() {
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::None,
+        1,
+        HashSet::from([16 + 11, 16 + 12]),
+        parse_channel_spec(&[]).unwrap(),
+        buf.clone(),
+    );
+    p.set_irq_names(HashMap::from([(11, "DMA1_Channel1".to_string())]));
+    p.process(ITMFrame::Exception {
+        no: 16 + 11,
+        event: ExceptionEvent::Entry,
+    });
+    p.process(ITMFrame::Exception {
+        no: 16 + 12,
+        event: ExceptionEvent::Entry,
+    });
+
+    /* Unnamed interrupts keep their number */
+    let out = buf.contents();
+    assert!(out.contains("INTERRUPT DMA1_Channel1 Entry"), "{out:?}");
+    assert!(out.contains("INTERRUPT 12 Entry"), "{out:?}");
+}
+
+#[test]
+fn test_flush_explicit() {
+    let (mut p, buf) = make_counting_processor(FlushPolicy::Buffered {
+        threshold: DEFAULT_FLUSH_THRESHOLD,
+    });
+    p.set_line_buffering(true);
+    feed_chars(&mut p, 1, "Hello\nWorld");
+
+    /* State indications from the collector leave output where it is */
+    p.state_ind(&CollectError::NoError);
+    p.state_ind(&CollectError::Reset);
+    assert_eq!("Hello\n", buf.0.contents());
+
+    /* ...until the caller asks for it, when partial lines go too */
+    p.flush().unwrap();
+    assert_eq!("Hello\nWorld", buf.0.contents());
+}
+
+#[test]
+fn test_trigger_sequence() {
+    let (mut p, buf) = make_processor(&[(1, "{char}")]);
+    p.set_line_buffering(true);
+    p.set_trigger(vec![b'\r', b'\n']);
+
+    /* A lone CR or LF isn't the end of the line, only the pair is */
+    feed_chars(&mut p, 1, "a\rb\nc\r");
+    assert_eq!("", buf.contents());
+    feed_chars(&mut p, 1, "\nd");
+    assert_eq!("a\rb\nc\r\n", buf.contents());
+
+    assert_eq!(Ok(vec![0x0d, 0x0a]), parse_trigger("\\r\\n"));
+    assert_eq!(Ok(vec![b'x']), parse_trigger("x"));
+    assert!(parse_trigger("\\x0").is_err());
+    assert!(parse_trigger("").is_err());
+}
+
+#[test]
+fn test_format_per_line() {
+    let (mut p, buf) = make_processor(&[(1, "> {char}\\n")]);
+    p.set_format_per_line(true);
+
+    /* The line arrives as a four character packet and then single characters */
+    p.process(ITMFrame::Instrumentation {
+        addr: 1,
+        data: u32::from_le_bytes(*b"Hell"),
+        len: 4,
+        ts: None,
+    });
+    assert_eq!("", buf.contents());
+    feed_chars(&mut p, 1, "o\nWor");
+    assert_eq!("> Hello\n", buf.contents());
+
+    /* Whatever is gathered comes out when per line formatting stops */
+    p.set_format_per_line(false);
+    assert_eq!("> Hello\n> Wor\n", buf.contents());
+}
+
+#[test]
+fn test_arrival_time() {
+    let channel = parse_channel_spec(&["1,{char}".to_string()]).unwrap();
+    let buf = SharedBuf::default();
+    let mut p = ITMProcessor::new(
+        DEFAULT_TRIGGER_CHAR,
+        IntervalType::Absolute,
+        1,
+        HashSet::new(),
+        channel,
+        buf.clone(),
+    );
+
+    /* The time shown is when the frame arrived, not when it was formatted */
+    let at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_000_000_000_123);
+    collector::FrameHandler::process_at(
+        &mut p,
+        ITMFrame::Instrumentation {
+            addr: 1,
+            data: 'A' as u32,
+            len: 1,
+            ts: None,
+        },
+        at,
+    );
+    let shown = chrono::DateTime::<Local>::from(at).format("%Y-%m-%d %H:%M:%S%.3f");
+    assert!(buf.contents().contains(&format!("{}|", shown)));
+    assert!(buf.contents().ends_with('A'));
+}
+
+#[test]
+fn test_ring_capacity() {
+    let (mut p, buf) = make_processor(&[(1, "{char}"), (2, "{char}")]);
+    p.set_ring_capacity(2);
+    feed_chars(&mut p, 1, "one\ntwo\nthree\nfo");
+    feed_chars(&mut p, 2, "other\n");
+
+    /* Only the latest complete lines are kept, and output carries on as normal */
+    let lines = |p: &ITMProcessor, ch| p.channel_lines(ch).map(String::from).collect::<Vec<_>>();
+    assert_eq!(["two", "three"], lines(&p, 1)[..]);
+    assert_eq!(["other"], lines(&p, 2)[..]);
+    assert!(lines(&p, 3).is_empty());
+    assert_eq!(Some("three"), p.channel_lines(1).next_back());
+    assert!(buf.contents().starts_with("one\ntwo\nthree\nfo"));
+
+    p.set_ring_capacity(0);
+    assert!(lines(&p, 1).is_empty());
+}
@@ -2,15 +2,19 @@
 //!
 //! Coverts a orbflow packet into a valid frame for higher layers, and turns a higher layer
 //! packet into an orbflow one. The encoder and decoder only work atomically on complete
-//! frames, so there is no concept of state.
+//! frames, so there is no concept of state. The exception is [`OFlowEncoder`], for building
+//! a frame a piece at a time.
 //!
 //! OFLOW packets are characterised by a single byte stream number, followed by a number of
 //! bytes of (stream specific) data, terminated by a twos compliment checksum byte that makes
-//! the whole frame sum to zero.
+//! the whole frame sum to zero. Some dialects carry the checksum immediately after the stream
+//! number instead, see [`ChecksumPosition`].
 //!
 //!
 
 use std::fmt;
+use std::io::IoSlice;
+use std::ops::Range;
 use std::vec::Vec;
 
 #[path = "test_lib.rs"]
@@ -25,8 +29,10 @@ pub enum OFlowError {
     Overlong,
     /// Insufficent data in buffer to complete the packet
     ShortData,
-    /// Duff checksum
-    BadChecksum,
+    /// Duff checksum, with the checksum the frame should have had and the one it had
+    BadChecksum { expected: u8, got: u8 },
+    /// Payload is not the expected length
+    WrongLength { got: usize, expected: usize },
     /// Function not implemented
     Unimplemented,
 }
@@ -37,7 +43,16 @@ impl fmt::Display for OFlowError {
             OFlowError::ZeroLength => write!(f, "Zero length message"),
             OFlowError::Overlong => write!(f, "Packet is too long"),
             OFlowError::ShortData => write!(f, "Packet is too short"),
-            OFlowError::BadChecksum => write!(f, "Bad checksum"),
+            OFlowError::BadChecksum { expected, got } => {
+                write!(
+                    f,
+                    "Bad checksum 0x{:02x} when 0x{:02x} expected",
+                    got, expected
+                )
+            }
+            OFlowError::WrongLength { got, expected } => {
+                write!(f, "Payload length {} when {} expected", got, expected)
+            }
             OFlowError::Unimplemented => write!(f, "Unimplemented"),
         }
     }
@@ -48,18 +63,41 @@ impl std::error::Error for OFlowError {}
 /// An OrbFlow frame
 ///
 /// An orbflow frame consists of a stream number followed by the frame content. The last byte of this
-/// content is a checksum.
+/// content is a checksum, unless the frame was decoded with [`ChecksumPosition::Leading`].
 ///
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OFlowFrame {
     stream_number: u8,
+    checksum: ChecksumKind,
+    position: ChecksumPosition,
     inner: Vec<u8>,
 }
 
 /// Access the data carried by the orbflow frame
 impl OFlowFrame {
     pub fn content(&self) -> &[u8] {
-        &self.inner[OFlow::STREAM_LEN..self.inner.len() - OFlow::CHECKSUM_LEN]
+        &self.inner[self.position.data_range(self.inner.len())]
+    }
+}
+
+/// Take the data carried by the orbflow frame
+///
+/// The frame is consumed, and its storage reused for the data, so no new allocation is made.
+///
+/// # Example
+/// ```
+/// use oflow::OFlow;
+/// let mut of = OFlow::new();
+/// let encoded = of.encode_to_vec(42, vec![1u8, 2, 3]).unwrap();
+/// assert_eq!(vec![1u8, 2, 3], of.decode(encoded).unwrap().into_content());
+/// ```
+///
+impl OFlowFrame {
+    pub fn into_content(mut self) -> Vec<u8> {
+        let data = self.position.data_range(self.inner.len());
+        self.inner.truncate(data.end);
+        self.inner.drain(..data.start);
+        self.inner
     }
 }
 
@@ -76,10 +114,10 @@ impl std::ops::Index<usize> for OFlowFrame {
     type Output = u8;
 
     fn index(&self, i: usize) -> &Self::Output {
-        if self.inner.len() < 2 || i > self.inner.len() - 1 - OFlow::CHECKSUM_LEN {
+        if i >= self.content().len() {
             panic!("Index out of range");
         }
-        &self.inner[i + OFlow::STREAM_LEN]
+        &self.content()[i]
     }
 }
 
@@ -98,26 +136,301 @@ impl OFlowFrame {
     }
 }
 
+impl OFlowFrame {
+    /// Create a valid frame for the stream carrying `content`
+    ///
+    /// For tools that build frames to inject, without doing the checksum by hand. The frame
+    /// has the default twos complement checksum, at the end.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::{OFlow, OFlowFrame};
+    /// let frame = OFlowFrame::with_content(42, &[1, 2, 3]);
+    /// let mut of = OFlow::new();
+    /// assert_eq!(&[1u8, 2, 3], of.decode(frame.oflow_frame().to_vec()).unwrap().content());
+    /// ```
+    ///
+    pub fn with_content(stream_number: u8, content: &[u8]) -> OFlowFrame {
+        let mut frame = OFlowFrame {
+            stream_number,
+            checksum: ChecksumKind::default(),
+            position: ChecksumPosition::default(),
+            inner: vec![stream_number; OFlow::OVERHEAD_LEN],
+        };
+        frame.set_content(content);
+        frame
+    }
+
+    /// Replace the data carried by the frame, fixing up its checksum
+    ///
+    /// For tools that rewrite the data of frames, such as redaction. The checksum in the
+    /// inner frame is recalculated using the checksum kind and position the frame was decoded
+    /// with, so it still passes [`OFlow::decode`].
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let mut of = OFlow::new();
+    /// let encoded = of.encode_to_vec(42, vec![1u8, 2, 3]).unwrap();
+    /// let mut frame = of.decode(encoded).unwrap();
+    /// frame.set_content(&[0, 0]);
+    /// assert_eq!(&[0u8, 0], of.decode(frame.oflow_frame().to_vec()).unwrap().content());
+    /// ```
+    ///
+    pub fn set_content(&mut self, content: &[u8]) {
+        let sum = self.checksum.checksum(self.stream_number, content);
+        self.inner.truncate(OFlow::STREAM_LEN);
+        match self.position {
+            ChecksumPosition::Trailing => {
+                self.inner.extend_from_slice(content);
+                self.inner.push(sum);
+            }
+            ChecksumPosition::Leading => {
+                self.inner.push(sum);
+                self.inner.extend_from_slice(content);
+            }
+        }
+    }
+}
+
+/// Types of checksum that can protect an orbflow frame
+#[derive(Default, Debug, Clone, Eq, Copy, PartialEq)]
+pub enum ChecksumKind {
+    /// Twos complement checksum that makes the whole frame sum to zero
+    #[default]
+    TwosComplement,
+    /// Checksum that makes the whole frame XOR to zero
+    Xor,
+}
+
+impl ChecksumKind {
+    /// Calculate the checksum byte for the stream number and data of a frame
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::ChecksumKind;
+    /// assert_eq!(0x54, ChecksumKind::Xor.checksum(0x55, &[1u8]));
+    /// ```
+    ///
+    pub fn checksum(&self, stream_number: u8, ip: &[u8]) -> u8 {
+        self.complete(self.accumulate(stream_number, ip))
+    }
+
+    // Add more bytes to a running sum
+    fn accumulate(&self, sum: u8, ip: &[u8]) -> u8 {
+        match self {
+            ChecksumKind::TwosComplement => ip.iter().fold(sum, |sum, c| sum.wrapping_add(*c)),
+            ChecksumKind::Xor => ip.iter().fold(sum, |sum, c| sum ^ c),
+        }
+    }
+
+    // Turn a running sum into the checksum byte
+    fn complete(&self, sum: u8) -> u8 {
+        match self {
+            ChecksumKind::TwosComplement => sum.wrapping_neg(),
+            ChecksumKind::Xor => sum,
+        }
+    }
+
+    // Check a complete frame, with stream number and checksum, returning the checksum it
+    // should have had and the one it had if they don't match
+    fn mismatch(&self, position: ChecksumPosition, frame: &[u8]) -> Option<(u8, u8)> {
+        if frame.len() < OFlow::OVERHEAD_LEN {
+            return None;
+        }
+        let got = frame[position.checksum_index(frame.len())];
+        let expected = self.checksum(frame[0], &frame[position.data_range(frame.len())]);
+        (expected != got).then_some((expected, got))
+    }
+}
+
+/// Where the checksum sits in an orbflow frame
+#[derive(Default, Debug, Clone, Eq, Copy, PartialEq)]
+pub enum ChecksumPosition {
+    /// After the data, at the end of the frame
+    #[default]
+    Trailing,
+    /// Immediately after the stream number, before the data
+    Leading,
+}
+
+impl ChecksumPosition {
+    // Index of the checksum in a complete frame of `len` bytes
+    fn checksum_index(&self, len: usize) -> usize {
+        match self {
+            ChecksumPosition::Trailing => len - OFlow::CHECKSUM_LEN,
+            ChecksumPosition::Leading => OFlow::STREAM_LEN,
+        }
+    }
+
+    // Range of the data in a complete frame of `len` bytes
+    fn data_range(&self, len: usize) -> Range<usize> {
+        match self {
+            ChecksumPosition::Trailing => OFlow::STREAM_LEN..len - OFlow::CHECKSUM_LEN,
+            ChecksumPosition::Leading => OFlow::OVERHEAD_LEN..len,
+        }
+    }
+}
+
 /// Statistics maintained in orbflow frame processing
 #[derive(Default, Debug, Clone, Eq, Copy, PartialEq)]
 pub struct OFlowStats {
     /* Statistics maintained by this decoder */
     /// Number of bytes of input from source
-    inbytestotal: u64,
+    pub inbytestotal: u64,
     /// Number of input packets processed
-    inpackets: u64,
-    /// Number of input error packets
-    inerrpackets: u64,
+    pub inpackets: u64,
+    /// Number of input packets with a bad checksum, pointing at bit errors on the link
+    pub badchecksum: u64,
+    /// Number of input packets shorter than the frame overhead or the payload expected
+    pub tooshort: u64,
+    /// Number of input packets longer than the maximum or the payload expected
+    pub toolong: u64,
+}
+
+/// An encoded orbflow frame held as its three parts, for vectored writes
+///
+/// The stream number and checksum are held here, and the data is borrowed from the caller, so
+/// nothing is copied. Created by [`OFlow::encode_slices`].
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OFlowSlices<'a> {
+    stream_number: [u8; OFlow::STREAM_LEN],
+    data: &'a [u8],
+    checksum: [u8; OFlow::CHECKSUM_LEN],
+    position: ChecksumPosition,
+}
+
+impl OFlowSlices<'_> {
+    /// The stream number, data and checksum, in the order they are sent
+    pub fn parts(&self) -> [&[u8]; 3] {
+        match self.position {
+            ChecksumPosition::Trailing => [&self.stream_number, self.data, &self.checksum],
+            ChecksumPosition::Leading => [&self.stream_number, &self.checksum, self.data],
+        }
+    }
+
+    /// The parts ready for [`std::io::Write::write_vectored`]
+    pub fn io_slices(&self) -> [IoSlice<'_>; 3] {
+        self.parts().map(IoSlice::new)
+    }
+
+    /// Total length of the encoded frame
+    pub fn len(&self) -> usize {
+        OFlow::OVERHEAD_LEN + self.data.len()
+    }
+
+    /// An encoded frame is never empty, it always has a stream number and checksum
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// An orbflow frame being encoded a piece at a time
+///
+/// For producers that generate the data of a frame as they go, rather than having it all to
+/// hand. Each piece is added to the frame, and to the running checksum, as it is pushed, so
+/// the data needn't be gathered into a vector of its own first. Created by
+/// [`OFlowEncoder::begin`], with the checksum kind, position and length limit of the `OFlow`
+/// it was created from.
+///
+/// A [`ChecksumPosition::Leading`] checksum can only be put in place when the frame is
+/// finished, which moves the data along by a byte.
+///
+/// # Example
+/// ```
+/// use oflow::{OFlow, OFlowEncoder};
+/// let mut of = OFlow::new();
+/// let mut enc = OFlowEncoder::begin(&of, 42);
+/// enc.push(&[1, 2]).unwrap();
+/// enc.push(&[3]).unwrap();
+/// assert_eq!(of.encode_to_vec(42, vec![1, 2, 3]), enc.finish());
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OFlowEncoder {
+    frame: Vec<u8>,
+    sum: u8,
+    checksum: ChecksumKind,
+    position: ChecksumPosition,
+    max_len: usize,
+}
+
+impl OFlowEncoder {
+    /// Start a frame for the stream, using the settings of `oflow`
+    pub fn begin(oflow: &OFlow, stream_number: u8) -> Self {
+        Self {
+            frame: vec![stream_number],
+            sum: stream_number,
+            checksum: oflow.checksum,
+            position: oflow.position,
+            max_len: oflow.max_len,
+        }
+    }
+
+    /// Add more data to the frame
+    ///
+    /// # Errors
+    ///
+    /// `OFlowError::Overlong` if the data would go beyond the longest frame allowed, in which
+    /// case none of it is added.
+    ///
+    pub fn push(&mut self, ip: &[u8]) -> Result<(), OFlowError> {
+        if self.frame.len() - OFlow::STREAM_LEN + ip.len() > self.max_len {
+            return Err(OFlowError::Overlong);
+        }
+        self.sum = self.checksum.accumulate(self.sum, ip);
+        self.frame.extend_from_slice(ip);
+        Ok(())
+    }
+
+    /// Complete the frame with its checksum, ready for transmission or storage
+    ///
+    /// # Errors
+    ///
+    /// `OFlowError::ZeroLength` if no data was pushed.
+    ///
+    pub fn finish(mut self) -> Result<Vec<u8>, OFlowError> {
+        if self.frame.len() == OFlow::STREAM_LEN {
+            return Err(OFlowError::ZeroLength);
+        }
+        let sum = self.checksum.complete(self.sum);
+        match self.position {
+            ChecksumPosition::Trailing => self.frame.push(sum),
+            ChecksumPosition::Leading => self.frame.insert(OFlow::STREAM_LEN, sum),
+        }
+        Ok(self.frame)
+    }
 }
 
 /// The OFLOW encoder/decoder object
 ///
 /// This maintains statistics of packets encoded and decoded by the orbflow machine.
 ///
-#[derive(Default, Debug, Clone, Eq, Copy, PartialEq)]
+#[derive(Debug, Clone, Eq, Copy, PartialEq)]
 pub struct OFlow {
     /* Return statistics maintained by this decoder */
     stats: OFlowStats,
+    /* Type of checksum in use */
+    checksum: ChecksumKind,
+    /* Where the checksum sits in the frame */
+    position: ChecksumPosition,
+    /* Longest data content accepted by encode and decode */
+    max_len: usize,
+    /* Is the checksum of decoded frames checked? */
+    verify: bool,
+}
+
+impl Default for OFlow {
+    fn default() -> Self {
+        Self {
+            stats: OFlowStats::default(),
+            checksum: ChecksumKind::default(),
+            position: ChecksumPosition::default(),
+            max_len: OFlow::MAX_PACKET_LEN,
+            verify: true,
+        }
+    }
 }
 
 impl OFlow {
@@ -125,10 +438,11 @@ impl OFlow {
     pub const MAX_PACKET_LEN: usize = 8192;
     pub const STREAM_LEN: usize = 1;
     pub const CHECKSUM_LEN: usize = 1;
+    /// Length of the prefix for length prefixed framing
+    pub const LENGTH_PREFIX_LEN: usize = 2;
 
     // Encoded packet has a flow number at the start and a checksum at the end
     const OVERHEAD_LEN: usize = OFlow::STREAM_LEN + OFlow::CHECKSUM_LEN;
-    const MAX_ENC_PACKET_LEN: usize = OFlow::OVERHEAD_LEN + OFlow::MAX_PACKET_LEN;
 
     /// Create new instance of Oflow
     ///
@@ -140,6 +454,125 @@ impl OFlow {
         }
     }
 
+    /// Create new instance of Oflow using the specified checksum
+    ///
+    /// New instance will have zero'ed statistics, and will use `kind` for both encode and decode.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::{ChecksumKind, OFlow};
+    /// let mut of = OFlow::with_checksum(ChecksumKind::Xor);
+    /// let encoded = of.encode_to_vec(42, vec![1u8, 2, 3]).unwrap();
+    /// assert_eq!(&[1u8, 2, 3], of.decode(encoded).unwrap().content());
+    /// ```
+    ///
+    pub fn with_checksum(kind: ChecksumKind) -> OFlow {
+        Self {
+            checksum: kind,
+            ..Default::default()
+        }
+    }
+
+    /// Create new instance of Oflow with the checksum at the specified position
+    ///
+    /// New instance will have zero'ed statistics, and will expect and create frames with the
+    /// checksum at `position`. The checksum value itself is the same wherever it sits. The
+    /// `oflow_frame!` macro has no instance, so always creates trailing checksums.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::{ChecksumPosition, OFlow};
+    /// let mut of = OFlow::with_checksum_position(ChecksumPosition::Leading);
+    /// let encoded = of.encode_to_vec(42, vec![1u8, 2, 3]).unwrap();
+    /// assert_eq!(vec![42u8, 208, 1, 2, 3], encoded);
+    /// assert_eq!(&[1u8, 2, 3], of.decode(encoded).unwrap().content());
+    /// ```
+    ///
+    pub fn with_checksum_position(position: ChecksumPosition) -> OFlow {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    /// Create new instance of Oflow accepting frames of up to `max_len` bytes of data
+    ///
+    /// By default frames may carry up to [`OFlow::MAX_PACKET_LEN`] bytes. A constrained link can
+    /// enforce smaller frames, and a high bandwidth one allow larger, with both encode and
+    /// decode rejecting anything longer as `OFlowError::Overlong`. The `oflow_frame!` macro has
+    /// no instance, so always uses the default.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::{OFlow, OFlowError};
+    /// let mut of = OFlow::with_max_len(2);
+    /// assert_eq!(Err(OFlowError::Overlong), of.encode_to_vec(42, vec![1u8, 2, 3]));
+    /// ```
+    ///
+    pub fn with_max_len(max_len: usize) -> OFlow {
+        Self {
+            max_len,
+            ..Default::default()
+        }
+    }
+
+    /// Set if the checksum of decoded frames is checked
+    ///
+    /// On by default. Over a transport that already guarantees integrity, checking is wasted
+    /// work, and producers there may not fill in the checksum at all. When off, decode still
+    /// takes the checksum byte (at the position this instance uses) out of the frame, but
+    /// doesn't look at its value. Encode is unaffected.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let mut of = OFlow::new();
+    /// of.set_verify_checksum(false);
+    /// assert_eq!(&[1u8, 2, 3], of.decode(vec![42, 1, 2, 3, 0]).unwrap().content());
+    /// ```
+    ///
+    pub fn set_verify_checksum(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Return the longest data content accepted by this instance
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Return the bytes orbflow adds to a payload of `payload_len` bytes
+    ///
+    /// This is the stream number and checksum, so it's the same whatever the length.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// assert_eq!(2, OFlow::overhead_for(100));
+    /// ```
+    ///
+    pub const fn overhead_for(_payload_len: usize) -> usize {
+        OFlow::OVERHEAD_LEN
+    }
+
+    /// Return the most bytes orbflow and COBS together add to a payload of `payload_len` bytes
+    ///
+    /// For sizing a link. COBS adds a run length byte for each run of up to 254 bytes (there is
+    /// always at least one), plus the sentinel at the end. The worst case is a frame with no
+    /// sentinel in it, where every run is full length.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// assert_eq!(4, OFlow::encoded_overhead_with_cobs(252));
+    /// assert_eq!(5, OFlow::encoded_overhead_with_cobs(253));
+    /// ```
+    ///
+    pub const fn encoded_overhead_with_cobs(payload_len: usize) -> usize {
+        const COBS_RUN_LEN: usize = 254;
+        let frame_len = payload_len + OFlow::overhead_for(payload_len);
+        OFlow::overhead_for(payload_len) + frame_len.div_ceil(COBS_RUN_LEN) + 1
+    }
+
     /// Return input statistics
     ///
     /// Returns the input statistics for the decoder. Note there are no output
@@ -173,39 +606,111 @@ impl OFlow {
     /// let opvec_candidate = oflow.decode(ipvec).unwrap();
     ///
     pub fn decode(&mut self, ip: Vec<u8>) -> Result<OFlowFrame, OFlowError> {
+        self.decode_checked(ip, None)
+    }
+
+    /// Decode the inner data frame, checking it carries the expected amount of data
+    ///
+    /// As [`OFlow::decode`], but for streams that carry fixed size records. A frame that
+    /// passes its checksum, but doesn't have a payload of `expected_payload_len`, is rejected.
+    ///
+    /// #Errors
+    /// As [`OFlow::decode`], plus `OFlowError::WrongLength` if the payload isn't the expected size.
+    ///
+    /// #Example
+    /// ```
+    /// use oflow::{OFlow, OFlowError};
+    /// let ipvec = vec![27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    /// let mut oflow = OFlow::new();
+    /// assert_eq!(
+    ///     Err(OFlowError::WrongLength { got: 3, expected: 4 }),
+    ///     oflow.decode_expect(ipvec, 4)
+    /// );
+    /// ```
+    ///
+    pub fn decode_expect(
+        &mut self,
+        ip: Vec<u8>,
+        expected_payload_len: usize,
+    ) -> Result<OFlowFrame, OFlowError> {
+        self.decode_checked(ip, Some(expected_payload_len))
+    }
+
+    /// Decode the data frame within the passed slice, without taking a copy
+    ///
+    /// As [`OFlow::decode`], but the frame is borrowed rather than owned, so no allocation is
+    /// needed. Returns the stream number and the data within the frame.
+    ///
+    /// #Errors
+    /// As [`OFlow::decode`].
+    ///
+    /// #Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let ip = [27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    /// let mut oflow = OFlow::new();
+    /// assert_eq!(Ok((27, &ip[1..4])), oflow.decode_slice(&ip));
+    /// ```
+    ///
+    pub fn decode_slice<'a>(&mut self, ip: &'a [u8]) -> Result<(u8, &'a [u8]), OFlowError> {
+        self.validate(ip, None)?;
+        Ok((ip[0], &ip[self.position.data_range(ip.len())]))
+    }
+
+    // Decode the frame, optionally checking the payload length
+    fn decode_checked(
+        &mut self,
+        ip: Vec<u8>,
+        expected: Option<usize>,
+    ) -> Result<OFlowFrame, OFlowError> {
+        self.validate(&ip, expected)?;
+        Ok(OFlowFrame {
+            stream_number: ip[0],
+            checksum: self.checksum,
+            position: self.position,
+            inner: ip,
+        })
+    }
+
+    // Check the frame is good, optionally checking the payload length, and keep the accounting
+    fn validate(&mut self, ip: &[u8], expected: Option<usize>) -> Result<(), OFlowError> {
         if ip.len() < 1 + OFlow::OVERHEAD_LEN {
-            self.stats.inerrpackets += 1;
+            self.stats.tooshort += 1;
             Err(OFlowError::ShortData)
-        } else if ip.len() > OFlow::MAX_ENC_PACKET_LEN {
-            self.stats.inerrpackets += 1;
+        } else if ip.len() > OFlow::OVERHEAD_LEN + self.max_len {
+            self.stats.toolong += 1;
             Err(OFlowError::Overlong)
-        } else {
-            /* Create checksum */
-            let mut sum: usize = 0;
-            for c in ip[0..ip.len()].iter() {
-                sum += *c as usize;
-            }
-
-            if sum & 0xff != 0 {
-                /* Checksum didn't match (i.e. sum to zero), not worth going further */
-                self.stats.inerrpackets += 1;
-                Err(OFlowError::BadChecksum)
+        } else if let Some((expected, got)) = self
+            .verify
+            .then(|| self.checksum.mismatch(self.position, ip))
+            .flatten()
+        {
+            /* Checksum didn't match, not worth going further */
+            self.stats.badchecksum += 1;
+            Err(OFlowError::BadChecksum { expected, got })
+        } else if let Some(expected) = expected.filter(|e| *e != ip.len() - OFlow::OVERHEAD_LEN) {
+            /* Valid frame, but not carrying what was expected */
+            let got = ip.len() - OFlow::OVERHEAD_LEN;
+            if got < expected {
+                self.stats.tooshort += 1;
             } else {
-                /* All good, updating accounting and return the inner content */
-                self.stats.inpackets += 1;
-                self.stats.inbytestotal += (ip.len() - OFlow::OVERHEAD_LEN) as u64;
-                Ok(OFlowFrame {
-                    stream_number: ip[0],
-                    inner: ip,
-                })
+                self.stats.toolong += 1;
             }
+            Err(OFlowError::WrongLength { got, expected })
+        } else {
+            /* All good, updating accounting */
+            self.stats.inpackets += 1;
+            self.stats.inbytestotal += (ip.len() - OFlow::OVERHEAD_LEN) as u64;
+            Ok(())
         }
     }
 
     /// Return the checksum for an orbflow data frame
     ///
     /// This is normally only used as part of the frame construction macro. It returns no
-    /// errors and the checksum is always valid.
+    /// errors and the checksum is always valid. This is always the default twos complement
+    /// checksum, use [`ChecksumKind::checksum`] for other kinds. The value doesn't depend on
+    /// the [`ChecksumPosition`], only where it is placed does.
     ///
     /// # Example
     /// ```
@@ -215,10 +720,7 @@ impl OFlow {
     /// ```
     ///
     pub fn get_checksum(stream_number: u8, ip: &[u8]) -> u8 {
-        //let mut sum: u8 = stream_number;
-        let mut sum: u8 = ip.iter().sum();
-        sum += stream_number;
-        (256usize - (sum as usize)) as u8
+        ChecksumKind::TwosComplement.checksum(stream_number, ip)
     }
 
     /// Create an encoded orbflow vector ready for transmission or storage
@@ -242,19 +744,161 @@ impl OFlow {
     pub fn encode_to_vec(&mut self, stream_number: u8, ip: Vec<u8>) -> Result<Vec<u8>, OFlowError> {
         if ip.is_empty() {
             Err(OFlowError::ZeroLength)
-        } else if ip.len() > OFlow::MAX_PACKET_LEN {
+        } else if ip.len() > self.max_len {
             Err(OFlowError::Overlong)
         } else {
-            let op_assy = crate::oflow_frame!(stream_number, &ip);
-            let mut constructed_frame = vec![0u8; 0];
-            for o in op_assy {
-                for i in o {
-                    constructed_frame.push(i);
-                }
+            let sum = self.checksum.checksum(stream_number, &ip);
+            let mut constructed_frame = Vec::with_capacity(ip.len() + OFlow::OVERHEAD_LEN);
+            constructed_frame.push(stream_number);
+            if self.position == ChecksumPosition::Leading {
+                constructed_frame.push(sum);
+            }
+            constructed_frame.extend_from_slice(&ip);
+            if self.position == ChecksumPosition::Trailing {
+                constructed_frame.push(sum);
             }
             Ok(constructed_frame)
         }
     }
+
+    /// Create an encoded orbflow frame as three slices, without copying the data
+    ///
+    /// For vectored writes the stream number, data and checksum can be written as they are,
+    /// rather than being flattened into a new vector as [`OFlow::encode_to_vec`] does. This is
+    /// the borrowed equivalent of the `oflow_frame!` macro.
+    ///
+    /// # Errors
+    ///
+    /// As [`OFlow::encode_to_vec`].
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// use std::io::Write;
+    /// let data = [1u8, 2, 3];
+    /// let of = OFlow::new();
+    /// let frame = of.encode_slices(42, &data).unwrap();
+    /// let mut op = Vec::new();
+    /// op.write_vectored(&frame.io_slices()).unwrap();
+    /// assert_eq!(frame.len(), op.len());
+    /// ```
+    ///
+    pub fn encode_slices<'a>(
+        &self,
+        stream_number: u8,
+        ip: &'a [u8],
+    ) -> Result<OFlowSlices<'a>, OFlowError> {
+        if ip.is_empty() {
+            Err(OFlowError::ZeroLength)
+        } else if ip.len() > self.max_len {
+            Err(OFlowError::Overlong)
+        } else {
+            Ok(OFlowSlices {
+                stream_number: [stream_number],
+                data: ip,
+                checksum: [self.checksum.checksum(stream_number, ip)],
+                position: self.position,
+            })
+        }
+    }
+
+    /// Create an encoded orbflow keepalive frame
+    ///
+    /// A keepalive is a frame for the stream that carries no data, just the stream number and
+    /// checksum. It is the only legitimate way to construct an empty frame. It serves only to
+    /// show the link is alive, and a receiver will drop it as `OFlowError::ShortData`.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let mut of = OFlow::new();
+    /// assert_eq!(vec![42u8, 214], of.encode_keepalive(42));
+    /// ```
+    ///
+    pub fn encode_keepalive(&mut self, stream_number: u8) -> Vec<u8> {
+        vec![stream_number, self.checksum.checksum(stream_number, &[])]
+    }
+
+    /// Create an orbflow frame with a length prefix, ready for transmission over a reliable link
+    ///
+    /// On lossless links (e.g. TCP) there's no need for COBS to find the frame boundaries. Instead
+    /// the frame is preceded by its length (stream number, data and checksum) as a little-endian
+    /// `u16`. The frame itself is the same as [`OFlow::encode_to_vec`].
+    ///
+    /// # Errors
+    ///
+    /// As [`OFlow::encode_to_vec`], plus `OFlowError::Overlong` if the frame is too long for
+    /// its length to fit in the prefix.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let mut of = OFlow::new();
+    /// let f = of.encode_length_prefixed(42, vec![1u8, 2, 3]).unwrap();
+    /// assert_eq!(&[5u8, 0, 42, 1, 2, 3], &f[..6]);
+    /// ```
+    ///
+    pub fn encode_length_prefixed(
+        &mut self,
+        stream_number: u8,
+        ip: Vec<u8>,
+    ) -> Result<Vec<u8>, OFlowError> {
+        let frame = self.encode_to_vec(stream_number, ip)?;
+        if frame.len() > u16::MAX as usize {
+            return Err(OFlowError::Overlong);
+        }
+        let mut constructed_frame = Vec::with_capacity(OFlow::LENGTH_PREFIX_LEN + frame.len());
+        constructed_frame.extend_from_slice(&(frame.len() as u16).to_le_bytes());
+        constructed_frame.extend_from_slice(&frame);
+        Ok(constructed_frame)
+    }
+
+    /// Return the total length of the length prefixed frame at the start of the input
+    ///
+    /// This is the length of the prefix plus the length it declares, so is the number of bytes
+    /// to be consumed for the frame. `None` if there isn't enough input to hold the prefix.
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// assert_eq!(Some(7), OFlow::length_prefixed_len(&[5u8, 0, 42]));
+    /// assert_eq!(None, OFlow::length_prefixed_len(&[5u8]));
+    /// ```
+    ///
+    pub fn length_prefixed_len(ip: &[u8]) -> Option<usize> {
+        let prefix = ip.get(..OFlow::LENGTH_PREFIX_LEN)?;
+        Some(OFlow::LENGTH_PREFIX_LEN + u16::from_le_bytes([prefix[0], prefix[1]]) as usize)
+    }
+
+    /// Decode a length prefixed frame from the start of the input
+    ///
+    /// Returns the inner data frame and the number of bytes of input it occupied, so the next
+    /// frame starts immediately after. The frame is validated as [`OFlow::decode`].
+    ///
+    /// # Errors
+    ///
+    /// `OFlowError::ShortData` if the input is shorter than the length it declares, otherwise
+    /// as [`OFlow::decode`].
+    ///
+    /// # Example
+    /// ```
+    /// use oflow::OFlow;
+    /// let mut of = OFlow::new();
+    /// let f = of.encode_length_prefixed(42, vec![1u8, 2, 3]).unwrap();
+    /// let (frame, used) = of.decode_length_prefixed(&f).unwrap();
+    /// assert_eq!(&[1u8, 2, 3], frame.content());
+    /// assert_eq!(f.len(), used);
+    /// ```
+    ///
+    pub fn decode_length_prefixed(&mut self, ip: &[u8]) -> Result<(OFlowFrame, usize), OFlowError> {
+        match OFlow::length_prefixed_len(ip) {
+            Some(len) if len <= ip.len() => {
+                let frame = self.decode(ip[OFlow::LENGTH_PREFIX_LEN..len].to_vec())?;
+                Ok((frame, len))
+            }
+            _ => Err(OFlowError::ShortData),
+        }
+    }
 }
 
 /// Zero-copy creation of orbflow packet as sequence of slices
@@ -265,26 +909,28 @@ impl OFlow {
 ///
 /// # Errors
 ///
-/// Panics for no source data, or the source data being too long, otherwise
-/// returns a valid sequence of vectors.
+/// Returns `OFlowError::ZeroLength` for no source data, or `OFlowError::Overlong` for the
+/// source data being too long, the same as [`OFlow::encode_to_vec`]. It no longer panics.
+/// Otherwise returns a valid sequence of vectors. Use [`OFlow::encode_keepalive`] for an
+/// empty frame.
 ///
 /// # Example
 /// ```
 /// use oflow::OFlow;
 /// let data = vec![1u8,2,3];
-/// let oflow_packet = oflow::oflow_frame!(42,&data);
+/// let oflow_packet = oflow::oflow_frame!(42,&data).unwrap();
 /// ```
 ///
 #[macro_export]
 macro_rules! oflow_frame {
     ($l:expr,&$m:expr) => {{
         if $m.len() == 0 {
-            panic!("Zero length orbflow packet");
-        }
-        if $m.len() > OFlow::MAX_PACKET_LEN {
-            panic!("Orbflow packet to long");
+            Err($crate::OFlowError::ZeroLength)
+        } else if $m.len() > $crate::OFlow::MAX_PACKET_LEN {
+            Err($crate::OFlowError::Overlong)
+        } else {
+            let sum = $crate::OFlow::get_checksum($l, &$m);
+            Ok(vec![vec![$l], $m, vec![sum]])
         }
-        let sum = OFlow::get_checksum($l, &$m);
-        vec![vec![$l], $m, vec![sum]]
     }};
 }
@@ -20,7 +20,17 @@ fn decode_bad_packet() {
     let ipvec = vec![27u8, 1, 2, 3, 27 + 1 + 2 + 3 + 1];
     let mut oflow = OFlow::new();
     let opvec_candidate = oflow.decode(ipvec);
-    assert_eq!(opvec_candidate, Err(OFlowError::BadChecksum));
+    assert_eq!(
+        opvec_candidate,
+        Err(OFlowError::BadChecksum {
+            expected: (256usize - (27 + 1 + 2 + 3)) as u8,
+            got: 27 + 1 + 2 + 3 + 1
+        })
+    );
+    assert_eq!(
+        "Bad checksum 0x22 when 0xdf expected",
+        opvec_candidate.unwrap_err().to_string()
+    );
 }
 
 #[test]
@@ -64,5 +74,391 @@ fn create_macro_frame() {
         vec![(256usize - (27 + 1 + 2 + 3)) as u8],
     ];
     let d = crate::oflow_frame!(27u8, &v);
-    assert_eq!(opvec, d);
+    assert_eq!(Ok(opvec), d);
+}
+
+#[test]
+fn create_macro_empty_frame() {
+    let v: Vec<u8> = vec![];
+    assert_eq!(Err(OFlowError::ZeroLength), crate::oflow_frame!(27u8, &v));
+    let v = vec![0u8; OFlow::MAX_PACKET_LEN + 1];
+    assert_eq!(Err(OFlowError::Overlong), crate::oflow_frame!(27u8, &v));
+}
+
+#[test]
+fn create_empty_frame() {
+    let mut oflow = OFlow::new();
+    assert_eq!(Err(OFlowError::ZeroLength), oflow.encode_to_vec(27, vec![]));
+
+    /* Empty frames are only made on purpose, and sum to zero like any other */
+    let k = oflow.encode_keepalive(27);
+    assert_eq!(vec![27u8, (256usize - 27) as u8], k);
+    let mut oflow = OFlow::with_checksum(ChecksumKind::Xor);
+    assert_eq!(vec![27u8, 27], oflow.encode_keepalive(27));
+}
+
+#[test]
+fn checksum_wraps() {
+    let v = vec![200u8, 100, 250];
+    let sum = OFlow::get_checksum(27, &v);
+    let mut oflow = OFlow::new();
+    let decoded = oflow.decode(vec![27u8, 200, 100, 250, sum]).unwrap();
+    assert_eq!(&v[..], decoded.content());
+}
+
+#[test]
+fn xor_round_trip() {
+    let mut oflow = OFlow::with_checksum(ChecksumKind::Xor);
+    let encoded = oflow.encode_to_vec(27, vec![1u8, 2, 3, 0xf0]).unwrap();
+    assert_eq!(vec![27u8, 1, 2, 3, 0xf0, 27 ^ 1 ^ 2 ^ 3 ^ 0xf0], encoded);
+    let decoded = oflow.decode(encoded).unwrap();
+    assert_eq!(27, decoded.get_stream_no());
+    assert_eq!(&[1u8, 2, 3, 0xf0], decoded.content());
+}
+
+#[test]
+fn xor_bad_packet() {
+    let mut oflow = OFlow::with_checksum(ChecksumKind::Xor);
+    /* This is a valid twos complement frame, but not a valid XOR one */
+    let ipvec = vec![27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    assert_eq!(
+        oflow.decode(ipvec),
+        Err(OFlowError::BadChecksum {
+            expected: 27 ^ 1 ^ 2 ^ 3,
+            got: (256usize - (27 + 1 + 2 + 3)) as u8
+        })
+    );
+
+    let mut encoded = oflow.encode_to_vec(27, vec![1u8, 2, 3]).unwrap();
+    encoded[2] ^= 0x10;
+    assert_eq!(
+        oflow.decode(encoded),
+        Err(OFlowError::BadChecksum {
+            expected: 27 ^ 1 ^ 0x12 ^ 3,
+            got: 27 ^ 1 ^ 2 ^ 3
+        })
+    );
+}
+
+#[test]
+fn decode_expected_length() {
+    let ipvec = vec![27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    let mut oflow = OFlow::new();
+    let opvec_candidate = oflow.decode_expect(ipvec, 3).unwrap();
+    assert_eq!(&[1u8, 2, 3], opvec_candidate.content());
+    assert_eq!(1, oflow.stats().inpackets);
+}
+
+#[test]
+fn decode_wrong_length() {
+    let ipvec = vec![27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    let mut oflow = OFlow::new();
+    assert_eq!(
+        oflow.decode_expect(ipvec.clone(), 2),
+        Err(OFlowError::WrongLength {
+            got: 3,
+            expected: 2
+        })
+    );
+    assert_eq!(
+        oflow.decode_expect(ipvec, 4),
+        Err(OFlowError::WrongLength {
+            got: 3,
+            expected: 4
+        })
+    );
+    assert_eq!(0, oflow.stats().inpackets);
+    assert_eq!(1, oflow.stats().tooshort);
+    assert_eq!(1, oflow.stats().toolong);
+}
+
+#[test]
+fn length_prefixed_round_trip() {
+    let mut oflow = OFlow::new();
+    let mut ip = oflow.encode_length_prefixed(27, vec![1u8, 2, 3]).unwrap();
+    ip.extend(oflow.encode_length_prefixed(28, vec![0u8; 300]).unwrap());
+    assert_eq!(&[5u8, 0], &ip[..2]);
+    assert_eq!(&[46u8, 1], &ip[7..9]);
+
+    let (f, used) = oflow.decode_length_prefixed(&ip).unwrap();
+    assert_eq!(27, f.get_stream_no());
+    assert_eq!(&[1u8, 2, 3], f.content());
+    let (f, used2) = oflow.decode_length_prefixed(&ip[used..]).unwrap();
+    assert_eq!(28, f.get_stream_no());
+    assert_eq!(300, f.len());
+    assert_eq!(ip.len(), used + used2);
+    assert_eq!(2, oflow.stats().inpackets);
+}
+
+#[test]
+fn length_prefixed_short() {
+    let mut oflow = OFlow::new();
+    let ip = oflow.encode_length_prefixed(27, vec![1u8, 2, 3]).unwrap();
+    for n in 0..ip.len() {
+        assert_eq!(
+            Err(OFlowError::ShortData),
+            oflow.decode_length_prefixed(&ip[..n])
+        );
+    }
+
+    /* The frame must still be sound once it's all there */
+    let mut bad = ip.clone();
+    bad[3] ^= 0x10;
+    assert!(matches!(
+        oflow.decode_length_prefixed(&bad),
+        Err(OFlowError::BadChecksum { .. })
+    ));
+}
+
+#[test]
+fn frame_into_content() {
+    let ipvec = vec![27u8, 1, 2, 3, (256usize - (27 + 1 + 2 + 3)) as u8];
+    let mut oflow = OFlow::new();
+    let frame = oflow.decode(ipvec).unwrap();
+    let content = frame.content().to_vec();
+    assert_eq!(content, frame.into_content());
+}
+
+#[test]
+fn custom_max_len_encode() {
+    let mut oflow = OFlow::with_max_len(16);
+    assert_eq!(16, oflow.max_len());
+    assert!(oflow.encode_to_vec(1, vec![0x55; 16]).is_ok());
+    assert_eq!(
+        Err(OFlowError::Overlong),
+        oflow.encode_to_vec(1, vec![0x55; 17])
+    );
+
+    /* ...and it can go beyond the default too */
+    let mut oflow = OFlow::with_max_len(OFlow::MAX_PACKET_LEN * 2);
+    assert!(oflow
+        .encode_to_vec(1, vec![0x55; OFlow::MAX_PACKET_LEN + 1])
+        .is_ok());
+}
+
+#[test]
+fn custom_max_len_decode() {
+    let mut oflow = OFlow::with_max_len(16);
+    let ok = OFlow::new().encode_to_vec(1, vec![0x55; 16]).unwrap();
+    let long = OFlow::new().encode_to_vec(1, vec![0x55; 17]).unwrap();
+    assert_eq!(&[0x55; 16], oflow.decode(ok).unwrap().content());
+    assert_eq!(Err(OFlowError::Overlong), oflow.decode(long.clone()));
+    assert_eq!(1, oflow.stats().toolong);
+    assert!(OFlow::new().decode(long).is_ok());
+}
+
+#[test]
+fn encode_slices_matches_vec() {
+    let data = [0x11u8, 0x22, 0x33, 0x44];
+    for kind in [ChecksumKind::TwosComplement, ChecksumKind::Xor] {
+        let mut oflow = OFlow::with_checksum(kind);
+        let frame = oflow.encode_slices(7, &data).unwrap();
+        assert_eq!(
+            oflow.encode_to_vec(7, data.to_vec()).unwrap(),
+            frame.parts().concat()
+        );
+        assert_eq!(data.len() + 2, frame.len());
+    }
+    assert_eq!(
+        Err(OFlowError::ZeroLength),
+        OFlow::new().encode_slices(7, &[])
+    );
+    assert_eq!(
+        Err(OFlowError::Overlong),
+        OFlow::with_max_len(3).encode_slices(7, &data)
+    );
+}
+
+#[test]
+fn leading_checksum_round_trip() {
+    let data = vec![0x11u8, 0x22, 0x33];
+    let mut oflow = OFlow::with_checksum_position(ChecksumPosition::Leading);
+    let encoded = oflow.encode_to_vec(7, data.clone()).unwrap();
+    let sum = OFlow::get_checksum(7, &data);
+    assert_eq!(vec![7, sum, 0x11, 0x22, 0x33], encoded);
+    assert_eq!(
+        encoded,
+        oflow.encode_slices(7, &data).unwrap().parts().concat()
+    );
+
+    let frame = oflow.decode(encoded.clone()).unwrap();
+    assert_eq!(7, frame.get_stream_no());
+    assert_eq!(&data[..], frame.content());
+    assert_eq!(0x33, frame[2]);
+    assert_eq!(data, frame.into_content());
+
+    /* The frame still sums to zero, so a trailing decoder accepts it, but with the wrong data */
+    let frame = OFlow::new().decode(encoded.clone()).unwrap();
+    assert_eq!(&[sum, 0x11, 0x22], frame.content());
+
+    /* ...and a corrupt leading checksum reports both values */
+    let mut bad = encoded;
+    bad[1] ^= 0xff;
+    assert_eq!(
+        Err(OFlowError::BadChecksum {
+            expected: sum,
+            got: sum ^ 0xff
+        }),
+        oflow.decode(bad)
+    );
+}
+
+#[test]
+fn overhead() {
+    for len in [0, 1, 253, OFlow::MAX_PACKET_LEN] {
+        assert_eq!(2, OFlow::overhead_for(len));
+    }
+
+    /* Frame is payload + 2, then a run length per 254 bytes of frame and a sentinel */
+    for (len, expected) in [
+        (1, 2 + 1 + 1),
+        (252, 2 + 1 + 1),
+        (253, 2 + 2 + 1),
+        (506, 2 + 2 + 1),
+        (507, 2 + 3 + 1),
+        (OFlow::MAX_PACKET_LEN, 2 + 33 + 1),
+    ] {
+        assert_eq!(expected, OFlow::encoded_overhead_with_cobs(len), "{len}");
+    }
+}
+
+#[test]
+fn incremental_encode() {
+    let data: Vec<u8> = (0..1000).map(|n| (n * 7) as u8).collect();
+    for mut of in [
+        OFlow::new(),
+        OFlow::with_checksum(ChecksumKind::Xor),
+        OFlow::with_checksum_position(ChecksumPosition::Leading),
+    ] {
+        let mut enc = OFlowEncoder::begin(&of, 9);
+        for piece in data.chunks(33) {
+            enc.push(piece).unwrap();
+        }
+        let frame = enc.finish().unwrap();
+        assert_eq!(of.encode_to_vec(9, data.clone()).unwrap(), frame);
+        assert_eq!(&data[..], of.decode(frame).unwrap().content());
+    }
+
+    assert_eq!(
+        Err(OFlowError::ZeroLength),
+        OFlowEncoder::begin(&OFlow::new(), 9).finish()
+    );
+
+    /* Data that would make the frame too long is refused, but what was there is kept */
+    let of = OFlow::with_max_len(4);
+    let mut enc = OFlowEncoder::begin(&of, 9);
+    enc.push(&[1, 2, 3]).unwrap();
+    assert_eq!(Err(OFlowError::Overlong), enc.push(&[4, 5]));
+    enc.push(&[4]).unwrap();
+    assert_eq!(
+        vec![9, 1, 2, 3, 4, 0u8.wrapping_sub(19)],
+        enc.finish().unwrap()
+    );
+}
+
+#[test]
+fn decode_slice() {
+    let mut oflow = OFlow::with_checksum_position(ChecksumPosition::Leading);
+    let frame = oflow.encode_to_vec(9, vec![1, 2, 3]).unwrap();
+    assert_eq!(Ok((9, &[1u8, 2, 3][..])), oflow.decode_slice(&frame));
+
+    let mut bad = frame.clone();
+    bad[2] ^= 0xff;
+    assert!(matches!(
+        oflow.decode_slice(&bad),
+        Err(OFlowError::BadChecksum { .. })
+    ));
+    assert_eq!(Err(OFlowError::ShortData), oflow.decode_slice(&frame[..2]));
+    assert_eq!(1, oflow.stats().inpackets);
+    assert_eq!(1, oflow.stats().badchecksum);
+    assert_eq!(1, oflow.stats().tooshort);
+}
+
+#[test]
+fn error_counters() {
+    let mut oflow = OFlow::with_max_len(4);
+    let good = oflow.encode_to_vec(1, vec![1, 2, 3]).unwrap();
+
+    let mut bad = good.clone();
+    bad[1] ^= 0x01;
+    assert!(oflow.decode(bad).is_err());
+    assert_eq!(
+        (1, 0, 0),
+        (
+            oflow.stats().badchecksum,
+            oflow.stats().tooshort,
+            oflow.stats().toolong
+        )
+    );
+
+    assert_eq!(Err(OFlowError::ShortData), oflow.decode(vec![1]));
+    assert_eq!(
+        (1, 1, 0),
+        (
+            oflow.stats().badchecksum,
+            oflow.stats().tooshort,
+            oflow.stats().toolong
+        )
+    );
+
+    let long = OFlow::new().encode_to_vec(1, vec![0; 5]).unwrap();
+    assert_eq!(Err(OFlowError::Overlong), oflow.decode(long));
+    assert_eq!(
+        (1, 1, 1),
+        (
+            oflow.stats().badchecksum,
+            oflow.stats().tooshort,
+            oflow.stats().toolong
+        )
+    );
+    assert_eq!(0, oflow.stats().inpackets);
+}
+
+#[test]
+fn unverified_checksum() {
+    let mut oflow = OFlow::new();
+    let mut frame = oflow.encode_to_vec(3, vec![1, 2, 3]).unwrap();
+    *frame.last_mut().unwrap() ^= 0xff;
+    assert!(matches!(
+        oflow.decode(frame.clone()),
+        Err(OFlowError::BadChecksum { .. })
+    ));
+
+    /* The bad checksum is still taken off, and not counted against the frame */
+    oflow.set_verify_checksum(false);
+    let decoded = oflow.decode(frame).unwrap();
+    assert_eq!(3, decoded.get_stream_no());
+    assert_eq!(&[1u8, 2, 3], decoded.content());
+    assert_eq!(1, oflow.stats().inpackets);
+    assert_eq!(1, oflow.stats().badchecksum);
+}
+
+#[test]
+fn edited_content() {
+    let mut oflow = OFlow::new();
+    let frame = OFlowFrame::with_content(7, &[1, 2, 3]);
+    assert_eq!(
+        oflow.encode_to_vec(7, vec![1, 2, 3]).unwrap(),
+        frame.oflow_frame()
+    );
+    let decoded = oflow.decode(frame.oflow_frame().to_vec()).unwrap();
+    assert_eq!(frame, decoded);
+
+    /* Edits keep the checksum kind and position the frame was decoded with */
+    for (kind, position) in [
+        (ChecksumKind::TwosComplement, ChecksumPosition::Trailing),
+        (ChecksumKind::Xor, ChecksumPosition::Trailing),
+        (ChecksumKind::Xor, ChecksumPosition::Leading),
+    ] {
+        let mut oflow = OFlow::with_checksum(kind);
+        oflow.position = position;
+        let encoded = oflow.encode_to_vec(9, vec![0x55; 10]).unwrap();
+        let mut frame = oflow.decode(encoded).unwrap();
+        frame.set_content(&[0xaa, 0, 0xff]);
+        assert_eq!(&[0xaau8, 0, 0xff], frame.content());
+        let decoded = oflow.decode(frame.oflow_frame().to_vec()).unwrap();
+        assert_eq!(9, decoded.get_stream_no());
+        assert_eq!(&[0xaau8, 0, 0xff], decoded.content());
+    }
+    assert_eq!(0, oflow.stats().badchecksum);
 }